@@ -0,0 +1,195 @@
+use axum::{
+    extract::{Request, State},
+    http::header::AUTHORIZATION,
+    middleware::Next,
+    response::Response,
+};
+use ethers::core::rand::{thread_rng, RngCore};
+use ethers::utils::keccak256;
+use std::collections::HashSet;
+use crate::error::OpenBankError;
+use crate::AppState;
+
+const USER_KEY_PREFIX: &str = "obk_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Operator, // Runs the onramp: deposits, withdrawals, everything readable
+    User,     // End user, limited to their own data
+    Auditor,  // Read-only access to everything
+}
+
+// Who is calling, attached to every authenticated request
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub role: Role,
+    pub user_id: Option<String>, // Set for Role::User
+}
+
+impl Principal {
+    pub fn require_operator(&self) -> Result<(), OpenBankError> {
+        if self.role == Role::Operator {
+            Ok(())
+        } else {
+            Err(OpenBankError::Forbidden)
+        }
+    }
+
+    // Operators and auditors, for data that spans all users
+    pub fn require_staff(&self) -> Result<(), OpenBankError> {
+        if self.role == Role::User {
+            Err(OpenBankError::Forbidden)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Users may only read and change their own data
+    pub fn require_user(&self, user_id: &str) -> Result<(), OpenBankError> {
+        if self.role == Role::Operator || self.user_id.as_deref() == Some(user_id) {
+            Ok(())
+        } else {
+            Err(OpenBankError::Forbidden)
+        }
+    }
+
+    // Like require_user, but auditors may read anyone's data too
+    pub fn require_read(&self, user_id: &str) -> Result<(), OpenBankError> {
+        if self.role == Role::Auditor {
+            return Ok(());
+        }
+        self.require_user(user_id)
+    }
+}
+
+// Static operator and auditor keys, read from OPERATOR_API_KEYS and
+// AUDITOR_API_KEYS (comma separated). Only their hashes are kept in memory.
+#[derive(Debug, Default)]
+pub struct AuthConfig {
+    operator_keys: HashSet<String>,
+    auditor_keys: HashSet<String>,
+}
+
+impl AuthConfig {
+    pub fn from_env() -> Self {
+        let config = Self {
+            operator_keys: keys_from_env("OPERATOR_API_KEYS"),
+            auditor_keys: keys_from_env("AUDITOR_API_KEYS"),
+        };
+        if config.operator_keys.is_empty() {
+            println!("Warning: OPERATOR_API_KEYS is not set, operator routes are unreachable");
+        }
+        config
+    }
+}
+
+fn keys_from_env(name: &str) -> HashSet<String> {
+    std::env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .map(hash_key)
+        .collect()
+}
+
+pub fn hash_key(key: &str) -> String {
+    hex::encode(keccak256(key.as_bytes()))
+}
+
+// New random user API key; the caller stores hash_key() of it
+pub fn generate_key() -> String {
+    let mut bytes = [0u8; 32];
+    thread_rng().fill_bytes(&mut bytes);
+    format!("{}{}", USER_KEY_PREFIX, hex::encode(bytes))
+}
+
+/// Middleware resolving `Authorization: Bearer <key>` into a `Principal`
+/// request extension. Requests without a known key are rejected with 401.
+pub async fn authenticate(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, OpenBankError> {
+    let unauthorized = || OpenBankError::Unauthorized;
+    let key = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(unauthorized)?;
+    let key_hash = hash_key(key.trim());
+
+    let principal = if state.auth.operator_keys.contains(&key_hash) {
+        Principal { role: Role::Operator, user_id: None }
+    } else if state.auth.auditor_keys.contains(&key_hash) {
+        Principal { role: Role::Auditor, user_id: None }
+    } else {
+        let api_key = state.storage.find_api_key(&key_hash)?
+            .ok_or_else(unauthorized)?;
+        Principal { role: Role::User, user_id: Some(api_key.user_id) }
+    };
+
+    request.extensions_mut().insert(principal);
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn operator() -> Principal {
+        Principal { role: Role::Operator, user_id: None }
+    }
+
+    fn auditor() -> Principal {
+        Principal { role: Role::Auditor, user_id: None }
+    }
+
+    fn user(user_id: &str) -> Principal {
+        Principal { role: Role::User, user_id: Some(user_id.to_string()) }
+    }
+
+    #[test]
+    fn operators_may_do_everything() {
+        let operator = operator();
+        assert!(operator.require_operator().is_ok());
+        assert!(operator.require_staff().is_ok());
+        assert!(operator.require_user("anyone").is_ok());
+        assert!(operator.require_read("anyone").is_ok());
+    }
+
+    #[test]
+    fn auditors_may_read_but_never_write() {
+        let auditor = auditor();
+        assert!(auditor.require_staff().is_ok());
+        assert!(auditor.require_read("anyone").is_ok());
+        assert!(matches!(auditor.require_operator(), Err(OpenBankError::Forbidden)));
+        assert!(matches!(auditor.require_user("anyone"), Err(OpenBankError::Forbidden)));
+    }
+
+    #[test]
+    fn users_only_reach_their_own_data() {
+        let alice = user("alice");
+        assert!(alice.require_user("alice").is_ok());
+        assert!(alice.require_read("alice").is_ok());
+        assert!(matches!(alice.require_user("bob"), Err(OpenBankError::Forbidden)));
+        assert!(matches!(alice.require_read("bob"), Err(OpenBankError::Forbidden)));
+        assert!(matches!(alice.require_staff(), Err(OpenBankError::Forbidden)));
+        assert!(matches!(alice.require_operator(), Err(OpenBankError::Forbidden)));
+
+        // A user principal without an id matches nobody
+        let orphan = Principal { role: Role::User, user_id: None };
+        assert!(orphan.require_user("").is_err());
+        assert!(orphan.require_read("alice").is_err());
+    }
+
+    #[test]
+    fn user_keys_are_random_and_stored_hashed() {
+        let key = generate_key();
+        assert!(key.starts_with(USER_KEY_PREFIX));
+        assert_ne!(key, generate_key());
+        assert_eq!(hash_key(&key), hash_key(&key));
+        assert_ne!(hash_key(&key), key);
+    }
+}
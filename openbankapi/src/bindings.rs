@@ -0,0 +1,6 @@
+// Typed contract bindings generated at compile time from the Foundry artifacts,
+// so a signature mismatch between the ABI and our calls fails the build.
+use ethers::contract::abigen;
+
+abigen!(OnrampEcuador, "OnrampEcuador.json");
+abigen!(USDTToken, "USDTToken.json");
@@ -1,33 +1,79 @@
 use ethers::{
-    contract::{Contract, ContractInstance},
-    core::types::{Address, U256},
-    providers::{Http, Provider},
-    signers::LocalWallet,
-    abi::Abi,
+    contract::{ContractCall, ContractError, ContractRevert},
+    core::types::{
+        transaction::eip2718::TypedTransaction, Address, BlockNumber, Bytes, Transaction, TransactionReceipt, H256,
+        U256, U64,
+    },
+    providers::{Middleware, MiddlewareError, PendingTransaction, Provider},
+    signers::Signer,
     middleware::SignerMiddleware,
+    utils::keccak256,
 };
+use std::collections::BTreeMap;
 use std::sync::Arc;
-use std::fs;
-use crate::types::SmartContractConfig;
+use tokio::sync::Mutex;
+use crate::bindings::{OnrampEcuador, OnrampEcuadorErrors, OnrampEcuadorEvents, USDTToken};
+use crate::gas::GasPolicy;
+use crate::rpc::{self, FailoverTransport};
+use crate::signer::OwnerSigner;
+use crate::types::{
+    ChainEvent, ChainEventKind, ContractStats, ContractTransaction, ContractUserBalance, SmartContractConfig, TxReceipt,
+    TxStatus,
+};
 use crate::error::OpenBankError;
 
+type OwnerClient = SignerMiddleware<Provider<FailoverTransport>, OwnerSigner>;
+
+// Nodes only accept a replacement that pays at least 10% more; bump a bit beyond that
+const REPLACEMENT_FEE_BUMP_PERCENT: u64 = 20;
+
+// Contract call signed by the owner key but not broadcast yet, so its hash
+// and nonce can be recorded before anything reaches the network
+pub struct SignedTransaction {
+    pub tx_hash: String,
+    pub nonce: u64,
+    raw: Bytes,
+}
+
+// Where a previously broadcast transaction stands
+pub enum TxProgress {
+    Pending,          // In the mempool, or mined without enough confirmations yet
+    Unknown,          // The node has never seen it and its nonce is still free
+    Replaced,         // Another transaction from the owner used its nonce
+    Mined(TxReceipt), // Mined and buried under the configured confirmations
+}
+
 pub struct ContractClient {
-    contract: ContractInstance<Arc<SignerMiddleware<Provider<Http>, LocalWallet>>, SignerMiddleware<Provider<Http>, LocalWallet>>,
-    provider: Arc<Provider<Http>>,
+    contract: OnrampEcuador<OwnerClient>,
+    chain_id: u64,
+    confirmations: usize,
+    gas_policy: GasPolicy,
+    ws_url: Option<String>,
+    // Send queue for the owner key: transactions take their nonce from here one
+    // at a time. None means it has to be read from the node again.
+    next_nonce: Mutex<Option<U256>>,
 }
 
 impl ContractClient {
     pub async fn new(config: SmartContractConfig) -> Result<Self, OpenBankError> {
-        let provider = Provider::<Http>::try_from(config.rpc_url)
-            .map_err(|e| OpenBankError::SmartContractError { 
-                message: format!("Failed to create provider: {}", e) 
-            })?;
+        // Every RPC URL gets its own check below, the failover transport would
+        // happily mix answers from endpoints on different chains
+        for url in &config.rpc_urls {
+            let provider = rpc::connect(std::slice::from_ref(url), config.rpc_policy.clone())?;
+            let actual = provider
+                .get_chainid()
+                .await
+                .map_err(|e| OpenBankError::SmartContractError { 
+                    message: format!("Failed to get chain id from {}: {}", rpc::redact(url), e) 
+                })?;
+            if actual != U256::from(config.chain_id) {
+                return Err(OpenBankError::ChainIdMismatch { expected: config.chain_id, actual: actual.low_u64() });
+            }
+        }
+        let provider = rpc::connect(&config.rpc_urls, config.rpc_policy)?;
         
-        let wallet = config.owner_private_key
-            .parse::<LocalWallet>()
-            .map_err(|e| OpenBankError::SmartContractError { 
-                message: format!("Invalid private key: {}", e) 
-            })?;
+        // Local key, keystore or remote signer, depending on SIGNER_BACKEND
+        let signer = OwnerSigner::connect(config.signer, config.chain_id).await?;
         
         let contract_address = config.contract_address
             .parse::<Address>()
@@ -35,132 +81,683 @@ impl ContractClient {
                 message: format!("Invalid contract address: {}", e) 
             })?;
         
-        // Read ABI from JSON file
-        let abi_content = fs::read_to_string("OnrampEcuador.json")
-            .map_err(|e| OpenBankError::SmartContractError { 
-                message: format!("Failed to read ABI file: {}", e) 
-            })?;
+        // Create signer middleware
+        let client = SignerMiddleware::new(provider, signer);
+        let client = Arc::new(client);
         
-        let contract_json: serde_json::Value = serde_json::from_str(&abi_content)
-            .map_err(|e| OpenBankError::SmartContractError { 
-                message: format!("Failed to parse ABI JSON: {}", e) 
-            })?;
+        // Typed contract instance, ABI embedded at compile time
+        let contract = OnrampEcuador::new(contract_address, client);
         
-        let abi = contract_json["abi"]
-            .as_array()
-            .ok_or_else(|| OpenBankError::SmartContractError { 
-                message: "ABI not found in contract JSON".to_string() 
-            })?;
+        Ok(Self {
+            contract,
+            chain_id: config.chain_id,
+            confirmations: config.confirmations,
+            gas_policy: config.gas_policy,
+            ws_url: config.ws_url,
+            next_nonce: Mutex::new(None),
+        })
+    }
+    
+    pub fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+    
+    // WebSocket endpoint for subscriptions, if one is configured
+    pub fn ws_url(&self) -> Option<&str> {
+        self.ws_url.as_deref()
+    }
+    
+    // RPC endpoints in configured order, with their last observed health
+    pub fn rpc_endpoints(&self) -> Vec<String> {
+        self.contract.client_ref().provider().as_ref().describe()
+    }
+    
+    // Broadcasts a contract write and waits until it is buried under the
+    // configured number of confirmations. Once the transaction has a hash,
+    // failures are reported as TransactionNotConfirmed because it may still be mined.
+    async fn send_and_confirm(&self, call: ContractCall<OwnerClient, ()>, action: &str) -> Result<TxReceipt, OpenBankError> {
+        // A nonce that is too low means the local count fell behind; it has been
+        // resynced by broadcast(), so one retry with a fresh nonce is enough
+        let signed = match self.sign_and_broadcast(call.clone()).await {
+            Err(OpenBankError::SmartContractError { message }) if is_nonce_too_low(&message) => {
+                println!("Warning: Owner nonce was behind, retrying {} transaction", action);
+                self.sign_and_broadcast(call).await?
+            }
+            result => result?,
+        };
+        let tx_hash = signed.tx_hash.clone();
         
-        let abi_string = serde_json::to_string(abi)
-            .map_err(|e| OpenBankError::SmartContractError { 
-                message: format!("Failed to serialize ABI: {}", e) 
+        let receipt = PendingTransaction::new(parse_tx_hash(&tx_hash)?, self.contract.client_ref().provider())
+            .confirmations(self.confirmations)
+            .await
+            .map_err(|e| OpenBankError::TransactionNotConfirmed { 
+                tx_hash: tx_hash.clone(),
+                message: e.to_string(),
+            })?
+            .ok_or_else(|| OpenBankError::TransactionNotConfirmed { 
+                tx_hash: tx_hash.clone(),
+                message: "transaction dropped from the mempool".to_string(),
             })?;
         
-        // Parse ABI
-        let abi: Abi = serde_json::from_str(&abi_string)
-            .map_err(|e| OpenBankError::SmartContractError { 
-                message: format!("Failed to parse ABI: {}", e) 
-            })?;
+        Ok(receipt_summary(tx_hash, &receipt))
+    }
+    
+    async fn sign_and_broadcast(&self, call: ContractCall<OwnerClient, ()>) -> Result<SignedTransaction, OpenBankError> {
+        let signed = self.sign_call(call).await?;
+        self.broadcast(&signed).await?;
+        Ok(signed)
+    }
+    
+    // Takes the next nonce from the send queue, fills in gas and chain id and
+    // signs the call locally. Concurrent callers wait for each other, so every
+    // transaction gets its own nonce.
+    async fn sign_call(&self, call: ContractCall<OwnerClient, ()>) -> Result<SignedTransaction, OpenBankError> {
+        let client = self.contract.client_ref();
+        let mut next_nonce = self.next_nonce.lock().await;
+        let nonce = match *next_nonce {
+            Some(nonce) => nonce,
+            None => client
+                .get_transaction_count(client.address(), Some(BlockNumber::Pending.into()))
+                .await
+                .map_err(|e| OpenBankError::SmartContractError { 
+                    message: format!("Failed to get owner nonce: {}", e) 
+                })?,
+        };
         
-        // Create signer middleware
-        let client = SignerMiddleware::new(provider.clone(), wallet);
-        let client = Arc::new(client);
+        let mut tx = call.tx;
+        tx.set_nonce(nonce);
+        let tx = self.fill_transaction(tx).await?;
+        let signed = self.sign_transaction(tx).await?;
         
-        // Create contract instance
-        let contract = Contract::new(contract_address, abi, client.clone());
+        // Only a signed transaction uses up its nonce
+        *next_nonce = Some(nonce + 1);
+        Ok(signed)
+    }
+    
+    // Signs the call again with the nonce of an earlier attempt that is stuck or
+    // was dropped. If the node still has the earlier transaction, fees are bumped
+    // over it so the new one is accepted as its replacement.
+    async fn sign_replacement(
+        &self,
+        call: ContractCall<OwnerClient, ()>,
+        nonce: u64,
+        replaced_hash: &str,
+    ) -> Result<SignedTransaction, OpenBankError> {
+        let mut tx = call.tx;
+        tx.set_nonce(nonce);
+        let mut tx = self.fill_transaction(tx).await?;
         
-        let provider = Arc::new(provider);
+        let previous = self.contract
+            .client_ref()
+            .get_transaction(parse_tx_hash(replaced_hash)?)
+            .await
+            .map_err(|e| OpenBankError::SmartContractError { 
+                message: format!("Failed to look up transaction {}: {}", replaced_hash, e) 
+            })?;
+        if let Some(previous) = previous {
+            // Fee caps only apply to estimates; the ceiling still holds for the bump
+            bump_fees(&mut tx, &previous);
+            self.gas_policy.check_ceiling(&tx)?;
+        }
         
-        Ok(Self { contract, provider })
+        self.sign_transaction(tx).await
     }
     
-    pub async fn deposit_usdt(&self, amount: u64, description: String) -> Result<(), OpenBankError> {
-        let amount_wei = U256::from(amount);
-        
+    // Forgets the locally tracked nonce so the next transaction reads it from the node
+    pub async fn resync_nonce(&self) {
+        *self.next_nonce.lock().await = None;
+    }
+    
+    // Prices the transaction according to the gas policy; fails with
+    // GasCeilingExceeded rather than signing something too expensive
+    async fn fill_transaction(&self, tx: TypedTransaction) -> Result<TypedTransaction, OpenBankError> {
+        let mut tx = self.gas_policy.prepare(tx);
         self.contract
-            .method::<_, ()>("depositUSDT", (amount_wei, description))
-            .map_err(|e| OpenBankError::SmartContractError { 
-                message: format!("Failed to call depositUSDT: {}", e) 
-            })?
-            .send()
+            .client_ref()
+            .fill_transaction(&mut tx, Some(BlockNumber::Pending.into()))
+            .await
+            .map_err(|e| middleware_error("Failed to prepare transaction", &e))?;
+        
+        self.gas_policy.apply(&mut tx);
+        self.gas_policy.check_ceiling(&tx)?;
+        Ok(tx)
+    }
+    
+    async fn sign_transaction(&self, tx: TypedTransaction) -> Result<SignedTransaction, OpenBankError> {
+        let client = self.contract.client_ref();
+        let signature = client
+            .signer()
+            .sign_transaction(&tx)
             .await
             .map_err(|e| OpenBankError::SmartContractError { 
-                message: format!("Failed to send deposit transaction: {}", e) 
+                message: format!("Failed to sign transaction: {}", e) 
             })?;
+        let raw = tx.rlp_signed(&signature);
+        
+        Ok(SignedTransaction {
+            tx_hash: format!("{:?}", H256::from(keccak256(&raw))),
+            nonce: to_u64(tx.nonce().copied().unwrap_or_default(), "nonce")?,
+            raw,
+        })
+    }
+    
+    pub async fn broadcast(&self, signed: &SignedTransaction) -> Result<(), OpenBankError> {
+        let result = self.contract
+            .client_ref()
+            .send_raw_transaction(signed.raw.clone())
+            .await;
+        
+        // Whether or not the node kept the transaction, its pending count is now
+        // the right next nonce; resyncing keeps a lost transaction from leaving a gap
+        if let Err(e) = result {
+            self.resync_nonce().await;
+            return Err(OpenBankError::SmartContractError { 
+                message: format!("Failed to broadcast transaction {}: {}", signed.tx_hash, e) 
+            });
+        }
         
         Ok(())
     }
     
-    pub async fn send_usdt_to_address(
+    pub async fn transaction_progress(&self, tx_hash: &str, nonce: u64) -> Result<TxProgress, OpenBankError> {
+        let hash = parse_tx_hash(tx_hash)?;
+        let client = self.contract.client_ref();
+        let rpc_error = |e: <OwnerClient as Middleware>::Error| OpenBankError::SmartContractError { 
+            message: format!("Failed to look up transaction {}: {}", tx_hash, e) 
+        };
+        
+        if let Some(receipt) = client.get_transaction_receipt(hash).await.map_err(rpc_error)? {
+            let Some(block_number) = receipt.block_number else {
+                return Ok(TxProgress::Pending);
+            };
+            let latest = client.get_block_number().await.map_err(rpc_error)?;
+            let confirmations = (self.confirmations as u64).max(1);
+            if latest.as_u64() + 1 < block_number.as_u64() + confirmations {
+                return Ok(TxProgress::Pending);
+            }
+            return Ok(TxProgress::Mined(receipt_summary(tx_hash.to_string(), &receipt)));
+        }
+        
+        if client.get_transaction(hash).await.map_err(rpc_error)?.is_some() {
+            return Ok(TxProgress::Pending);
+        }
+        
+        // Not known to the node: either dropped, or its nonce went to another transaction.
+        // Check the receipt again in case it was mined while we were looking.
+        let mined_nonce = client
+            .get_transaction_count(client.address(), Some(BlockNumber::Latest.into()))
+            .await
+            .map_err(rpc_error)?;
+        if mined_nonce.as_u64() <= nonce {
+            return Ok(TxProgress::Unknown);
+        }
+        if client.get_transaction_receipt(hash).await.map_err(rpc_error)?.is_some() {
+            return Ok(TxProgress::Pending);
+        }
+        Ok(TxProgress::Replaced)
+    }
+    
+    pub async fn deposit_usdt(&self, amount: u64, description: String) -> Result<TxReceipt, OpenBankError> {
+        let amount_wei = U256::from(amount);
+        
+        let call = self.contract.deposit_usdt(amount_wei, description);
+        self.send_and_confirm(call, "deposit").await
+    }
+    
+    // Signs a sendUSDTToAddress call without broadcasting it
+    pub async fn sign_usdt_transfer(
         &self, 
         recipient: String, 
         amount: u64, 
         description: String
+    ) -> Result<SignedTransaction, OpenBankError> {
+        let call = self.usdt_transfer_call(&recipient, amount, description)?;
+        self.sign_call(call).await
+    }
+    
+    // Prices a sendUSDTToAddress call without signing it, so a withdrawal can be
+    // refused up front while gas is above the configured ceiling
+    pub async fn check_usdt_transfer_gas(
+        &self,
+        recipient: String,
+        amount: u64,
+        description: String,
     ) -> Result<(), OpenBankError> {
-        let recipient = recipient
-            .parse::<Address>()
-            .map_err(|_e| OpenBankError::InvalidWalletAddress { address: recipient.clone() })?;
+        if self.gas_policy.max_tx_fee.is_none() {
+            return Ok(());
+        }
+        let call = self.usdt_transfer_call(&recipient, amount, description)?;
+        self.fill_transaction(call.tx).await?;
+        Ok(())
+    }
+    
+    // Signs the same sendUSDTToAddress call again to replace a stuck attempt
+    pub async fn sign_usdt_transfer_replacement(
+        &self, 
+        recipient: String, 
+        amount: u64, 
+        description: String,
+        nonce: u64,
+        replaced_hash: &str,
+    ) -> Result<SignedTransaction, OpenBankError> {
+        let call = self.usdt_transfer_call(&recipient, amount, description)?;
+        self.sign_replacement(call, nonce, replaced_hash).await
+    }
+    
+    fn usdt_transfer_call(
+        &self,
+        recipient: &str,
+        amount: u64,
+        description: String,
+    ) -> Result<ContractCall<OwnerClient, ()>, OpenBankError> {
+        let recipient = parse_address(recipient)?;
         
         let amount_wei = U256::from(amount);
         
+        Ok(self.contract.send_usdt_to_address(recipient, amount_wei, description))
+    }
+    
+    pub async fn get_user_balance(&self, user_address: String) -> Result<ContractUserBalance, OpenBankError> {
+        let user_address = parse_address(&user_address)?;
+        
+        let balance = self.contract
+            .get_user_balance(user_address)
+            .call()
+            .await
+            .map_err(|e| call_error("Failed to get user balance", e))?;
+        
+        Ok(ContractUserBalance {
+            deposited: balance.deposited,
+            withdrawn: balance.withdrawn,
+            last_deposit: to_u64(balance.last_deposit, "lastDeposit")?,
+            last_withdrawal: to_u64(balance.last_withdrawal, "lastWithdrawal")?,
+            has_deposited: balance.has_deposited,
+        })
+    }
+    
+    pub async fn get_contract_stats(&self) -> Result<ContractStats, OpenBankError> {
+        let result = self.contract
+            .get_contract_stats()
+            .call()
+            .await
+            .map_err(|e| call_error("Failed to get contract stats", e))?;
+        
+        Ok(ContractStats {
+            total_users: result.0,
+            total_deposits: result.1,
+            total_withdrawals: result.2,
+            contract_balance: result.3,
+            total_transactions: result.4,
+        })
+    }
+    
+    pub async fn get_available_balance(&self, user_address: String) -> Result<U256, OpenBankError> {
+        let user_address = parse_address(&user_address)?;
+        
         self.contract
-            .method::<_, ()>("sendUSDTToAddress", (recipient, amount_wei, description))
-            .map_err(|e| OpenBankError::SmartContractError { 
-                message: format!("Failed to call sendUSDTToAddress: {}", e) 
-            })?
-            .send()
+            .get_available_balance(user_address)
+            .call()
             .await
-            .map_err(|e| OpenBankError::SmartContractError { 
-                message: format!("Failed to send withdrawal transaction: {}", e) 
-            })?;
+            .map_err(|e| call_error("Failed to get available balance", e))
+    }
+    
+    pub async fn get_transaction(&self, transaction_id: u64) -> Result<ContractTransaction, OpenBankError> {
+        let transaction = self.contract
+            .get_transaction(U256::from(transaction_id))
+            .call()
+            .await
+            .map_err(|e| call_error(&format!("Failed to get transaction {}", transaction_id), e))?;
         
-        Ok(())
+        Ok(ContractTransaction {
+            id: transaction_id,
+            user: format!("{:?}", transaction.user),
+            amount: transaction.amount,
+            timestamp: to_u64(transaction.timestamp, "timestamp")?,
+            is_deposit: transaction.is_deposit,
+            description: transaction.description,
+        })
+    }
+    
+    // Ids of the user's most recent contract transactions, at most `limit`
+    pub async fn get_user_transactions(&self, user_address: String, limit: u64) -> Result<Vec<u64>, OpenBankError> {
+        let user_address = parse_address(&user_address)?;
+        
+        let ids = self.contract
+            .get_user_transactions(user_address, U256::from(limit))
+            .call()
+            .await
+            .map_err(|e| call_error("Failed to get user transactions", e))?;
+        
+        ids.into_iter().map(|id| to_u64(id, "transactionId")).collect()
+    }
+    
+    pub async fn is_paused(&self) -> Result<bool, OpenBankError> {
+        self.contract
+            .paused()
+            .call()
+            .await
+            .map_err(|e| call_error("Failed to get paused state", e))
+    }
+    
+    // Owner only: stops deposits and withdrawals on the contract
+    pub async fn pause(&self) -> Result<TxReceipt, OpenBankError> {
+        self.send_and_confirm(self.contract.pause(), "pause").await
+    }
+    
+    // Owner only
+    pub async fn unpause(&self) -> Result<TxReceipt, OpenBankError> {
+        self.send_and_confirm(self.contract.unpause(), "unpause").await
     }
     
-    pub async fn get_user_balance(&self, user_address: String) -> Result<crate::types::ContractUserBalance, OpenBankError> {
-        let user_address = user_address
+    // Owner only: moves the contract's whole USDT balance to the owner
+    pub async fn emergency_withdraw(&self) -> Result<TxReceipt, OpenBankError> {
+        self.send_and_confirm(self.contract.emergency_withdraw(), "emergency withdraw").await
+    }
+    
+    // Owner only: points the contract at a different USDT token
+    pub async fn set_usdt_token(&self, token_address: String) -> Result<TxReceipt, OpenBankError> {
+        let token_address = token_address
             .parse::<Address>()
-            .map_err(|_e| OpenBankError::InvalidWalletAddress { address: user_address.clone() })?;
+            .map_err(|_e| OpenBankError::InvalidTokenAddress { address: token_address.clone() })?;
         
-        let result: (U256, U256, U256, U256, bool) = self.contract
-            .method("getUserBalance", user_address)
-            .map_err(|e| OpenBankError::SmartContractError { 
-                message: format!("Failed to call getUserBalance: {}", e) 
-            })?
+        self.send_and_confirm(self.contract.set_usdt_token(token_address), "set USDT token").await
+    }
+    
+    // The USDT token the contract pays out, bound to the same owner client
+    pub async fn usdt_token(&self) -> Result<USDTToken<OwnerClient>, OpenBankError> {
+        let token_address = self.contract
+            .usdt_token()
             .call()
             .await
+            .map_err(|e| call_error("Failed to get USDT token address", e))?;
+        
+        Ok(USDTToken::new(token_address, self.contract.client()))
+    }
+    
+    // USDT held by the contract and available for withdrawals, in base units
+    pub async fn get_usdt_reserve(&self) -> Result<U256, OpenBankError> {
+        self.usdt_token()
+            .await?
+            .balance_of(self.contract.address())
+            .call()
+            .await
+            .map_err(|e| call_error("Failed to get contract USDT balance", e))
+    }
+    
+    pub async fn latest_block_number(&self) -> Result<u64, OpenBankError> {
+        self.contract.client()
+            .get_block_number()
+            .await
+            .map(|n| n.as_u64())
+            .map_err(|e| OpenBankError::SmartContractError { 
+                message: format!("Failed to get latest block number: {}", e) 
+            })
+    }
+    
+    // Hash of the canonical block at this height, None if the node doesn't have it yet
+    pub async fn block_hash(&self, block_number: u64) -> Result<Option<String>, OpenBankError> {
+        let block = self.contract.client()
+            .get_block(block_number)
+            .await
             .map_err(|e| OpenBankError::SmartContractError { 
-                message: format!("Failed to get user balance: {}", e) 
+                message: format!("Failed to get block {}: {}", block_number, e) 
             })?;
         
-        Ok(crate::types::ContractUserBalance {
-            deposited: result.0.as_u64(),
-            withdrawn: result.1.as_u64(),
-            last_deposit: result.2.as_u64(),
-            last_withdrawal: result.3.as_u64(),
-            has_deposited: result.4,
-        })
+        Ok(block.and_then(|b| b.hash).map(|h| format!("{:?}", h)))
     }
     
-    pub async fn get_contract_stats(&self) -> Result<(u64, u64, u64, u64, u64), OpenBankError> {
-        let result: (U256, U256, U256, U256, U256) = self.contract
-            .method("getContractStats", ())
-            .map_err(|e| OpenBankError::SmartContractError { 
-                message: format!("Failed to call getContractStats: {}", e) 
-            })?
-            .call()
+    // Deposit, withdrawal, emergency withdrawal and token change events in an
+    // inclusive block range; ownership and pause events are skipped
+    pub async fn get_events(&self, from_block: u64, to_block: u64) -> Result<Vec<ChainEvent>, OpenBankError> {
+        let logs = self.contract
+            .events()
+            .from_block(from_block)
+            .to_block(to_block)
+            .query_with_meta()
             .await
             .map_err(|e| OpenBankError::SmartContractError { 
-                message: format!("Failed to get contract stats: {}", e) 
+                message: format!("Failed to get events for blocks {}-{}: {}", from_block, to_block, e) 
             })?;
         
-        Ok((
-            result.0.as_u64(),
-            result.1.as_u64(),
-            result.2.as_u64(),
-            result.3.as_u64(),
-            result.4.as_u64(),
-        ))
+        let mut events = Vec::new();
+        for (event, meta) in logs {
+            let (kind, address, amount, description, timestamp) = match event {
+                OnrampEcuadorEvents::DepositMadeFilter(e) => {
+                    (ChainEventKind::DepositMade, e.user, e.amount, Some(e.description), Some(e.timestamp))
+                }
+                OnrampEcuadorEvents::WithdrawalMadeFilter(e) => {
+                    (ChainEventKind::WithdrawalMade, e.user, e.amount, Some(e.description), Some(e.timestamp))
+                }
+                OnrampEcuadorEvents::EmergencyWithdrawFilter(e) => {
+                    (ChainEventKind::EmergencyWithdraw, e.owner, e.amount, None, Some(e.timestamp))
+                }
+                OnrampEcuadorEvents::UsdttokenSetFilter(e) => {
+                    (ChainEventKind::UsdtTokenSet, e.token_address, U256::zero(), None, None)
+                }
+                _ => continue,
+            };
+            
+            let tx_hash = format!("{:?}", meta.transaction_hash);
+            let log_index = to_u64(meta.log_index, "logIndex")?;
+            events.push(ChainEvent {
+                id: format!("{}:{}:{}", self.chain_id, tx_hash, log_index),
+                chain_id: self.chain_id,
+                kind,
+                address: format!("{:?}", address),
+                amount,
+                description,
+                timestamp: timestamp.map(|t| to_u64(t, "timestamp")).transpose()?,
+                block_number: meta.block_number.as_u64(),
+                block_hash: format!("{:?}", meta.block_hash),
+                tx_hash,
+                log_index,
+            });
+        }
+        
+        Ok(events)
+    }
+}
+
+// One contract client per configured chain, keyed by chain id. Requests that
+// don't name a chain go to the default chain, the first one configured.
+pub struct ContractClients {
+    clients: BTreeMap<u64, Arc<ContractClient>>,
+    default_chain_id: u64,
+}
+
+impl ContractClients {
+    pub async fn connect(configs: Vec<SmartContractConfig>) -> Result<Self, OpenBankError> {
+        let default_chain_id = configs
+            .first()
+            .map(|config| config.chain_id)
+            .ok_or_else(|| OpenBankError::SmartContractError { 
+                message: "No chains configured".to_string() 
+            })?;
+        
+        let mut clients = BTreeMap::new();
+        for config in configs {
+            let chain_id = config.chain_id;
+            let client = ContractClient::new(config).await?;
+            if clients.insert(chain_id, Arc::new(client)).is_some() {
+                return Err(OpenBankError::SmartContractError { 
+                    message: format!("Chain {} is configured twice", chain_id) 
+                });
+            }
+        }
+        
+        Ok(Self { clients, default_chain_id })
+    }
+    
+    pub fn get(&self, chain_id: Option<u64>) -> Result<Arc<ContractClient>, OpenBankError> {
+        let chain_id = chain_id.unwrap_or(self.default_chain_id);
+        self.clients
+            .get(&chain_id)
+            .cloned()
+            .ok_or(OpenBankError::UnsupportedChain { chain_id })
+    }
+    
+    pub fn all(&self) -> impl Iterator<Item = &Arc<ContractClient>> {
+        self.clients.values()
+    }
+}
+
+fn parse_address(address: &str) -> Result<Address, OpenBankError> {
+    address
+        .parse::<Address>()
+        .map_err(|_e| OpenBankError::InvalidWalletAddress { address: address.to_string() })
+}
+
+fn parse_tx_hash(tx_hash: &str) -> Result<H256, OpenBankError> {
+    tx_hash
+        .parse::<H256>()
+        .map_err(|e| OpenBankError::SmartContractError { 
+            message: format!("Invalid transaction hash {}: {}", tx_hash, e) 
+        })
+}
+
+// A failed contract read, or a revert explained by revert_error
+fn call_error<M: Middleware>(context: &str, e: ContractError<M>) -> OpenBankError {
+    if let Some(inner) = e.as_middleware_error() {
+        return middleware_error(context, inner);
+    }
+    match e.as_revert() {
+        Some(data) => revert_error(data, &e.to_string()),
+        None => OpenBankError::SmartContractError { 
+            message: format!("{}: {}", context, e) 
+        },
+    }
+}
+
+// Same for errors from the node, e.g. a gas estimate that reverts
+fn middleware_error<E: MiddlewareError>(context: &str, e: &E) -> OpenBankError {
+    let revert = e
+        .as_error_response()
+        .and_then(|response| Some(revert_error(&response.as_revert_data()?, &response.message)));
+    revert.unwrap_or_else(|| OpenBankError::SmartContractError { 
+        message: format!("{}: {}", context, e) 
+    })
+}
+
+// Turns revert data into the matching API error. Not every node returns the
+// data, so the reason is also looked for in the error message.
+fn revert_error(data: &Bytes, message: &str) -> OpenBankError {
+    let reason = match OnrampEcuadorErrors::decode_with_selector(data) {
+        Some(OnrampEcuadorErrors::EnforcedPause(_)) => return OpenBankError::ContractPaused,
+        Some(OnrampEcuadorErrors::ExpectedPause(_)) => "contract is not paused".to_string(),
+        Some(OnrampEcuadorErrors::OwnableUnauthorizedAccount(e)) => {
+            format!("{:?} is not the contract owner", e.account)
+        }
+        Some(OnrampEcuadorErrors::OwnableInvalidOwner(e)) => format!("{:?} is not a valid owner", e.owner),
+        Some(OnrampEcuadorErrors::ReentrancyGuardReentrantCall(_)) => "reentrant call".to_string(),
+        Some(OnrampEcuadorErrors::RevertString(reason)) => reason,
+        None => match message.split_once("reverted: ") {
+            Some((_, reason)) => reason.trim().to_string(),
+            None => message.to_string(),
+        },
+    };
+    
+    // Reasons from require() strings, including OpenZeppelin 4's Pausable
+    match reason.as_str() {
+        "Pausable: paused" => OpenBankError::ContractPaused,
+        "Insufficient contract balance" => OpenBankError::InsufficientContractBalance,
+        _ => OpenBankError::ContractReverted { reason },
+    }
+}
+
+// Geth and most other nodes say "nonce too low", some clients word it differently
+fn is_nonce_too_low(message: &str) -> bool {
+    let message = message.to_lowercase();
+    message.contains("nonce too low") || message.contains("nonce is too low") || message.contains("oldnonce")
+}
+
+// Raises the fees of a replacement to at least REPLACEMENT_FEE_BUMP_PERCENT
+// above the transaction it replaces, keeping current fees if they are higher
+fn bump_fees(tx: &mut TypedTransaction, previous: &Transaction) {
+    let bump = |fee: U256| fee * (100 + REPLACEMENT_FEE_BUMP_PERCENT) / 100;
+    if let TypedTransaction::Eip1559(inner) = tx {
+        if let Some(fee) = previous.max_fee_per_gas {
+            inner.max_fee_per_gas = inner.max_fee_per_gas.max(Some(bump(fee)));
+        }
+        if let Some(fee) = previous.max_priority_fee_per_gas {
+            inner.max_priority_fee_per_gas = inner.max_priority_fee_per_gas.max(Some(bump(fee)));
+        }
+    } else if let Some(price) = previous.gas_price {
+        let price = tx.gas_price().unwrap_or_default().max(bump(price));
+        tx.set_gas_price(price);
+    }
+}
+
+// Contract values that are semantically small (ids, timestamps, gas) still
+// arrive as U256; a value out of range is an error rather than a panic
+fn to_u64(value: U256, field: &str) -> Result<u64, OpenBankError> {
+    u64::try_from(value).map_err(|_| OpenBankError::SmartContractError {
+        message: format!("Contract value {} = {} does not fit in 64 bits", field, value),
+    })
+}
+
+fn receipt_summary(tx_hash: String, receipt: &TransactionReceipt) -> TxReceipt {
+    let status = if receipt.status == Some(U64::one()) {
+        TxStatus::Success
+    } else {
+        TxStatus::Reverted
+    };
+    
+    TxReceipt {
+        tx_hash,
+        block_number: receipt.block_number.map(|n| n.as_u64()),
+        gas_used: receipt.gas_used.and_then(|g| u64::try_from(g).ok()),
+        status,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::abi::{AbiEncode, Token};
+    use crate::bindings::EnforcedPause;
+
+    // Error(string) revert data, as a require() with a message produces
+    fn revert_string(reason: &str) -> Bytes {
+        let mut data = vec![0x08, 0xc3, 0x79, 0xa0];
+        data.extend(ethers::abi::encode(&[Token::String(reason.to_string())]));
+        data.into()
+    }
+
+    #[test]
+    fn revert_data_maps_to_api_errors() {
+        let paused = OnrampEcuadorErrors::EnforcedPause(EnforcedPause).encode();
+        assert!(matches!(revert_error(&paused.into(), ""), OpenBankError::ContractPaused));
+
+        let cases = [
+            ("Pausable: paused", "CONTRACT_PAUSED"),
+            ("Insufficient contract balance", "INSUFFICIENT_CONTRACT_BALANCE"),
+            ("Amount must be positive", "CONTRACT_REVERTED"),
+        ];
+        for (reason, code) in cases {
+            let error = revert_error(&revert_string(reason), "execution reverted");
+            assert_eq!(serde_json::to_value(&error).unwrap()["code"], code, "{}", reason);
+        }
+    }
+
+    #[test]
+    fn reason_falls_back_to_the_message() {
+        let cases = [
+            ("execution reverted: Pausable: paused", "CONTRACT_PAUSED"),
+            ("execution reverted: Insufficient contract balance", "INSUFFICIENT_CONTRACT_BALANCE"),
+            ("execution reverted: Amount must be positive", "CONTRACT_REVERTED"),
+        ];
+        for (message, code) in cases {
+            let error = revert_error(&Bytes::default(), message);
+            assert_eq!(serde_json::to_value(&error).unwrap()["code"], code, "{}", message);
+        }
+
+        match revert_error(&Bytes::default(), "execution reverted: Amount must be positive ") {
+            OpenBankError::ContractReverted { reason } => assert_eq!(reason, "Amount must be positive"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match revert_error(&Bytes::default(), "out of gas") {
+            OpenBankError::ContractReverted { reason } => assert_eq!(reason, "out of gas"),
+            other => panic!("unexpected {:?}", other),
+        }
     }
 }
@@ -1,7 +1,17 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
 use thiserror::Error;
 use serde::Serialize;
+use crate::request_id;
+use crate::types::ApiResponse;
 
+// Serialized as { "code": "USER_NOT_FOUND", "details": { ... } }. The codes are
+// part of the public API: renaming a variant needs a #[serde(rename)] to keep its code.
 #[derive(Error, Debug, Serialize)]
+#[serde(tag = "code", content = "details", rename_all = "SCREAMING_SNAKE_CASE")]
 pub enum OpenBankError {
     #[error("User not found: {user_id}")]
     UserNotFound { user_id: String },
@@ -9,8 +19,92 @@ pub enum OpenBankError {
     #[error("Account not found: {account_id}")]
     AccountNotFound { account_id: String },
     
-    #[error("Invalid amount: {amount}. Amount must be positive")]
-    InvalidAmount { amount: f64 },
+    #[error("Invalid amount: {amount}. Amount must be a positive decimal within the currency precision")]
+    InvalidAmount { amount: String },
+    
+    #[error("Amount out of range: {amount}")]
+    AmountOutOfRange { amount: String },
+    
+    #[error("Invalid exchange rate: {rate}. Rate must be a positive decimal")]
+    InvalidRate { rate: String },
+    
+    #[error("Converting {from} to {to} needs an FX quote")]
+    FxQuoteRequired { from: String, to: String },
+    
+    #[error("Amount {amount} does not cover its fees of {fees}")]
+    AmountBelowFees { amount: String, fees: String },
+    
+    #[error("No exchange rate available from {from} to {to}")]
+    RateUnavailable { from: String, to: String },
+    
+    #[error("Quote not found: {quote_id}")]
+    QuoteNotFound { quote_id: String },
+    
+    #[error("Quote {quote_id} has expired")]
+    QuoteExpired { quote_id: String },
+    
+    #[error("Quote {quote_id} was already used")]
+    QuoteAlreadyUsed { quote_id: String },
+    
+    #[error("Quote {quote_id} does not match the request: {reason}")]
+    QuoteMismatch { quote_id: String, reason: String },
+    
+    #[error("Unsupported currency: {currency}")]
+    UnsupportedCurrency { currency: String },
+    
+    #[error("Currency mismatch: expected {expected}, found {found}")]
+    CurrencyMismatch { expected: String, found: String },
+    
+    #[error("Insufficient funds in account {account_id}: available {available}, requested {requested}")]
+    InsufficientFunds { account_id: String, available: String, requested: String },
+    
+    #[error("Account {account_id} is closed")]
+    AccountInactive { account_id: String },
+    
+    #[error("Account {account_id} still holds {balance} and can only be closed once empty")]
+    AccountNotEmpty { account_id: String, balance: String },
+    
+    #[error("Withdrawal of {requested} is above the per-transaction limit of {limit}")]
+    TransactionLimitExceeded { limit: String, requested: String },
+    
+    #[error("Withdrawal of {requested} would exceed the daily limit of {limit}, {used} already used")]
+    DailyLimitExceeded { limit: String, used: String, requested: String },
+    
+    #[error("Withdrawal of {requested} would exceed the monthly limit of {limit}, {used} already used")]
+    MonthlyLimitExceeded { limit: String, used: String, requested: String },
+    
+    #[error("Withdrawal of {requested} would exceed the hourly outflow limit of {limit} on chain {chain_id}, {used} already out")]
+    HourlyOutflowLimitExceeded { chain_id: u64, limit: String, used: String, requested: String },
+    
+    #[error("Account {account_id} does not belong to user {user_id}")]
+    AccountOwnershipMismatch { account_id: String, user_id: String },
+    
+    #[error("Hold not found or no longer active: {hold_id}")]
+    HoldNotFound { hold_id: String },
+    
+    #[error("Withdrawal not found: {withdrawal_id}")]
+    WithdrawalNotFound { withdrawal_id: String },
+    
+    #[error("No reconciliation report has been generated yet")]
+    ReconciliationReportNotFound,
+    
+    #[error("Invalid request: {message}")]
+    InvalidRequest { message: String },
+    
+    #[error("Invalid Idempotency-Key header: must be 1-255 visible ASCII characters")]
+    InvalidIdempotencyKey,
+    
+    #[error("Idempotency key {key} was already used with a different request")]
+    IdempotencyKeyReused { key: String },
+    
+    #[error("A request with idempotency key {key} is still being processed")]
+    IdempotencyRequestInProgress { key: String },
+    
+    #[error("Missing or invalid API key")]
+    Unauthorized,
+    
+    #[error("Not allowed to access this resource")]
+    Forbidden,
     
     #[error("User already exists: {email}")]
     UserAlreadyExists { email: String },
@@ -18,9 +112,252 @@ pub enum OpenBankError {
     #[error("Invalid wallet address: {address}")]
     InvalidWalletAddress { address: String },
     
+    #[error("Wallet challenge not found or already used: {nonce}")]
+    WalletChallengeNotFound { nonce: String },
+    
+    #[error("Wallet challenge {nonce} has expired")]
+    WalletChallengeExpired { nonce: String },
+    
+    #[error("Signature was not made by wallet {address}")]
+    InvalidWalletSignature { address: String },
+    
+    #[error("Invalid token address: {address}")]
+    InvalidTokenAddress { address: String },
+    
     #[error("No wallet address associated with user")]
     NoWalletAddress,
     
+    #[error("Chain {chain_id} is not configured")]
+    UnsupportedChain { chain_id: u64 },
+    
+    #[error("Wallet is registered on chain {wallet_chain_id}, not chain {chain_id}")]
+    WalletChainMismatch { wallet_chain_id: u64, chain_id: u64 },
+    
+    #[error("RPC endpoint reports chain {actual}, expected chain {expected}")]
+    ChainIdMismatch { expected: u64, actual: u64 },
+    
     #[error("Smart contract error: {message}")]
     SmartContractError { message: String },
+    
+    #[error("The contract is paused")]
+    ContractPaused,
+    
+    #[error("The contract does not hold enough USDT for this transfer")]
+    InsufficientContractBalance,
+    
+    #[error("The contract rejected the transaction: {reason}")]
+    ContractReverted { reason: String },
+    
+    #[error("Estimated transaction fee of {fee} wei exceeds the ceiling of {ceiling} wei")]
+    GasCeilingExceeded { fee: String, ceiling: String },
+    
+    #[error("Transaction {tx_hash} was broadcast but not confirmed: {message}")]
+    TransactionNotConfirmed { tx_hash: String, message: String },
+    
+    #[error("Journal entry {entry_id} does not balance")]
+    UnbalancedJournalEntry { entry_id: String },
+    
+    #[error("Storage error: {message}")]
+    StorageError { message: String },
+}
+
+impl OpenBankError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            OpenBankError::UserNotFound { .. }
+            | OpenBankError::AccountNotFound { .. }
+            | OpenBankError::HoldNotFound { .. }
+            | OpenBankError::WithdrawalNotFound { .. }
+            | OpenBankError::WalletChallengeNotFound { .. }
+            | OpenBankError::QuoteNotFound { .. }
+            | OpenBankError::ReconciliationReportNotFound => StatusCode::NOT_FOUND,
+            OpenBankError::InvalidAmount { .. }
+            | OpenBankError::AmountOutOfRange { .. }
+            | OpenBankError::InvalidRate { .. }
+            | OpenBankError::FxQuoteRequired { .. }
+            | OpenBankError::QuoteExpired { .. }
+            | OpenBankError::QuoteMismatch { .. }
+            | OpenBankError::AmountBelowFees { .. }
+            | OpenBankError::UnsupportedCurrency { .. }
+            | OpenBankError::CurrencyMismatch { .. }
+            | OpenBankError::InvalidRequest { .. }
+            | OpenBankError::InvalidIdempotencyKey
+            | OpenBankError::InvalidWalletAddress { .. }
+            | OpenBankError::InvalidWalletSignature { .. }
+            | OpenBankError::WalletChallengeExpired { .. }
+            | OpenBankError::InvalidTokenAddress { .. }
+            | OpenBankError::UnsupportedChain { .. }
+            | OpenBankError::WalletChainMismatch { .. } => StatusCode::BAD_REQUEST,
+            OpenBankError::Unauthorized => StatusCode::UNAUTHORIZED,
+            OpenBankError::Forbidden
+            | OpenBankError::AccountOwnershipMismatch { .. } => StatusCode::FORBIDDEN,
+            OpenBankError::UserAlreadyExists { .. }
+            | OpenBankError::IdempotencyRequestInProgress { .. }
+            | OpenBankError::AccountInactive { .. }
+            | OpenBankError::QuoteAlreadyUsed { .. } => StatusCode::CONFLICT,
+            OpenBankError::InsufficientFunds { .. }
+            | OpenBankError::AccountNotEmpty { .. }
+            | OpenBankError::TransactionLimitExceeded { .. }
+            | OpenBankError::DailyLimitExceeded { .. }
+            | OpenBankError::MonthlyLimitExceeded { .. }
+            | OpenBankError::NoWalletAddress
+            | OpenBankError::RateUnavailable { .. }
+            | OpenBankError::IdempotencyKeyReused { .. }
+            | OpenBankError::ContractReverted { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            OpenBankError::StorageError { .. }
+            | OpenBankError::UnbalancedJournalEntry { .. }
+            | OpenBankError::SmartContractError { .. }
+            | OpenBankError::ChainIdMismatch { .. }
+            | OpenBankError::TransactionNotConfirmed { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            OpenBankError::ContractPaused
+            | OpenBankError::InsufficientContractBalance
+            | OpenBankError::GasCeilingExceeded { .. }
+            | OpenBankError::HourlyOutflowLimitExceeded { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+// The `error` of a failed ApiResponse: code and details of the variant, the
+// human readable message and the id of the request for support and logs
+#[derive(Debug, Serialize)]
+pub struct ApiError {
+    #[serde(flatten)]
+    pub error: OpenBankError,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl IntoResponse for OpenBankError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let request_id = request_id::current();
+        if status.is_server_error() {
+            println!("Warning: Request {} failed: {}", request_id.as_deref().unwrap_or("-"), self);
+        }
+
+        let body = ApiResponse::<()> {
+            success: false,
+            data: None,
+            error: Some(ApiError {
+                message: self.to_string(),
+                error: self,
+                request_id,
+            }),
+        };
+        (status, Json(body)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s(value: &str) -> String {
+        value.to_string()
+    }
+
+    // Every variant with its status and code. status() has no catch-all, so a new
+    // variant has to be placed there, and this table pins where the others went.
+    fn table() -> Vec<(OpenBankError, StatusCode, &'static str)> {
+        vec![
+            (OpenBankError::UserNotFound { user_id: s("u") }, StatusCode::NOT_FOUND, "USER_NOT_FOUND"),
+            (OpenBankError::AccountNotFound { account_id: s("a") }, StatusCode::NOT_FOUND, "ACCOUNT_NOT_FOUND"),
+            (OpenBankError::HoldNotFound { hold_id: s("h") }, StatusCode::NOT_FOUND, "HOLD_NOT_FOUND"),
+            (OpenBankError::WithdrawalNotFound { withdrawal_id: s("w") }, StatusCode::NOT_FOUND, "WITHDRAWAL_NOT_FOUND"),
+            (OpenBankError::WalletChallengeNotFound { nonce: s("n") }, StatusCode::NOT_FOUND, "WALLET_CHALLENGE_NOT_FOUND"),
+            (OpenBankError::QuoteNotFound { quote_id: s("q") }, StatusCode::NOT_FOUND, "QUOTE_NOT_FOUND"),
+            (OpenBankError::ReconciliationReportNotFound, StatusCode::NOT_FOUND, "RECONCILIATION_REPORT_NOT_FOUND"),
+            (OpenBankError::InvalidAmount { amount: s("x") }, StatusCode::BAD_REQUEST, "INVALID_AMOUNT"),
+            (OpenBankError::AmountOutOfRange { amount: s("x") }, StatusCode::BAD_REQUEST, "AMOUNT_OUT_OF_RANGE"),
+            (OpenBankError::InvalidRate { rate: s("x") }, StatusCode::BAD_REQUEST, "INVALID_RATE"),
+            (OpenBankError::FxQuoteRequired { from: s("EUR"), to: s("USDT") }, StatusCode::BAD_REQUEST, "FX_QUOTE_REQUIRED"),
+            (OpenBankError::QuoteExpired { quote_id: s("q") }, StatusCode::BAD_REQUEST, "QUOTE_EXPIRED"),
+            (OpenBankError::QuoteMismatch { quote_id: s("q"), reason: s("r") }, StatusCode::BAD_REQUEST, "QUOTE_MISMATCH"),
+            (OpenBankError::AmountBelowFees { amount: s("1"), fees: s("2") }, StatusCode::BAD_REQUEST, "AMOUNT_BELOW_FEES"),
+            (OpenBankError::UnsupportedCurrency { currency: s("X") }, StatusCode::BAD_REQUEST, "UNSUPPORTED_CURRENCY"),
+            (OpenBankError::CurrencyMismatch { expected: s("USD"), found: s("EUR") }, StatusCode::BAD_REQUEST, "CURRENCY_MISMATCH"),
+            (OpenBankError::InvalidRequest { message: s("m") }, StatusCode::BAD_REQUEST, "INVALID_REQUEST"),
+            (OpenBankError::InvalidIdempotencyKey, StatusCode::BAD_REQUEST, "INVALID_IDEMPOTENCY_KEY"),
+            (OpenBankError::InvalidWalletAddress { address: s("0x") }, StatusCode::BAD_REQUEST, "INVALID_WALLET_ADDRESS"),
+            (OpenBankError::InvalidWalletSignature { address: s("0x") }, StatusCode::BAD_REQUEST, "INVALID_WALLET_SIGNATURE"),
+            (OpenBankError::WalletChallengeExpired { nonce: s("n") }, StatusCode::BAD_REQUEST, "WALLET_CHALLENGE_EXPIRED"),
+            (OpenBankError::InvalidTokenAddress { address: s("0x") }, StatusCode::BAD_REQUEST, "INVALID_TOKEN_ADDRESS"),
+            (OpenBankError::UnsupportedChain { chain_id: 5 }, StatusCode::BAD_REQUEST, "UNSUPPORTED_CHAIN"),
+            (OpenBankError::WalletChainMismatch { wallet_chain_id: 1, chain_id: 137 }, StatusCode::BAD_REQUEST, "WALLET_CHAIN_MISMATCH"),
+            (OpenBankError::Unauthorized, StatusCode::UNAUTHORIZED, "UNAUTHORIZED"),
+            (OpenBankError::Forbidden, StatusCode::FORBIDDEN, "FORBIDDEN"),
+            (OpenBankError::AccountOwnershipMismatch { account_id: s("a"), user_id: s("u") }, StatusCode::FORBIDDEN, "ACCOUNT_OWNERSHIP_MISMATCH"),
+            (OpenBankError::UserAlreadyExists { email: s("e") }, StatusCode::CONFLICT, "USER_ALREADY_EXISTS"),
+            (OpenBankError::IdempotencyRequestInProgress { key: s("k") }, StatusCode::CONFLICT, "IDEMPOTENCY_REQUEST_IN_PROGRESS"),
+            (OpenBankError::AccountInactive { account_id: s("a") }, StatusCode::CONFLICT, "ACCOUNT_INACTIVE"),
+            (OpenBankError::QuoteAlreadyUsed { quote_id: s("q") }, StatusCode::CONFLICT, "QUOTE_ALREADY_USED"),
+            (
+                OpenBankError::InsufficientFunds { account_id: s("a"), available: s("1"), requested: s("2") },
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "INSUFFICIENT_FUNDS",
+            ),
+            (OpenBankError::AccountNotEmpty { account_id: s("a"), balance: s("1") }, StatusCode::UNPROCESSABLE_ENTITY, "ACCOUNT_NOT_EMPTY"),
+            (
+                OpenBankError::TransactionLimitExceeded { limit: s("1"), requested: s("2") },
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "TRANSACTION_LIMIT_EXCEEDED",
+            ),
+            (
+                OpenBankError::DailyLimitExceeded { limit: s("1"), used: s("1"), requested: s("2") },
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "DAILY_LIMIT_EXCEEDED",
+            ),
+            (
+                OpenBankError::MonthlyLimitExceeded { limit: s("1"), used: s("1"), requested: s("2") },
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "MONTHLY_LIMIT_EXCEEDED",
+            ),
+            (OpenBankError::NoWalletAddress, StatusCode::UNPROCESSABLE_ENTITY, "NO_WALLET_ADDRESS"),
+            (OpenBankError::RateUnavailable { from: s("EUR"), to: s("USDT") }, StatusCode::UNPROCESSABLE_ENTITY, "RATE_UNAVAILABLE"),
+            (OpenBankError::IdempotencyKeyReused { key: s("k") }, StatusCode::UNPROCESSABLE_ENTITY, "IDEMPOTENCY_KEY_REUSED"),
+            (OpenBankError::ContractReverted { reason: s("r") }, StatusCode::UNPROCESSABLE_ENTITY, "CONTRACT_REVERTED"),
+            (OpenBankError::StorageError { message: s("m") }, StatusCode::INTERNAL_SERVER_ERROR, "STORAGE_ERROR"),
+            (OpenBankError::UnbalancedJournalEntry { entry_id: s("j") }, StatusCode::INTERNAL_SERVER_ERROR, "UNBALANCED_JOURNAL_ENTRY"),
+            (OpenBankError::SmartContractError { message: s("m") }, StatusCode::INTERNAL_SERVER_ERROR, "SMART_CONTRACT_ERROR"),
+            (OpenBankError::ChainIdMismatch { expected: 1, actual: 137 }, StatusCode::INTERNAL_SERVER_ERROR, "CHAIN_ID_MISMATCH"),
+            (
+                OpenBankError::TransactionNotConfirmed { tx_hash: s("0x"), message: s("m") },
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "TRANSACTION_NOT_CONFIRMED",
+            ),
+            (OpenBankError::ContractPaused, StatusCode::SERVICE_UNAVAILABLE, "CONTRACT_PAUSED"),
+            (OpenBankError::InsufficientContractBalance, StatusCode::SERVICE_UNAVAILABLE, "INSUFFICIENT_CONTRACT_BALANCE"),
+            (OpenBankError::GasCeilingExceeded { fee: s("1"), ceiling: s("0") }, StatusCode::SERVICE_UNAVAILABLE, "GAS_CEILING_EXCEEDED"),
+            (
+                OpenBankError::HourlyOutflowLimitExceeded { chain_id: 1, limit: s("1"), used: s("1"), requested: s("2") },
+                StatusCode::SERVICE_UNAVAILABLE,
+                "HOURLY_OUTFLOW_LIMIT_EXCEEDED",
+            ),
+        ]
+    }
+
+    #[test]
+    fn variants_map_to_their_status_and_code() {
+        for (error, status, code) in table() {
+            assert_eq!(error.status(), status, "{}", code);
+            let json = serde_json::to_value(&error).unwrap();
+            assert_eq!(json["code"], code);
+        }
+    }
+
+    #[test]
+    fn only_internal_failures_are_server_errors() {
+        let internal = ["STORAGE_ERROR", "UNBALANCED_JOURNAL_ENTRY", "SMART_CONTRACT_ERROR", "CHAIN_ID_MISMATCH", "TRANSACTION_NOT_CONFIRMED"];
+        for (error, _, code) in table() {
+            let is_internal = error.status() == StatusCode::INTERNAL_SERVER_ERROR;
+            assert_eq!(is_internal, internal.contains(&code), "{}", code);
+        }
+    }
+
+    #[test]
+    fn details_are_serialized_under_details() {
+        let json = serde_json::to_value(OpenBankError::AccountInactive { account_id: s("a") }).unwrap();
+        assert_eq!(json["details"]["account_id"], "a");
+    }
 }
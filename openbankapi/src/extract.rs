@@ -0,0 +1,38 @@
+use axum::extract::{
+    rejection::{JsonRejection, PathRejection, QueryRejection},
+    FromRequest, FromRequestParts,
+};
+use crate::error::OpenBankError;
+
+// axum's Json, Path and Query extractors, but a malformed request is rejected
+// with InvalidRequest in the usual error envelope instead of a plain text body
+
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(OpenBankError))]
+pub struct ApiJson<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(OpenBankError))]
+pub struct ApiPath<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(OpenBankError))]
+pub struct ApiQuery<T>(pub T);
+
+impl From<JsonRejection> for OpenBankError {
+    fn from(rejection: JsonRejection) -> Self {
+        OpenBankError::InvalidRequest { message: rejection.body_text() }
+    }
+}
+
+impl From<PathRejection> for OpenBankError {
+    fn from(rejection: PathRejection) -> Self {
+        OpenBankError::InvalidRequest { message: rejection.body_text() }
+    }
+}
+
+impl From<QueryRejection> for OpenBankError {
+    fn from(rejection: QueryRejection) -> Self {
+        OpenBankError::InvalidRequest { message: rejection.body_text() }
+    }
+}
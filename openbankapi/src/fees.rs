@@ -0,0 +1,247 @@
+use serde::Deserialize;
+use crate::error::OpenBankError;
+use crate::money::Money;
+use crate::types::{FeeKind, FeeLine};
+
+// One line of the fee schedule. A rule charges `fixed` plus `bps` of the amount,
+// or the `bps` of the first tier whose `up_to` covers the amount, kept within
+// `min` and `max`. Amounts are decimals in the currency of the account paying.
+// Rules without a currency or chain apply to all of them.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FeeRule {
+    kind: FeeKind,
+    currency: Option<String>,
+    chain_id: Option<u64>,
+    fixed: Option<String>,
+    #[serde(default)]
+    bps: u32,
+    #[serde(default)]
+    tiers: Vec<FeeTier>,
+    min: Option<String>,
+    max: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FeeTier {
+    up_to: Option<String>, // Inclusive; the last tier must leave it out
+    bps: u32,
+}
+
+impl FeeRule {
+    fn matches(&self, kind: FeeKind, currency: &str, chain_id: Option<u64>) -> bool {
+        self.kind == kind
+            && self.currency.as_deref().is_none_or(|c| c == currency)
+            && self.chain_id.is_none_or(|c| Some(c) == chain_id)
+    }
+
+    // Rules naming a currency beat rules naming a chain, which beat catch-alls
+    fn specificity(&self) -> u8 {
+        u8::from(self.currency.is_some()) * 2 + u8::from(self.chain_id.is_some())
+    }
+
+    fn fee(&self, amount: &Money) -> Result<Money, OpenBankError> {
+        let currency = amount.currency();
+        let mut bps = self.bps;
+        for tier in &self.tiers {
+            bps = tier.bps;
+            match &tier.up_to {
+                Some(up_to) if Money::parse(up_to, currency)?.minor_units() < amount.minor_units() => continue,
+                _ => break,
+            }
+        }
+
+        // Percentages round up to the next minor unit
+        let out_of_range = || OpenBankError::AmountOutOfRange { amount: amount.to_string() };
+        let scaled = i128::from(amount.minor_units()) * i128::from(bps);
+        let percentage = i64::try_from((scaled + 9_999) / 10_000).map_err(|_| out_of_range())?;
+        let mut fee = Money::from_minor_units(percentage, currency)?;
+        if let Some(fixed) = &self.fixed {
+            fee = fee.checked_add(&Money::parse(fixed, currency)?)?;
+        }
+        if let Some(min) = &self.min {
+            let min = Money::parse(min, currency)?;
+            if fee.minor_units() < min.minor_units() {
+                fee = min;
+            }
+        }
+        if let Some(max) = &self.max {
+            let max = Money::parse(max, currency)?;
+            if fee.minor_units() > max.minor_units() {
+                fee = max;
+            }
+        }
+        Ok(fee)
+    }
+
+    fn validate(&self) -> Result<(), OpenBankError> {
+        // Fixed amounts are checked against the rule's currency, or any 2-decimal fiat
+        let currency = self.currency.as_deref().unwrap_or("USD");
+        for amount in [&self.fixed, &self.min, &self.max].into_iter().flatten() {
+            Money::parse(amount, currency)?;
+        }
+        for tier in &self.tiers {
+            if let Some(up_to) = &tier.up_to {
+                Money::parse(up_to, currency)?;
+            }
+        }
+        if self.tiers.last().is_some_and(|tier| tier.up_to.is_some()) {
+            return Err(config_error("the last fee tier must not have an up_to".to_string()));
+        }
+        if std::iter::once(self.bps).chain(self.tiers.iter().map(|tier| tier.bps)).any(|bps| bps >= 10_000) {
+            return Err(config_error("fee bps must be below 10000".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Fees charged on conversions and withdrawals, read from FEE_SCHEDULE_FILE or
+/// inline from FEE_SCHEDULE, both a JSON list of rules such as
+/// `[{"kind": "withdrawal", "currency": "EUR", "fixed": "0.50", "bps": 25, "max": "20.00"}]`.
+/// Without either nothing is charged. For each kind the most specific matching rule applies.
+#[derive(Debug, Default)]
+pub struct FeeSchedule {
+    rules: Vec<FeeRule>,
+}
+
+impl FeeSchedule {
+    pub fn from_env() -> Result<Self, OpenBankError> {
+        let schedule = match std::env::var("FEE_SCHEDULE_FILE") {
+            Ok(path) => std::fs::read_to_string(&path)
+                .map_err(|e| config_error(format!("Failed to read FEE_SCHEDULE_FILE {}: {}", path, e)))?,
+            Err(_) => match std::env::var("FEE_SCHEDULE") {
+                Ok(schedule) => schedule,
+                Err(_) => return Ok(Self::default()),
+            },
+        };
+        Self::parse(&schedule)
+    }
+
+    // A schedule from its JSON list of rules, checked like one from the environment
+    pub fn parse(schedule: &str) -> Result<Self, OpenBankError> {
+        let rules: Vec<FeeRule> = serde_json::from_str(schedule)
+            .map_err(|e| config_error(format!("Invalid fee schedule: {}", e)))?;
+        for rule in &rules {
+            rule.validate()?;
+        }
+        Ok(Self { rules })
+    }
+
+    pub fn rule_count(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// The fee of `kind` on `amount`, in its currency, or None when nothing is charged.
+    pub fn fee(&self, kind: FeeKind, amount: &Money, chain_id: Option<u64>) -> Result<Option<FeeLine>, OpenBankError> {
+        let mut best: Option<&FeeRule> = None;
+        for rule in self.rules.iter().filter(|rule| rule.matches(kind, amount.currency(), chain_id)) {
+            if best.is_none_or(|best| rule.specificity() > best.specificity()) {
+                best = Some(rule);
+            }
+        }
+        let Some(rule) = best else {
+            return Ok(None);
+        };
+
+        let fee = rule.fee(amount)?;
+        Ok(fee.is_positive().then_some(FeeLine { kind, amount: fee }))
+    }
+}
+
+/// What is left of `amount` once `fees` are taken out. Fails unless something is.
+pub fn net_of_fees(amount: &Money, fees: &[FeeLine]) -> Result<Money, OpenBankError> {
+    let mut net = amount.clone();
+    for line in fees {
+        net = net.checked_sub(&line.amount)?;
+    }
+    if !net.is_positive() {
+        return Err(OpenBankError::AmountBelowFees {
+            amount: amount.to_string(),
+            fees: amount.checked_sub(&net)?.to_string(),
+        });
+    }
+    Ok(net)
+}
+
+fn config_error(message: String) -> OpenBankError {
+    OpenBankError::InvalidRequest { message }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(rules: &str) -> FeeSchedule {
+        let rules: Vec<FeeRule> = serde_json::from_str(rules).unwrap();
+        for rule in &rules {
+            rule.validate().unwrap();
+        }
+        FeeSchedule { rules }
+    }
+
+    fn money(amount: &str, currency: &str) -> Money {
+        Money::parse(amount, currency).unwrap()
+    }
+
+    fn fee(schedule: &FeeSchedule, kind: FeeKind, amount: &Money, chain_id: Option<u64>) -> Option<String> {
+        schedule.fee(kind, amount, chain_id).unwrap().map(|line| line.amount.to_string())
+    }
+
+    #[test]
+    fn most_specific_rule_wins() {
+        let schedule = schedule(
+            r#"[
+                {"kind": "withdrawal", "fixed": "1.00"},
+                {"kind": "withdrawal", "chain_id": 137, "fixed": "0.20"},
+                {"kind": "withdrawal", "currency": "EUR", "fixed": "0.50"},
+                {"kind": "conversion", "bps": 100}
+            ]"#,
+        );
+        let usd = money("10", "USD");
+        assert_eq!(fee(&schedule, FeeKind::Withdrawal, &usd, Some(1)).as_deref(), Some("1.00 USD"));
+        assert_eq!(fee(&schedule, FeeKind::Withdrawal, &usd, Some(137)).as_deref(), Some("0.20 USD"));
+        // A currency rule beats a chain rule
+        let eur = money("10", "EUR");
+        assert_eq!(fee(&schedule, FeeKind::Withdrawal, &eur, Some(137)).as_deref(), Some("0.50 EUR"));
+        assert_eq!(fee(&schedule, FeeKind::Conversion, &eur, None).as_deref(), Some("0.10 EUR"));
+        assert_eq!(fee(&FeeSchedule::default(), FeeKind::Conversion, &eur, None), None);
+    }
+
+    #[test]
+    fn percentages_round_up_within_min_and_max() {
+        let schedule = schedule(r#"[{"kind": "withdrawal", "fixed": "0.10", "bps": 25, "min": "0.50", "max": "20.00"}]"#);
+        // 0.25% of 1.01 is 0.002525, rounded up to 0.01, plus 0.10, raised to the minimum
+        assert_eq!(fee(&schedule, FeeKind::Withdrawal, &money("1.01", "USD"), None).as_deref(), Some("0.50 USD"));
+        // 0.25% of 1000.01 is 2.500025, rounded up to 2.51, plus 0.10
+        assert_eq!(fee(&schedule, FeeKind::Withdrawal, &money("1000.01", "USD"), None).as_deref(), Some("2.61 USD"));
+        assert_eq!(fee(&schedule, FeeKind::Withdrawal, &money("100000", "USD"), None).as_deref(), Some("20.00 USD"));
+    }
+
+    #[test]
+    fn tiers_pick_the_first_covering_bps() {
+        let schedule = schedule(
+            r#"[{"kind": "conversion", "tiers": [{"up_to": "100.00", "bps": 100}, {"up_to": "1000.00", "bps": 50}, {"bps": 10}]}]"#,
+        );
+        let fee = |amount: &str| fee(&schedule, FeeKind::Conversion, &money(amount, "USD"), None);
+        assert_eq!(fee("100").as_deref(), Some("1.00 USD"));
+        assert_eq!(fee("200").as_deref(), Some("1.00 USD"));
+        assert_eq!(fee("2000").as_deref(), Some("2.00 USD"));
+    }
+
+    #[test]
+    fn invalid_rules_are_rejected() {
+        let rules: Vec<FeeRule> = serde_json::from_str(r#"[{"kind": "conversion", "tiers": [{"up_to": "1.00", "bps": 10}]}]"#).unwrap();
+        assert!(rules[0].validate().is_err());
+        let rules: Vec<FeeRule> = serde_json::from_str(r#"[{"kind": "conversion", "bps": 10000}]"#).unwrap();
+        assert!(rules[0].validate().is_err());
+        assert!(serde_json::from_str::<Vec<FeeRule>>(r#"[{"kind": "conversion", "percent": 1}]"#).is_err());
+    }
+
+    #[test]
+    fn net_of_fees_must_leave_something() {
+        let fees = [FeeLine { kind: FeeKind::Withdrawal, amount: money("1.00", "USD") }];
+        assert_eq!(net_of_fees(&money("10", "USD"), &fees).unwrap(), money("9", "USD"));
+        assert!(matches!(net_of_fees(&money("1", "USD"), &fees), Err(OpenBankError::AmountBelowFees { .. })));
+    }
+}
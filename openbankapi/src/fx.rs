@@ -0,0 +1,208 @@
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use std::collections::HashMap;
+use uuid::Uuid;
+use crate::error::OpenBankError;
+use crate::fees;
+use crate::money::{currency_decimals, Money, Rate, USDT};
+use crate::types::{Account, FeeLine, Quote, QuotePurpose};
+
+const DEFAULT_SPREAD_BPS: u32 = 50;
+const DEFAULT_QUOTE_TTL_SECS: i64 = 60;
+
+/// Source of mid-market exchange rates.
+#[async_trait]
+pub trait RateProvider: Send + Sync {
+    // Recorded on every quote priced with it
+    fn name(&self) -> &str;
+    /// Units of `to` per unit of `from`, or `RateUnavailable` for unknown pairs.
+    async fn mid_rate(&self, from: &str, to: &str) -> Result<Rate, OpenBankError>;
+}
+
+// Fixed rates for offline use, read once from FX_RATES_FILE (a JSON object such
+// as {"EUR/USDT": "1.08"}) or else FX_RATES ("EUR/USDT=1.08,GBP/USDT=1.27").
+// USD/USDT is 1 unless configured otherwise. Inverse pairs are not derived,
+// since they rarely have an exact decimal.
+pub struct StaticRateProvider {
+    name: String,
+    rates: HashMap<(String, String), Rate>,
+}
+
+impl StaticRateProvider {
+    pub fn from_env() -> Result<Self, OpenBankError> {
+        let (name, pairs) = match std::env::var("FX_RATES_FILE") {
+            Ok(path) => {
+                let pairs: HashMap<String, String> = std::fs::read_to_string(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|contents| serde_json::from_str(&contents).map_err(|e| e.to_string()))
+                    .map_err(|e| config_error(format!("Failed to read FX_RATES_FILE {}: {}", path, e)))?;
+                (format!("file:{}", path), pairs.into_iter().collect())
+            }
+            Err(_) => {
+                let pairs = std::env::var("FX_RATES")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|entry| !entry.is_empty())
+                    .map(|entry| {
+                        entry
+                            .split_once('=')
+                            .map(|(pair, rate)| (pair.trim().to_string(), rate.trim().to_string()))
+                            .ok_or_else(|| config_error(format!("Invalid FX_RATES entry: {}", entry)))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                ("static".to_string(), pairs)
+            }
+        };
+
+        let mut rates = HashMap::new();
+        rates.insert(("USD".to_string(), USDT.to_string()), Rate::parse("1")?);
+        for (pair, rate) in pairs {
+            let (from, to) = pair
+                .split_once('/')
+                .ok_or_else(|| config_error(format!("Invalid currency pair: {}", pair)))?;
+            for currency in [from, to] {
+                if currency_decimals(currency).is_none() {
+                    return Err(OpenBankError::UnsupportedCurrency { currency: currency.to_string() });
+                }
+            }
+            rates.insert((from.to_string(), to.to_string()), Rate::parse(&rate)?);
+        }
+        Ok(Self { name, rates })
+    }
+}
+
+#[async_trait]
+impl RateProvider for StaticRateProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn mid_rate(&self, from: &str, to: &str) -> Result<Rate, OpenBankError> {
+        self.rates
+            .get(&(from.to_string(), to.to_string()))
+            .cloned()
+            .ok_or_else(|| OpenBankError::RateUnavailable { from: from.to_string(), to: to.to_string() })
+    }
+}
+
+fn config_error(message: String) -> OpenBankError {
+    OpenBankError::InvalidRequest { message }
+}
+
+// Prices quotes off a rate provider. The spread taken from the mid rate comes
+// from FX_SPREAD_BPS and how long a quote stays valid from FX_QUOTE_TTL_SECS.
+pub struct Quoter {
+    provider: Box<dyn RateProvider>,
+    spread_bps: u32,
+    ttl: Duration,
+}
+
+impl Quoter {
+    pub fn from_env() -> Result<Self, OpenBankError> {
+        let spread_bps = std::env::var("FX_SPREAD_BPS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_SPREAD_BPS);
+        if spread_bps >= 10_000 {
+            return Err(config_error(format!("FX_SPREAD_BPS must be below 10000, got {}", spread_bps)));
+        }
+        let ttl = std::env::var("FX_QUOTE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_QUOTE_TTL_SECS);
+
+        Ok(Self {
+            provider: Box::new(StaticRateProvider::from_env()?),
+            spread_bps,
+            ttl: Duration::seconds(ttl),
+        })
+    }
+
+    pub fn provider_name(&self) -> &str {
+        self.provider.name()
+    }
+
+    pub async fn mid_rate(&self, from: &str, to: &str) -> Result<Rate, OpenBankError> {
+        self.provider.mid_rate(from, to).await
+    }
+
+    /// Locks a rate, less the spread, for converting `amount` out of `account`
+    /// into `currency` once `fees` are taken out. The quote still has to be
+    /// stored to be usable.
+    pub async fn quote(
+        &self,
+        account: &Account,
+        amount: &Money,
+        currency: &str,
+        purpose: QuotePurpose,
+        chain_id: Option<u64>,
+        fees: Vec<FeeLine>,
+    ) -> Result<Quote, OpenBankError> {
+        if account.currency == currency {
+            return Err(OpenBankError::InvalidRequest {
+                message: format!("Account {} is already in {}", account.id, currency),
+            });
+        }
+        let mid_rate = self.provider.mid_rate(&account.currency, currency).await?;
+        let rate = mid_rate.less_bps(self.spread_bps)?;
+        let converted_amount = fees::net_of_fees(amount, &fees)?.convert(&rate, currency)?;
+        if !converted_amount.is_positive() {
+            return Err(OpenBankError::InvalidAmount { amount: amount.to_string() });
+        }
+
+        let now = Utc::now();
+        Ok(Quote {
+            id: Uuid::new_v4().to_string(),
+            user_id: account.user_id.clone(),
+            account_id: account.id.clone(),
+            amount: amount.clone(),
+            fees,
+            converted_amount,
+            mid_rate,
+            spread_bps: self.spread_bps,
+            rate,
+            provider: self.provider.name().to_string(),
+            purpose,
+            chain_id,
+            created_at: now,
+            expires_at: now + self.ttl,
+            used_by: None,
+        })
+    }
+}
+
+/// Checks that an unspent, unexpired quote for `purpose` covers converting
+/// exactly `amount` out of `account_id` into `currency`.
+pub fn check_quote(
+    quote: &Quote,
+    purpose: QuotePurpose,
+    account_id: &str,
+    amount: &Money,
+    currency: &str,
+) -> Result<(), OpenBankError> {
+    let mismatch = |reason: String| OpenBankError::QuoteMismatch { quote_id: quote.id.clone(), reason };
+    if quote.used_by.is_some() {
+        return Err(OpenBankError::QuoteAlreadyUsed { quote_id: quote.id.clone() });
+    }
+    if quote.expires_at <= Utc::now() {
+        return Err(OpenBankError::QuoteExpired { quote_id: quote.id.clone() });
+    }
+    if quote.purpose != purpose {
+        let quoted_for = match quote.purpose {
+            QuotePurpose::Withdrawal => "quoted for a withdrawal",
+            QuotePurpose::Transfer => "quoted for a transfer",
+        };
+        return Err(mismatch(quoted_for.to_string()));
+    }
+    if quote.account_id != account_id {
+        return Err(mismatch(format!("quoted for account {}", quote.account_id)));
+    }
+    if quote.amount != *amount {
+        return Err(mismatch(format!("quoted for {}", quote.amount)));
+    }
+    if quote.converted_amount.currency() != currency {
+        return Err(mismatch(format!("quoted into {}", quote.converted_amount.currency())));
+    }
+    Ok(())
+}
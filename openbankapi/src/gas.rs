@@ -0,0 +1,316 @@
+use ethers::core::types::{
+    transaction::eip2718::TypedTransaction, Eip1559TransactionRequest, TransactionRequest, U256,
+};
+use ethers::utils::{parse_units, ParseUnits};
+use crate::error::OpenBankError;
+
+const DEFAULT_GAS_LIMIT_MULTIPLIER_PERCENT: u64 = 120;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GasMode {
+    Eip1559,
+    Legacy,
+}
+
+// How owner transactions are priced. Unset caps mean the node's estimate is used as is.
+// Env: GAS_MODE (eip1559 | legacy), MAX_FEE_PER_GAS_GWEI, MAX_PRIORITY_FEE_PER_GAS_GWEI,
+// GAS_PRICE_GWEI, GAS_LIMIT_MULTIPLIER (1 to 10, at most two decimals, e.g. 1.2) and MAX_TX_FEE_ETH.
+#[derive(Debug, Clone)]
+pub struct GasPolicy {
+    pub mode: GasMode,
+    pub max_fee_per_gas: Option<U256>, // EIP-1559 cap, wei
+    pub max_priority_fee_per_gas: Option<U256>, // EIP-1559 cap, wei
+    pub gas_price: Option<U256>, // Fixed legacy gas price, wei
+    pub gas_limit_multiplier_percent: u64, // Applied to the node's gas estimate
+    pub max_tx_fee: Option<U256>, // Hard ceiling on gas limit * price for one transaction, wei
+}
+
+impl Default for GasPolicy {
+    fn default() -> Self {
+        Self {
+            mode: GasMode::Legacy,
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            gas_price: None,
+            gas_limit_multiplier_percent: DEFAULT_GAS_LIMIT_MULTIPLIER_PERCENT,
+            max_tx_fee: None,
+        }
+    }
+}
+
+impl GasPolicy {
+    pub fn from_env() -> Result<Self, OpenBankError> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    // Same as from_env, with the variables looked up through `var`
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, OpenBankError> {
+        let mode = match var("GAS_MODE").unwrap_or_else(|| "legacy".to_string()).to_lowercase().as_str() {
+            "eip1559" => GasMode::Eip1559,
+            "legacy" => GasMode::Legacy,
+            other => return Err(invalid("GAS_MODE", other)),
+        };
+
+        let gas_limit_multiplier_percent = match var("GAS_LIMIT_MULTIPLIER") {
+            Some(value) => match multiplier_percent(&value) {
+                Some(percent) if (100..=1000).contains(&percent) => percent,
+                _ => return Err(invalid("GAS_LIMIT_MULTIPLIER", &value)),
+            },
+            None => DEFAULT_GAS_LIMIT_MULTIPLIER_PERCENT,
+        };
+        // Negative amounts parse as signed, which is never a valid price
+        let units = |name: &str, units: &str| match var(name) {
+            Some(value) => match parse_units(&value, units) {
+                Ok(ParseUnits::U256(amount)) => Ok(Some(amount)),
+                _ => Err(invalid(name, &value)),
+            },
+            None => Ok(None),
+        };
+
+        Ok(Self {
+            mode,
+            max_fee_per_gas: units("MAX_FEE_PER_GAS_GWEI", "gwei")?,
+            max_priority_fee_per_gas: units("MAX_PRIORITY_FEE_PER_GAS_GWEI", "gwei")?,
+            gas_price: units("GAS_PRICE_GWEI", "gwei")?,
+            gas_limit_multiplier_percent,
+            max_tx_fee: units("MAX_TX_FEE_ETH", "ether")?,
+        })
+    }
+
+    // Converts the transaction to the configured type and sets the fixed legacy
+    // price, before the node fills in whatever is still missing
+    pub fn prepare(&self, tx: TypedTransaction) -> TypedTransaction {
+        let mut tx = match (self.mode, tx) {
+            (GasMode::Eip1559, TypedTransaction::Legacy(inner)) => Eip1559TransactionRequest {
+                from: inner.from,
+                to: inner.to,
+                gas: inner.gas,
+                value: inner.value,
+                data: inner.data,
+                nonce: inner.nonce,
+                chain_id: inner.chain_id,
+                ..Default::default()
+            }
+            .into(),
+            (GasMode::Legacy, TypedTransaction::Eip1559(inner)) => TransactionRequest {
+                from: inner.from,
+                to: inner.to,
+                gas: inner.gas,
+                value: inner.value,
+                data: inner.data,
+                nonce: inner.nonce,
+                chain_id: inner.chain_id,
+                ..Default::default()
+            }
+            .into(),
+            (_, tx) => tx,
+        };
+        if let (GasMode::Legacy, Some(price)) = (self.mode, self.gas_price) {
+            tx.set_gas_price(price);
+        }
+        tx
+    }
+
+    // Pads the estimated gas limit and clamps estimated fees to the caps
+    pub fn apply(&self, tx: &mut TypedTransaction) {
+        if let Some(gas) = tx.gas().copied() {
+            tx.set_gas(gas * self.gas_limit_multiplier_percent / 100);
+        }
+        if let TypedTransaction::Eip1559(inner) = tx {
+            if let Some(cap) = self.max_fee_per_gas {
+                inner.max_fee_per_gas = inner.max_fee_per_gas.map(|fee| fee.min(cap));
+            }
+            if let Some(cap) = self.max_priority_fee_per_gas {
+                inner.max_priority_fee_per_gas = inner.max_priority_fee_per_gas.map(|fee| fee.min(cap));
+            }
+            // The tip can never be above the total fee
+            inner.max_priority_fee_per_gas = inner.max_priority_fee_per_gas.min(inner.max_fee_per_gas);
+        }
+    }
+
+    // Worst-case fee of the transaction against the hard ceiling
+    pub fn check_ceiling(&self, tx: &TypedTransaction) -> Result<(), OpenBankError> {
+        let Some(ceiling) = self.max_tx_fee else {
+            return Ok(());
+        };
+        let price = match tx {
+            TypedTransaction::Eip1559(inner) => inner.max_fee_per_gas,
+            _ => tx.gas_price(),
+        }
+        .unwrap_or_default();
+        let fee = tx.gas().copied().unwrap_or_default().saturating_mul(price);
+
+        if fee > ceiling {
+            return Err(OpenBankError::GasCeilingExceeded {
+                fee: fee.to_string(),
+                ceiling: ceiling.to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+// A decimal multiplier such as "1.2" as a whole percentage, parsed exactly.
+// More than two fractional digits can't be represented and are rejected.
+fn multiplier_percent(value: &str) -> Option<u64> {
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if whole.is_empty() || !whole.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    if value.contains('.') && fraction.is_empty() {
+        return None;
+    }
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) || fraction.len() > 2 {
+        return None;
+    }
+
+    let mut percent: u64 = 0;
+    let padded = fraction.chars().chain(std::iter::repeat('0')).take(2);
+    for c in whole.chars().chain(padded) {
+        let digit = c.to_digit(10)? as u64;
+        percent = percent.checked_mul(10)?.checked_add(digit)?;
+    }
+    Some(percent)
+}
+
+fn invalid(name: &str, value: &str) -> OpenBankError {
+    OpenBankError::SmartContractError {
+        message: format!("Invalid {} in .env file: {}", name, value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn gwei(amount: u64) -> U256 {
+        U256::from(amount) * U256::exp10(9)
+    }
+
+    fn from_vars(vars: &[(&str, &str)]) -> Result<GasPolicy, OpenBankError> {
+        let vars: HashMap<_, _> = vars.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        GasPolicy::from_vars(|name| vars.get(name).cloned())
+    }
+
+    fn eip1559(gas: u64, max_fee: U256, max_priority_fee: U256) -> TypedTransaction {
+        Eip1559TransactionRequest::new()
+            .gas(gas)
+            .max_fee_per_gas(max_fee)
+            .max_priority_fee_per_gas(max_priority_fee)
+            .into()
+    }
+
+    #[test]
+    fn multipliers_parse_exactly_to_percent() {
+        assert_eq!(multiplier_percent("1.2"), Some(120));
+        assert_eq!(multiplier_percent("1.25"), Some(125));
+        assert_eq!(multiplier_percent("2"), Some(200));
+        for value in ["1.205", "1.", ".5", "-1.2", "1e2", "NaN", "", "1,2"] {
+            assert_eq!(multiplier_percent(value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn from_env_reads_the_policy() {
+        let policy = from_vars(&[]).unwrap();
+        assert_eq!(policy.mode, GasMode::Legacy);
+        assert_eq!(policy.gas_limit_multiplier_percent, DEFAULT_GAS_LIMIT_MULTIPLIER_PERCENT);
+        assert_eq!((policy.gas_price, policy.max_tx_fee), (None, None));
+
+        let policy = from_vars(&[
+            ("GAS_MODE", "EIP1559"),
+            ("GAS_LIMIT_MULTIPLIER", "1.5"),
+            ("MAX_FEE_PER_GAS_GWEI", "30"),
+            ("MAX_PRIORITY_FEE_PER_GAS_GWEI", "1.5"),
+            ("MAX_TX_FEE_ETH", "0.01"),
+        ])
+        .unwrap();
+        assert_eq!(policy.mode, GasMode::Eip1559);
+        assert_eq!(policy.gas_limit_multiplier_percent, 150);
+        assert_eq!(policy.max_fee_per_gas, Some(gwei(30)));
+        assert_eq!(policy.max_priority_fee_per_gas, Some(U256::from(1_500_000_000u64)));
+        assert_eq!(policy.max_tx_fee, Some(U256::exp10(16)));
+    }
+
+    #[test]
+    fn from_env_rejects_bad_values() {
+        let bad = [
+            ("GAS_MODE", "eip4844"),
+            ("GAS_LIMIT_MULTIPLIER", "0.99"),
+            ("GAS_LIMIT_MULTIPLIER", "10.01"),
+            ("GAS_LIMIT_MULTIPLIER", "1.205"),
+            ("GAS_LIMIT_MULTIPLIER", "abc"),
+            ("GAS_PRICE_GWEI", "abc"),
+            ("GAS_PRICE_GWEI", "-1"),
+            ("MAX_FEE_PER_GAS_GWEI", "30 gwei"),
+            ("MAX_TX_FEE_ETH", "-0.1"),
+        ];
+        for (name, value) in bad {
+            assert!(matches!(
+                from_vars(&[(name, value)]),
+                Err(OpenBankError::SmartContractError { message }) if message.contains(name)
+            ), "{}={}", name, value);
+        }
+        assert_eq!(from_vars(&[("GAS_LIMIT_MULTIPLIER", "10")]).unwrap().gas_limit_multiplier_percent, 1000);
+    }
+
+    #[test]
+    fn prepare_converts_to_the_configured_type() {
+        let legacy: TypedTransaction = TransactionRequest::new().gas(21_000).nonce(7).into();
+        let policy = GasPolicy { mode: GasMode::Eip1559, gas_price: Some(gwei(5)), ..Default::default() };
+        let tx = policy.prepare(legacy.clone());
+        assert!(matches!(tx, TypedTransaction::Eip1559(_)));
+        assert_eq!((tx.gas(), tx.nonce()), (Some(&U256::from(21_000)), Some(&U256::from(7))));
+        // The fixed price is only for legacy transactions
+        assert_eq!(tx.gas_price(), None);
+
+        let policy = GasPolicy { gas_price: Some(gwei(5)), ..Default::default() };
+        let tx = policy.prepare(eip1559(21_000, gwei(50), gwei(2)));
+        assert!(matches!(tx, TypedTransaction::Legacy(_)));
+        assert_eq!(tx.gas_price(), Some(gwei(5)));
+        assert_eq!(GasPolicy::default().prepare(legacy).gas_price(), None);
+    }
+
+    #[test]
+    fn apply_pads_the_gas_limit_and_caps_fees() {
+        let policy = GasPolicy {
+            mode: GasMode::Eip1559,
+            max_fee_per_gas: Some(gwei(30)),
+            max_priority_fee_per_gas: Some(gwei(40)),
+            ..Default::default()
+        };
+        let mut tx = eip1559(100_000, gwei(50), gwei(45));
+        policy.apply(&mut tx);
+        let TypedTransaction::Eip1559(inner) = &tx else { unreachable!() };
+        assert_eq!(inner.gas, Some(U256::from(120_000)));
+        assert_eq!(inner.max_fee_per_gas, Some(gwei(30)));
+        // Capped at the total fee, which is below its own cap
+        assert_eq!(inner.max_priority_fee_per_gas, Some(gwei(30)));
+
+        // Estimates under the caps are kept
+        let mut tx = eip1559(100_000, gwei(20), gwei(1));
+        policy.apply(&mut tx);
+        let TypedTransaction::Eip1559(inner) = &tx else { unreachable!() };
+        assert_eq!((inner.max_fee_per_gas, inner.max_priority_fee_per_gas), (Some(gwei(20)), Some(gwei(1))));
+    }
+
+    #[test]
+    fn check_ceiling_bounds_the_worst_case_fee() {
+        let tx = eip1559(100_000, gwei(50), gwei(2));
+        assert!(GasPolicy::default().check_ceiling(&tx).is_ok());
+
+        // 100k gas at 50 gwei is 0.005 ETH
+        let ceiling = |fee: U256| GasPolicy { max_tx_fee: Some(fee), ..Default::default() };
+        let fee = gwei(5_000_000);
+        assert!(ceiling(fee).check_ceiling(&tx).is_ok());
+        assert!(matches!(
+            ceiling(fee - 1).check_ceiling(&tx),
+            Err(OpenBankError::GasCeilingExceeded { fee: charged, .. }) if charged == fee.to_string()
+        ));
+
+        let legacy: TypedTransaction = TransactionRequest::new().gas(100_000).gas_price(gwei(50)).into();
+        assert!(ceiling(fee).check_ceiling(&legacy).is_ok());
+        assert!(ceiling(fee - 1).check_ceiling(&legacy).is_err());
+    }
+}
@@ -0,0 +1,318 @@
+use axum::{http::{HeaderMap, StatusCode}, response::Json};
+use ethers::utils::keccak256;
+use serde::{de::DeserializeOwned, Serialize};
+use std::future::Future;
+use crate::auth::{Principal, Role};
+use crate::error::OpenBankError;
+use crate::storage::Storage;
+use crate::types::{ApiResponse, IdempotencyCompletion, IdempotencyRecord};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+const MAX_KEY_LENGTH: usize = 255;
+const DEFAULT_RESERVATION_TTL_SECS: i64 = 300;
+
+type HandlerResult<T> = Result<(StatusCode, Json<ApiResponse<T>>), OpenBankError>;
+
+// Reads the optional Idempotency-Key header
+pub fn key_from_headers(headers: &HeaderMap) -> Result<Option<String>, OpenBankError> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
+    };
+
+    match value.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => Ok(Some(key.to_string())),
+        _ => Err(OpenBankError::InvalidIdempotencyKey),
+    }
+}
+
+// How long a key may stay reserved by a request that never finished, e.g.
+// because the process crashed, before a retry takes it over. Read from
+// IDEMPOTENCY_RESERVATION_TTL_SECS. A request still running when its key is
+// taken over can no longer complete it, so its write is refused.
+pub fn reservation_ttl() -> chrono::Duration {
+    let ttl = std::env::var("IDEMPOTENCY_RESERVATION_TTL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_RESERVATION_TTL_SECS);
+    chrono::Duration::seconds(ttl)
+}
+
+// Keys are picked by clients, so each caller gets keys of its own: a key sent
+// by one user can never replay another user's response
+pub fn scoped_key(principal: &Principal, key: Option<String>) -> Option<String> {
+    key.map(|key| match (principal.role, &principal.user_id) {
+        (Role::User, Some(user_id)) => format!("user:{}:{}", user_id, key),
+        (Role::Operator, _) => format!("operator:{}", key),
+        _ => format!("auditor:{}", key),
+    })
+}
+
+// Hash identifying a request: the endpoint name, its path parameters and body
+pub fn fingerprint<T: Serialize>(endpoint: &str, request: &T) -> String {
+    let body = serde_json::to_vec(&(endpoint, request)).unwrap_or_default();
+    hex::encode(keccak256(body))
+}
+
+// A key reserved for the running request. The handler completes it through the
+// storage write that makes its side effect, so the response is stored in the
+// same step and a reservation left in progress means nothing was written.
+#[derive(Debug, Clone)]
+pub struct Reservation {
+    key: String,
+    reserved_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl Reservation {
+    pub fn completion(&self, status: StatusCode) -> IdempotencyCompletion {
+        IdempotencyCompletion { key: self.key.clone(), reserved_at: self.reserved_at, status: status.as_u16() }
+    }
+}
+
+/// Runs `handler` at most once per idempotency key. The handler is given the
+/// reservation to complete along with its write; that response is replayed
+/// verbatim on retries with the same request, and reusing the key for a
+/// different request is rejected. Failed attempts free the key again so the
+/// client can retry them, and so does a reservation left in progress for
+/// longer than `reservation_ttl`.
+pub async fn run_once<T, F, Fut>(
+    storage: &dyn Storage,
+    key: Option<String>,
+    fingerprint: String,
+    handler: F,
+) -> HandlerResult<T>
+where
+    T: Serialize + DeserializeOwned,
+    F: FnOnce(Option<Reservation>) -> Fut,
+    Fut: Future<Output = HandlerResult<T>>,
+{
+    let Some(key) = key else {
+        return handler(None).await;
+    };
+
+    let now = chrono::Utc::now();
+    let record = IdempotencyRecord {
+        key: key.clone(),
+        fingerprint,
+        response_status: None,
+        response_body: None,
+        created_at: now,
+    };
+
+    if let Some(existing) = storage.reserve_idempotency_key(&record, now - reservation_ttl())? {
+        if existing.fingerprint != record.fingerprint {
+            return Err(OpenBankError::IdempotencyKeyReused { key });
+        }
+
+        let (Some(status), Some(body)) = (existing.response_status, existing.response_body) else {
+            return Err(OpenBankError::IdempotencyRequestInProgress { key });
+        };
+        let data: T = serde_json::from_str(&body).map_err(|e| OpenBankError::StorageError {
+            message: format!("Failed to decode stored response for idempotency key {}: {}", key, e),
+        })?;
+        let status = StatusCode::from_u16(status).unwrap_or(StatusCode::OK);
+
+        return Ok((status, Json(ApiResponse {
+            success: true,
+            data: Some(data),
+            error: None,
+        })));
+    }
+
+    let result = handler(Some(Reservation { key: key.clone(), reserved_at: now })).await;
+    // A key the handler completed stays put. One left reserved is only taken
+    // over once stale, which is safe as its handler wrote nothing.
+    if result.is_err()
+        && let Err(e) = storage.remove_idempotency_key(&key, now)
+    {
+        println!("Warning: Could not release idempotency key {}: {:?}", key, e);
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use rusqlite::Connection;
+    use crate::storage::{MemoryStorage, SqliteStorage};
+
+    fn backends() -> Vec<(&'static str, Box<dyn Storage>)> {
+        let sqlite = SqliteStorage::with_connection(Connection::open_in_memory().unwrap()).unwrap();
+        vec![("memory", Box::new(MemoryStorage::new())), ("sqlite", Box::new(sqlite))]
+    }
+
+    // Runs a handler answering `value`, counting how often it actually ran. It
+    // completes its key the way a write would.
+    async fn run(storage: &dyn Storage, key: &str, fingerprint: &str, value: &str, calls: &Cell<u32>) -> HandlerResult<String> {
+        run_once(storage, Some(key.to_string()), fingerprint.to_string(), |reservation| async move {
+            calls.set(calls.get() + 1);
+            complete(storage, &reservation.unwrap(), value)?;
+            Ok((StatusCode::ACCEPTED, Json(ApiResponse { success: true, data: Some(value.to_string()), error: None })))
+        })
+        .await
+    }
+
+    fn complete(storage: &dyn Storage, reservation: &Reservation, value: &str) -> Result<(), OpenBankError> {
+        storage.complete_idempotency_key(&reservation.completion(StatusCode::ACCEPTED), &serde_json::to_string(value).unwrap())
+    }
+
+    fn reservation(key: &str, fingerprint: &str, age: chrono::Duration) -> IdempotencyRecord {
+        IdempotencyRecord {
+            key: key.to_string(),
+            fingerprint: fingerprint.to_string(),
+            response_status: None,
+            response_body: None,
+            created_at: chrono::Utc::now() - age,
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_replay_the_first_response() {
+        for (name, storage) in backends() {
+            let calls = Cell::new(0);
+            let (status, Json(first)) = run(storage.as_ref(), "k", "f", "first", &calls).await.unwrap();
+            assert_eq!((status, first.data.as_deref()), (StatusCode::ACCEPTED, Some("first")), "{}", name);
+
+            let (status, Json(replayed)) = run(storage.as_ref(), "k", "f", "second", &calls).await.unwrap();
+            assert_eq!((status, replayed.data.as_deref()), (StatusCode::ACCEPTED, Some("first")), "{}", name);
+            assert_eq!(calls.get(), 1, "{}", name);
+
+            // Without a key every request runs
+            for _ in 0..2 {
+                let _ = run_once(storage.as_ref(), None, "f".to_string(), |_| async {
+                    calls.set(calls.get() + 1);
+                    Ok((StatusCode::OK, Json(ApiResponse { success: true, data: Some(()), error: None })))
+                })
+                .await
+                .unwrap();
+            }
+            assert_eq!(calls.get(), 3, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_key_is_bound_to_its_first_request() {
+        for (name, storage) in backends() {
+            let calls = Cell::new(0);
+            let _ = run(storage.as_ref(), "k", "f", "first", &calls).await.unwrap();
+            assert!(matches!(
+                run(storage.as_ref(), "k", "other", "second", &calls).await,
+                Err(OpenBankError::IdempotencyKeyReused { key }) if key == "k"
+            ), "{}", name);
+            assert_eq!(calls.get(), 1, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_running_request_blocks_its_retries() {
+        for (name, storage) in backends() {
+            let calls = Cell::new(0);
+            let running = reservation("k", "f", chrono::Duration::zero());
+            assert!(storage.reserve_idempotency_key(&running, chrono::Utc::now() - reservation_ttl()).unwrap().is_none());
+            assert!(matches!(
+                run(storage.as_ref(), "k", "f", "retry", &calls).await,
+                Err(OpenBankError::IdempotencyRequestInProgress { .. })
+            ), "{}", name);
+            assert!(matches!(
+                run(storage.as_ref(), "k", "other", "retry", &calls).await,
+                Err(OpenBankError::IdempotencyKeyReused { .. })
+            ), "{}", name);
+            assert_eq!(calls.get(), 0, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn stale_reservations_are_taken_over() {
+        for (name, storage) in backends() {
+            let calls = Cell::new(0);
+            let age = reservation_ttl() + chrono::Duration::seconds(1);
+            storage.reserve_idempotency_key(&reservation("k", "f", age), chrono::Utc::now() - age * 2).unwrap();
+            storage.reserve_idempotency_key(&reservation("other", "f", age), chrono::Utc::now() - age * 2).unwrap();
+
+            let (_, Json(response)) = run(storage.as_ref(), "k", "f", "retry", &calls).await.unwrap();
+            assert_eq!(response.data.as_deref(), Some("retry"), "{}", name);
+            let (_, Json(response)) = run(storage.as_ref(), "k", "f", "again", &calls).await.unwrap();
+            assert_eq!(response.data.as_deref(), Some("retry"), "{}", name);
+
+            // Only by the same request
+            assert!(matches!(
+                run(storage.as_ref(), "other", "changed", "retry", &calls).await,
+                Err(OpenBankError::IdempotencyKeyReused { .. })
+            ), "{}", name);
+            assert_eq!(calls.get(), 1, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_requests_free_their_key() {
+        for (name, storage) in backends() {
+            let calls = Cell::new(0);
+            let failed: HandlerResult<String> = run_once(storage.as_ref(), Some("k".to_string()), "f".to_string(), |_| async {
+                calls.set(calls.get() + 1);
+                Err(OpenBankError::NoWalletAddress)
+            })
+            .await;
+            assert!(matches!(failed, Err(OpenBankError::NoWalletAddress)), "{}", name);
+
+            // Even a different request may use the key now
+            let (_, Json(response)) = run(storage.as_ref(), "k", "other", "retry", &calls).await.unwrap();
+            assert_eq!(response.data.as_deref(), Some("retry"), "{}", name);
+            assert_eq!(calls.get(), 2, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_request_failing_after_its_write_is_not_run_again() {
+        for (name, storage) in backends() {
+            let calls = &Cell::new(0);
+            let storage = storage.as_ref();
+            // The write completed the key, then the request failed anyway
+            let failed: HandlerResult<String> = run_once(storage, Some("k".to_string()), "f".to_string(), |reservation| async move {
+                calls.set(calls.get() + 1);
+                complete(storage, &reservation.unwrap(), "written")?;
+                Err(OpenBankError::NoWalletAddress)
+            })
+            .await;
+            assert!(matches!(failed, Err(OpenBankError::NoWalletAddress)), "{}", name);
+
+            let (status, Json(response)) = run(storage, "k", "f", "retry", calls).await.unwrap();
+            assert_eq!((status, response.data.as_deref()), (StatusCode::ACCEPTED, Some("written")), "{}", name);
+            assert_eq!(calls.get(), 1, "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_taken_over_request_can_no_longer_write() {
+        for (name, storage) in backends() {
+            let calls = Cell::new(0);
+            let age = reservation_ttl() + chrono::Duration::seconds(1);
+            let stale = reservation("k", "f", age);
+            storage.reserve_idempotency_key(&stale, chrono::Utc::now() - age * 2).unwrap();
+            let original = Reservation { key: stale.key.clone(), reserved_at: stale.created_at };
+
+            let _ = run(storage.as_ref(), "k", "f", "retry", &calls).await.unwrap();
+            assert!(matches!(
+                complete(storage.as_ref(), &original, "original"),
+                Err(OpenBankError::IdempotencyRequestInProgress { .. })
+            ), "{}", name);
+            // Nor free the key it lost
+            storage.remove_idempotency_key("k", original.reserved_at).unwrap();
+
+            let (_, Json(response)) = run(storage.as_ref(), "k", "f", "again", &calls).await.unwrap();
+            assert_eq!(response.data.as_deref(), Some("retry"), "{}", name);
+            assert_eq!(calls.get(), 1, "{}", name);
+        }
+    }
+
+    #[test]
+    fn keys_are_scoped_to_their_caller() {
+        let user = |user_id: &str| Principal { role: Role::User, user_id: Some(user_id.to_string()) };
+        let operator = Principal { role: Role::Operator, user_id: None };
+        let key = || Some("k".to_string());
+        assert_ne!(scoped_key(&user("a"), key()), scoped_key(&user("b"), key()));
+        assert_ne!(scoped_key(&user("a"), key()), scoped_key(&operator, key()));
+        assert_eq!(scoped_key(&operator, key()), scoped_key(&operator, key()));
+        assert_eq!(scoped_key(&operator, None), None);
+    }
+}
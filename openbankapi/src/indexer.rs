@@ -0,0 +1,156 @@
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use crate::contract::ContractClient;
+use crate::error::OpenBankError;
+use crate::rpc;
+use crate::storage::Storage;
+use crate::types::IndexerCursor;
+
+const DEFAULT_START_BLOCK: u64 = 0;
+const DEFAULT_REORG_DEPTH: u64 = 12;
+const DEFAULT_POLL_INTERVAL_SECS: u64 = 10;
+const DEFAULT_BATCH_SIZE: u64 = 2000;
+
+struct IndexerConfig {
+    start_block: u64,
+    reorg_depth: u64,
+    batch_size: u64,
+}
+
+// Starts the background task that follows one chain's contract events into storage.
+// Settings come from INDEXER_START_BLOCK (or CHAIN_<id>_INDEXER_START_BLOCK),
+// INDEXER_REORG_DEPTH, INDEXER_POLL_INTERVAL_SECS and INDEXER_BATCH_SIZE.
+// With a WebSocket URL configured, new blocks also wake it up between polls.
+pub fn spawn_indexer(storage: Arc<dyn Storage>, contract_client: Arc<ContractClient>) {
+    let chain_start_block = format!("CHAIN_{}_INDEXER_START_BLOCK", contract_client.chain_id());
+    let config = IndexerConfig {
+        start_block: env_u64(&chain_start_block, env_u64("INDEXER_START_BLOCK", DEFAULT_START_BLOCK)),
+        reorg_depth: env_u64("INDEXER_REORG_DEPTH", DEFAULT_REORG_DEPTH),
+        batch_size: env_u64("INDEXER_BATCH_SIZE", DEFAULT_BATCH_SIZE).max(1),
+    };
+    let interval = env_u64("INDEXER_POLL_INTERVAL_SECS", DEFAULT_POLL_INTERVAL_SECS);
+
+    let new_block = Arc::new(Notify::new());
+    if let Some(ws_url) = contract_client.ws_url() {
+        rpc::watch_new_heads(contract_client.chain_id(), ws_url.to_string(), new_block.clone());
+    }
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = new_block.notified() => {}
+            }
+            if let Err(e) = index_next(storage.as_ref(), &contract_client, &config).await {
+                println!("Warning: Event indexer for chain {} failed: {:?}", contract_client.chain_id(), e);
+            }
+        }
+    });
+}
+
+fn env_u64(name: &str, default: u64) -> u64 {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+// Indexes one batch of blocks after the stored cursor, or rewinds the cursor
+// if the block it points at is no longer part of the canonical chain
+async fn index_next(
+    storage: &dyn Storage,
+    contract_client: &ContractClient,
+    config: &IndexerConfig,
+) -> Result<(), OpenBankError> {
+    let chain_id = contract_client.chain_id();
+    let from_block = match storage.get_indexer_cursor(chain_id)? {
+        Some(cursor) => {
+            let canonical = contract_client.block_hash(cursor.block_number).await?;
+            if canonical.as_deref() != Some(cursor.block_hash.as_str()) {
+                return rewind(storage, contract_client, config, &cursor).await;
+            }
+            cursor.block_number + 1
+        }
+        None => config.start_block,
+    };
+
+    let latest = contract_client.latest_block_number().await?;
+    if from_block > latest {
+        return Ok(());
+    }
+    let to_block = latest.min(from_block + config.batch_size - 1);
+
+    // The hash of the last block is read around the event query so a reorg
+    // in between can't pair events from one chain with a cursor from another
+    let to_hash = contract_client.block_hash(to_block).await?;
+    let events = contract_client.get_events(from_block, to_block).await?;
+    let Some(to_hash) = to_hash else {
+        return Ok(());
+    };
+    if contract_client.block_hash(to_block).await?.as_deref() != Some(to_hash.as_str()) {
+        return Ok(());
+    }
+
+    let cursor = IndexerCursor { chain_id, block_number: to_block, block_hash: to_hash };
+    storage.record_chain_events(&events, &cursor)?;
+    if !events.is_empty() {
+        println!("Indexed {} contract events in blocks {}-{} on chain {}", events.len(), from_block, to_block, chain_id);
+    }
+    Ok(())
+}
+
+// Drops everything indexed in the last reorg_depth blocks so they are read again
+async fn rewind(
+    storage: &dyn Storage,
+    contract_client: &ContractClient,
+    config: &IndexerConfig,
+    cursor: &IndexerCursor,
+) -> Result<(), OpenBankError> {
+    let Some(target) = rewind_target(cursor.block_number, config) else {
+        println!(
+            "Warning: Block {} on chain {} was reorganized, reindexing events from block {}",
+            cursor.block_number, cursor.chain_id, config.start_block
+        );
+        return storage.rewind_chain_events(cursor.chain_id, None);
+    };
+    let block_hash = contract_client.block_hash(target).await?.ok_or_else(|| OpenBankError::SmartContractError {
+        message: format!("Block {} not found while rewinding the indexer", target),
+    })?;
+
+    println!(
+        "Warning: Block {} on chain {} was reorganized, rewinding event indexer to block {}",
+        cursor.block_number, cursor.chain_id, target
+    );
+    let target = IndexerCursor { chain_id: cursor.chain_id, block_number: target, block_hash };
+    storage.rewind_chain_events(cursor.chain_id, Some(&target))
+}
+
+// Last block to keep after a reorg at `block_number`, or None when nothing
+// from start_block on can be kept and the cursor has to go
+fn rewind_target(block_number: u64, config: &IndexerConfig) -> Option<u64> {
+    block_number
+        .checked_sub(config.reorg_depth)
+        .filter(|target| *target >= config.start_block)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(start_block: u64) -> IndexerConfig {
+        IndexerConfig { start_block, reorg_depth: 12, batch_size: DEFAULT_BATCH_SIZE }
+    }
+
+    #[test]
+    fn rewinds_keep_nothing_before_the_start_block() {
+        assert_eq!(rewind_target(100, &config(0)), Some(88));
+        assert_eq!(rewind_target(12, &config(0)), Some(0));
+        // Block 0 itself may have been reorganized, so it is read again too
+        assert_eq!(rewind_target(11, &config(0)), None);
+        assert_eq!(rewind_target(0, &config(0)), None);
+        assert_eq!(rewind_target(62, &config(50)), Some(50));
+        assert_eq!(rewind_target(61, &config(50)), None);
+    }
+}
@@ -0,0 +1,436 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use chrono::Utc;
+use uuid::Uuid;
+use crate::error::OpenBankError;
+use crate::money::{Money, USDT};
+use crate::storage::Storage;
+use crate::types::{
+    Account, FeeLine, JournalEntry, LedgerBalance, LedgerCheckReport, LedgerViolation, LedgerViolationKind, Posting, PostingSide,
+};
+
+// Accounts postings are made against. User accounts are what the onramp owes
+// its users; the rest belong to the onramp itself.
+//
+// The contracts are topped up on-chain, outside this ledger, so nothing ever
+// debits the USDT inventory: it is a contra account whose credit balance is the
+// USDT paid out so far. What the contracts still hold is reconciled against the
+// chain instead (see reconciliation.rs).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LedgerAccount {
+    User { account_id: String },       // A user's fiat account
+    FiatClearing { currency: String }, // Omnibus bank account receiving fiat deposits
+    Conversion { currency: String },   // Counterpart of fiat sold for USDT and USDT paid out for it
+    UsdtInventory,                     // USDT paid out of the contracts (contra account)
+    Fees { currency: String },         // Fees earned by the onramp
+}
+
+impl fmt::Display for LedgerAccount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LedgerAccount::User { account_id } => write!(f, "user:{}", account_id),
+            LedgerAccount::FiatClearing { currency } => write!(f, "clearing:{}", currency),
+            LedgerAccount::Conversion { currency } => write!(f, "conversion:{}", currency),
+            LedgerAccount::UsdtInventory => f.write_str("usdt_inventory"),
+            LedgerAccount::Fees { currency } => write!(f, "fees:{}", currency),
+        }
+    }
+}
+
+pub fn user_account(account_id: &str) -> String {
+    LedgerAccount::User { account_id: account_id.to_string() }.to_string()
+}
+
+// Account id behind a "user:<account id>" ledger account
+pub fn user_account_id(ledger_account: &str) -> Option<&str> {
+    ledger_account.strip_prefix("user:")
+}
+
+fn posting(account: LedgerAccount, side: PostingSide, amount: &Money) -> Posting {
+    Posting { ledger_account: account.to_string(), side, amount: amount.clone() }
+}
+
+// Debits `amount` from a user account, crediting each fee to the fee account.
+// Returns the postings and what is left once the fees are taken out.
+fn debit_with_fees(account_id: &str, amount: &Money, fees: &[FeeLine]) -> Result<(Vec<Posting>, Money), OpenBankError> {
+    let mut postings = vec![posting(LedgerAccount::User { account_id: account_id.to_string() }, PostingSide::Debit, amount)];
+    let mut net = amount.clone();
+    for line in fees {
+        let fees_account = LedgerAccount::Fees { currency: line.amount.currency().to_string() };
+        postings.push(posting(fees_account, PostingSide::Credit, &line.amount));
+        net = net.checked_sub(&line.amount)?;
+    }
+    Ok((postings, net))
+}
+
+// Fiat received by the bank for a user: the omnibus account grows and so does
+// what the onramp owes the user
+pub fn deposit_entry(account: &Account, amount: &Money, transaction_id: &str, description: &str) -> JournalEntry {
+    JournalEntry {
+        id: Uuid::new_v4().to_string(),
+        description: description.to_string(),
+        reference: Some(transaction_id.to_string()),
+        created_at: Utc::now(),
+        postings: vec![
+            posting(LedgerAccount::FiatClearing { currency: account.currency.clone() }, PostingSide::Debit, amount),
+            posting(LedgerAccount::User { account_id: account.id.clone() }, PostingSide::Credit, amount),
+        ],
+    }
+}
+
+// Fiat from a user account, less fees, converted into `usdt` sent out of the contract
+pub fn withdrawal_entry(
+    account_id: &str,
+    amount: &Money,
+    fees: &[FeeLine],
+    usdt: &Money,
+    withdrawal_id: &str,
+    description: &str,
+) -> Result<JournalEntry, OpenBankError> {
+    let (mut postings, net) = debit_with_fees(account_id, amount, fees)?;
+    postings.extend([
+        posting(LedgerAccount::Conversion { currency: net.currency().to_string() }, PostingSide::Credit, &net),
+        posting(LedgerAccount::Conversion { currency: USDT.to_string() }, PostingSide::Debit, usdt),
+        posting(LedgerAccount::UsdtInventory, PostingSide::Credit, usdt),
+    ]);
+
+    Ok(JournalEntry {
+        id: Uuid::new_v4().to_string(),
+        description: description.to_string(),
+        reference: Some(withdrawal_id.to_string()),
+        created_at: Utc::now(),
+        postings,
+    })
+}
+
+// Fiat moved between two user accounts. Between currencies `converted` is what
+// the target receives for the amount less fees, and the conversion accounts
+// absorb the exchange.
+pub fn transfer_entry(
+    from: &Account,
+    to: &Account,
+    amount: &Money,
+    fees: &[FeeLine],
+    converted: &Money,
+    transfer_id: &str,
+    description: &str,
+) -> Result<JournalEntry, OpenBankError> {
+    let (mut postings, net) = debit_with_fees(&from.id, amount, fees)?;
+    if from.currency != to.currency {
+        postings.push(posting(LedgerAccount::Conversion { currency: from.currency.clone() }, PostingSide::Credit, &net));
+        postings.push(posting(LedgerAccount::Conversion { currency: to.currency.clone() }, PostingSide::Debit, converted));
+    }
+    postings.push(posting(LedgerAccount::User { account_id: to.id.clone() }, PostingSide::Credit, converted));
+
+    Ok(JournalEntry {
+        id: Uuid::new_v4().to_string(),
+        description: description.to_string(),
+        reference: Some(transfer_id.to_string()),
+        created_at: Utc::now(),
+        postings,
+    })
+}
+
+/// Fails unless every posting is positive and, per currency, debits equal credits.
+/// Storage calls this before recording an entry.
+pub fn ensure_balanced(entry: &JournalEntry) -> Result<(), OpenBankError> {
+    if entry.postings.is_empty() {
+        return Err(OpenBankError::UnbalancedJournalEntry { entry_id: entry.id.clone() });
+    }
+    for posting in &entry.postings {
+        if !posting.amount.is_positive() {
+            return Err(OpenBankError::InvalidAmount { amount: posting.amount.to_string() });
+        }
+    }
+    for (debits, credits) in totals_by_currency(&entry.postings)?.values() {
+        if debits != credits {
+            return Err(OpenBankError::UnbalancedJournalEntry { entry_id: entry.id.clone() });
+        }
+    }
+    Ok(())
+}
+
+// Debit and credit totals per currency
+fn totals_by_currency<'a>(
+    postings: impl IntoIterator<Item = &'a Posting>,
+) -> Result<BTreeMap<String, (Money, Money)>, OpenBankError> {
+    let mut totals: BTreeMap<String, (Money, Money)> = BTreeMap::new();
+    for posting in postings {
+        let currency = posting.amount.currency();
+        if !totals.contains_key(currency) {
+            totals.insert(currency.to_string(), (Money::zero(currency)?, Money::zero(currency)?));
+        }
+        let (debits, credits) = totals.get_mut(currency).expect("inserted above");
+        match posting.side {
+            PostingSide::Debit => *debits = debits.checked_add(&posting.amount)?,
+            PostingSide::Credit => *credits = credits.checked_add(&posting.amount)?,
+        }
+    }
+    Ok(totals)
+}
+
+/// Checks the ledger invariants: every entry balances, debits equal credits
+/// overall, and each user account's balance matches its postings and its
+/// transaction history without going negative. The onramp's own accounts may
+/// carry either sign, the USDT inventory being a contra account.
+pub fn check(storage: &dyn Storage) -> Result<LedgerCheckReport, OpenBankError> {
+    let entries = storage.list_journal_entries()?;
+    let mut violations = Vec::new();
+
+    for entry in &entries {
+        if let Err(e) = ensure_balanced(entry) {
+            violations.push(LedgerViolation {
+                kind: LedgerViolationKind::UnbalancedEntry,
+                reference: entry.id.clone(),
+                message: e.to_string(),
+            });
+        }
+    }
+
+    let postings = || entries.iter().flat_map(|entry| entry.postings.iter());
+    for (currency, (debits, credits)) in totals_by_currency(postings())? {
+        if debits != credits {
+            violations.push(LedgerViolation {
+                kind: LedgerViolationKind::TrialBalanceMismatch,
+                message: format!("Debits total {} but credits total {}", debits, credits),
+                reference: currency,
+            });
+        }
+    }
+
+    // Trial balance per ledger account
+    let mut by_account: BTreeMap<String, Vec<&Posting>> = BTreeMap::new();
+    for posting in postings() {
+        by_account.entry(posting.ledger_account.clone()).or_default().push(posting);
+    }
+    let mut balances = Vec::with_capacity(by_account.len());
+    for (ledger_account, account_postings) in &by_account {
+        for (_, (debits, credits)) in totals_by_currency(account_postings.iter().copied())? {
+            balances.push(LedgerBalance { ledger_account: ledger_account.clone(), debits, credits });
+        }
+    }
+
+    // Only user accounts are checked for sign, so usdt_inventory is never flagged
+    let mut accounts_checked = 0;
+    for user in storage.list_users()? {
+        for account in storage.list_user_accounts(&user.id)? {
+            accounts_checked += 1;
+            check_user_account(storage, &account, &by_account, &mut violations)?;
+        }
+    }
+
+    Ok(LedgerCheckReport {
+        checked_at: Utc::now(),
+        entries_checked: entries.len(),
+        accounts_checked,
+        balances,
+        violations,
+    })
+}
+
+fn check_user_account(
+    storage: &dyn Storage,
+    account: &Account,
+    by_account: &BTreeMap<String, Vec<&Posting>>,
+    violations: &mut Vec<LedgerViolation>,
+) -> Result<(), OpenBankError> {
+    let ledger_account = user_account(&account.id);
+    let mismatch = |message: String| LedgerViolation {
+        kind: LedgerViolationKind::AccountBalanceMismatch,
+        reference: ledger_account.clone(),
+        message,
+    };
+
+    // User accounts are liabilities, so their balance is credits minus debits
+    let mut derived = Money::zero(&account.currency)?;
+    for posting in by_account.get(&ledger_account).into_iter().flatten() {
+        derived = match posting.side {
+            PostingSide::Credit => derived.checked_add(&posting.amount)?,
+            PostingSide::Debit => derived.checked_sub(&posting.amount)?,
+        };
+    }
+    if derived != account.balance {
+        violations.push(mismatch(format!("Balance is {} but its postings add up to {}", account.balance, derived)));
+    }
+
+    if let Some(last) = storage.list_transactions(&account.id)?.last()
+        && last.balance_after != account.balance
+    {
+        violations.push(mismatch(format!(
+            "Balance is {} but transaction {} left it at {}",
+            account.balance, last.id, last.balance_after
+        )));
+    }
+
+    if account.balance.minor_units() < 0 || account.available_balance()?.minor_units() < 0 {
+        violations.push(LedgerViolation {
+            kind: LedgerViolationKind::NegativeBalance,
+            reference: ledger_account.clone(),
+            message: format!("Balance is {} with {} held", account.balance, account.held_balance),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{AccountRepository, MemoryStorage, TransactionRepository, UserRepository};
+    use crate::types::{AccountType, FeeKind, Transaction, TransactionType, Transfer, User};
+
+    fn money(amount: &str, currency: &str) -> Money {
+        Money::parse(amount, currency).unwrap()
+    }
+
+    fn entry(postings: Vec<Posting>) -> JournalEntry {
+        JournalEntry {
+            id: "entry".to_string(),
+            description: "test".to_string(),
+            reference: None,
+            created_at: Utc::now(),
+            postings,
+        }
+    }
+
+    fn account(id: &str, currency: &str) -> Account {
+        Account {
+            id: id.to_string(),
+            user_id: "user".to_string(),
+            account_type: AccountType::Deposit,
+            balance: Money::zero(currency).unwrap(),
+            held_balance: Money::zero(currency).unwrap(),
+            currency: currency.to_string(),
+            created_at: Utc::now(),
+            is_active: true,
+        }
+    }
+
+    fn transaction(account: &Account, amount: &Money) -> Transaction {
+        Transaction {
+            id: Uuid::new_v4().to_string(),
+            user_id: account.user_id.clone(),
+            account_id: account.id.clone(),
+            transaction_type: TransactionType::Deposit,
+            amount: amount.clone(),
+            description: "test".to_string(),
+            timestamp: Utc::now(),
+            balance_after: account.balance.clone(),
+            tx_hash: None,
+            transfer_id: None,
+        }
+    }
+
+    #[test]
+    fn entries_balance_per_currency() {
+        let usd = money("10", "USD");
+        ensure_balanced(&deposit_entry(&account("a", "USD"), &usd, "t", "deposit")).unwrap();
+
+        // 10 USD debited balances 10 USD credited, not 10 EUR
+        let mixed = entry(vec![
+            posting(LedgerAccount::User { account_id: "a".to_string() }, PostingSide::Debit, &usd),
+            posting(LedgerAccount::Fees { currency: "EUR".to_string() }, PostingSide::Credit, &money("10", "EUR")),
+        ]);
+        assert!(matches!(ensure_balanced(&mixed), Err(OpenBankError::UnbalancedJournalEntry { .. })));
+
+        let short = entry(vec![
+            posting(LedgerAccount::UsdtInventory, PostingSide::Debit, &money("1", USDT)),
+            posting(LedgerAccount::Conversion { currency: USDT.to_string() }, PostingSide::Credit, &money("0.999999", USDT)),
+        ]);
+        assert!(matches!(ensure_balanced(&short), Err(OpenBankError::UnbalancedJournalEntry { .. })));
+        assert!(ensure_balanced(&entry(Vec::new())).is_err());
+
+        let zero = entry(vec![
+            posting(LedgerAccount::UsdtInventory, PostingSide::Debit, &money("0", USDT)),
+            posting(LedgerAccount::Conversion { currency: USDT.to_string() }, PostingSide::Credit, &money("0", USDT)),
+        ]);
+        assert!(matches!(ensure_balanced(&zero), Err(OpenBankError::InvalidAmount { .. })));
+    }
+
+    #[test]
+    fn withdrawal_entry_credits_fees_and_converts_the_rest() {
+        let fees = [FeeLine { kind: FeeKind::Withdrawal, amount: money("0.50", "EUR") }];
+        let entry = withdrawal_entry("a", &money("10", "EUR"), &fees, &money("10.26", USDT), "w", "withdrawal").unwrap();
+        ensure_balanced(&entry).unwrap();
+        let conversion = entry.postings.iter().find(|p| p.ledger_account == "conversion:EUR").unwrap();
+        assert_eq!(conversion.amount, money("9.50", "EUR"));
+        assert!(entry.postings.iter().any(|p| p.ledger_account == "fees:EUR" && p.side == PostingSide::Credit));
+    }
+
+    fn storage_with_user() -> MemoryStorage {
+        let storage = MemoryStorage::new();
+        storage
+            .insert_user(&User {
+                id: "user".to_string(),
+                email: "user@example.com".to_string(),
+                name: "User".to_string(),
+                wallet_address: None,
+                wallet_chain_id: None,
+                tier: crate::types::DEFAULT_TIER.to_string(),
+                created_at: Utc::now(),
+                accounts: Vec::new(),
+            })
+            .unwrap();
+        storage
+    }
+
+    #[test]
+    fn check_finds_no_violations_in_a_consistent_ledger() {
+        let storage = storage_with_user();
+        let usd = account("usd", "USD");
+        let eur = account("eur", "EUR");
+        storage.insert_account(&usd).unwrap();
+        storage.insert_account(&eur).unwrap();
+
+        let amount = money("100", "USD");
+        storage.apply_transaction(transaction(&usd, &amount), &deposit_entry(&usd, &amount, "t", "deposit"), None).unwrap();
+        let sent = money("40", "USD");
+        let fees = [FeeLine { kind: FeeKind::Conversion, amount: money("1", "USD") }];
+        let received = money("35.88", "EUR");
+        let entry = transfer_entry(&usd, &eur, &sent, &fees, &received, "x", "transfer").unwrap();
+        let transfer = Transfer {
+            id: "x".to_string(),
+            from_account_id: usd.id.clone(),
+            to_account_id: eur.id.clone(),
+            amount: sent.clone(),
+            converted_amount: received.clone(),
+            fx_rate: None,
+            quote_id: None,
+            fees: fees.to_vec(),
+            debit: transaction(&usd, &sent.checked_neg().unwrap()),
+            credit: transaction(&eur, &received),
+        };
+        storage.apply_transfer(transfer, &entry, None).unwrap();
+
+        let report = check(&storage).unwrap();
+        assert!(report.violations.is_empty(), "{:?}", report.violations);
+        assert_eq!(report.entries_checked, 2);
+        assert_eq!(report.accounts_checked, 2);
+        let balance = |ledger_account: &str| report.balances.iter().find(|b| b.ledger_account == ledger_account).unwrap();
+        assert_eq!(balance("user:usd").credits.checked_sub(&balance("user:usd").debits).unwrap(), money("60", "USD"));
+        assert_eq!(balance("fees:USD").credits, money("1", "USD"));
+        assert_eq!(balance("conversion:EUR").debits, received);
+    }
+
+    #[test]
+    fn usdt_inventory_is_a_contra_account_left_out_of_the_sign_check() {
+        let storage = storage_with_user();
+        let mut usd = account("usd", "USD");
+        storage.insert_account(&usd).unwrap();
+        let amount = money("100", "USD");
+        storage.apply_transaction(transaction(&usd, &amount), &deposit_entry(&usd, &amount, "t", "deposit"), None).unwrap();
+
+        // Nothing funds the inventory in the ledger, so each payout deepens its credit balance
+        for _ in 0..2 {
+            usd = storage.get_account("usd").unwrap().unwrap();
+            let sent = money("25", "USD");
+            let entry = withdrawal_entry("usd", &sent, &[], &money("25", USDT), "w", "withdrawal").unwrap();
+            storage.apply_transaction(transaction(&usd, &sent.checked_neg().unwrap()), &entry, None).unwrap();
+        }
+
+        let report = check(&storage).unwrap();
+        assert!(report.violations.is_empty(), "{:?}", report.violations);
+        let inventory = report.balances.iter().find(|b| b.ledger_account == "usdt_inventory").unwrap();
+        assert_eq!(inventory.debits, money("0", USDT));
+        assert_eq!(inventory.credits, money("50", USDT));
+    }
+}
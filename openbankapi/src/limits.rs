@@ -0,0 +1,419 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration as StdDuration;
+use chrono::{DateTime, Duration, Utc};
+use ethers::types::U256;
+use crate::contract::{ContractClient, ContractClients};
+use crate::error::OpenBankError;
+use crate::money::{Money, USDT};
+use crate::storage::Storage;
+use crate::types::{GlobalLimits, TierLimits, User, WithdrawalStatus};
+
+const DEFAULT_SAMPLE_INTERVAL_SECS: u64 = 60;
+
+// A contract's total USDT withdrawn, as read at some time
+type Sample = (DateTime<Utc>, U256);
+
+// The limits used until operators set their own: LIMIT_PER_TRANSACTION_USDT,
+// LIMIT_DAILY_USDT and LIMIT_MONTHLY_USDT for every tier, and
+// LIMIT_HOURLY_OUTFLOW_USDT per chain. All are USDT decimals; unset means no limit.
+pub struct Limits {
+    defaults: TierLimits,
+    global_defaults: GlobalLimits,
+    // Contract withdrawal totals per chain, oldest first, to measure the last hour's outflow
+    samples: Mutex<HashMap<u64, VecDeque<Sample>>>,
+    // Held from the limit checks until the withdrawal is stored, so two
+    // concurrent withdrawals can't both fit under the same remaining limit
+    withdrawals: tokio::sync::Mutex<()>,
+}
+
+impl Limits {
+    pub fn from_env() -> Result<Self, OpenBankError> {
+        Ok(Self {
+            defaults: TierLimits {
+                tier: "default".to_string(),
+                per_transaction: limit_from_env("LIMIT_PER_TRANSACTION_USDT")?,
+                daily: limit_from_env("LIMIT_DAILY_USDT")?,
+                monthly: limit_from_env("LIMIT_MONTHLY_USDT")?,
+                updated_at: None,
+            },
+            global_defaults: GlobalLimits {
+                hourly_outflow: limit_from_env("LIMIT_HOURLY_OUTFLOW_USDT")?,
+                updated_at: None,
+            },
+            samples: Mutex::new(HashMap::new()),
+            withdrawals: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    pub fn defaults(&self) -> &TierLimits {
+        &self.defaults
+    }
+
+    pub async fn lock(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.withdrawals.lock().await
+    }
+
+    /// The tier's own limits, or the defaults under its name.
+    pub fn tier_limits(&self, storage: &dyn Storage, tier: &str) -> Result<TierLimits, OpenBankError> {
+        Ok(storage.get_tier_limits(tier)?.unwrap_or_else(|| TierLimits {
+            tier: tier.to_string(),
+            ..self.defaults.clone()
+        }))
+    }
+
+    pub fn global_limits(&self, storage: &dyn Storage) -> Result<GlobalLimits, OpenBankError> {
+        Ok(storage.get_global_limits()?.unwrap_or_else(|| self.global_defaults.clone()))
+    }
+
+    /// USDT withdrawn by the user over the last day and the last 30 days,
+    /// counting every withdrawal that hasn't failed or been replaced.
+    pub fn usage(&self, storage: &dyn Storage, user_id: &str) -> Result<(Money, Money), OpenBankError> {
+        let now = Utc::now();
+        let mut daily = Money::zero(USDT)?;
+        let mut monthly = Money::zero(USDT)?;
+        for withdrawal in storage.list_user_withdrawals(user_id)? {
+            if matches!(withdrawal.status, WithdrawalStatus::Failed | WithdrawalStatus::Replaced)
+                || withdrawal.created_at <= now - Duration::days(30)
+            {
+                continue;
+            }
+            monthly = monthly.checked_add(&withdrawal.usdt_amount)?;
+            if withdrawal.created_at > now - Duration::days(1) {
+                daily = daily.checked_add(&withdrawal.usdt_amount)?;
+            }
+        }
+        Ok((daily, monthly))
+    }
+
+    /// Fails when withdrawing `usdt_amount` would take the user past a limit of their tier.
+    pub fn check_user(&self, storage: &dyn Storage, user: &User, usdt_amount: &Money) -> Result<(), OpenBankError> {
+        let limits = self.tier_limits(storage, &user.tier)?;
+        if let Some(limit) = &limits.per_transaction
+            && usdt_amount.minor_units() > limit.minor_units()
+        {
+            return Err(OpenBankError::TransactionLimitExceeded {
+                limit: limit.to_string(),
+                requested: usdt_amount.to_string(),
+            });
+        }
+        if limits.daily.is_none() && limits.monthly.is_none() {
+            return Ok(());
+        }
+
+        let (daily_used, monthly_used) = self.usage(storage, &user.id)?;
+        if let Some(limit) = &limits.daily
+            && daily_used.checked_add(usdt_amount)?.minor_units() > limit.minor_units()
+        {
+            return Err(OpenBankError::DailyLimitExceeded {
+                limit: limit.to_string(),
+                used: daily_used.to_string(),
+                requested: usdt_amount.to_string(),
+            });
+        }
+        if let Some(limit) = &limits.monthly
+            && monthly_used.checked_add(usdt_amount)?.minor_units() > limit.minor_units()
+        {
+            return Err(OpenBankError::MonthlyLimitExceeded {
+                limit: limit.to_string(),
+                used: monthly_used.to_string(),
+                requested: usdt_amount.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// How much the contract's withdrawal total grew over the last hour, read
+    /// from the chain. Zero without an hourly limit, since none is checked then.
+    /// Called before lock(), so no RPC call is made while it is held.
+    pub async fn chain_outflow(&self, storage: &dyn Storage, contract_client: &ContractClient) -> Result<U256, OpenBankError> {
+        if self.global_limits(storage)?.hourly_outflow.is_none() {
+            return Ok(U256::zero());
+        }
+        let total = contract_client.get_contract_stats().await?.total_withdrawals;
+        let baseline = self.record_sample(contract_client.chain_id(), total);
+        Ok(total.saturating_sub(baseline))
+    }
+
+    /// Fails when sending `usdt_amount` would take the outflow of the last hour
+    /// on `chain_id` past the global limit. The outflow is `chain_outflow` plus
+    /// withdrawals not yet confirmed on that chain. A just-mined withdrawal can
+    /// be counted twice, which errs on the safe side.
+    pub fn check_outflow(
+        &self,
+        storage: &dyn Storage,
+        chain_id: u64,
+        chain_outflow: U256,
+        usdt_amount: &Money,
+    ) -> Result<(), OpenBankError> {
+        let Some(limit) = self.global_limits(storage)?.hourly_outflow else {
+            return Ok(());
+        };
+
+        let mut used = chain_outflow;
+        for withdrawal in storage.list_pending_withdrawals()? {
+            if withdrawal.chain_id == Some(chain_id) {
+                used += U256::from(withdrawal.usdt_amount.to_usdt_base_units()?);
+            }
+        }
+        let requested = U256::from(usdt_amount.to_usdt_base_units()?);
+        let limit_units = U256::from(limit.to_usdt_base_units()?);
+        if used + requested > limit_units {
+            return Err(OpenBankError::HourlyOutflowLimitExceeded {
+                chain_id,
+                limit: limit.to_string(),
+                used: usdt_from_base_units(used),
+                requested: usdt_amount.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    // Stores the chain's current withdrawal total and returns the total of an
+    // hour ago: the latest sample at least an hour old, or the oldest one while
+    // the history is shorter than that. Older samples are dropped.
+    fn record_sample(&self, chain_id: u64, total: U256) -> U256 {
+        let now = Utc::now();
+        let hour_ago = now - Duration::hours(1);
+        let mut samples = self.samples.lock().unwrap();
+        let samples = samples.entry(chain_id).or_default();
+        samples.push_back((now, total));
+        while samples.len() > 1 && samples[1].0 <= hour_ago {
+            samples.pop_front();
+        }
+        samples[0].1
+    }
+}
+
+// Samples each chain's withdrawal total every LIMITS_SAMPLE_INTERVAL_SECS, so
+// the hourly outflow is measured over a full hour even when withdrawals are rare.
+pub fn spawn_sampler(limits: Arc<Limits>, contracts: Arc<ContractClients>) {
+    let interval = std::env::var("LIMITS_SAMPLE_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_SAMPLE_INTERVAL_SECS);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(StdDuration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            for contract_client in contracts.all() {
+                match contract_client.get_contract_stats().await {
+                    Ok(stats) => {
+                        limits.record_sample(contract_client.chain_id(), stats.total_withdrawals);
+                    }
+                    Err(e) => println!(
+                        "Warning: Could not sample withdrawals on chain {}: {:?}",
+                        contract_client.chain_id(),
+                        e
+                    ),
+                }
+            }
+        }
+    });
+}
+
+fn limit_from_env(name: &str) -> Result<Option<Money>, OpenBankError> {
+    match std::env::var(name) {
+        Ok(value) => parse_limit(&value)
+            .map(Some)
+            .map_err(|e| OpenBankError::InvalidRequest { message: format!("Invalid {}: {}", name, e) }),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Tier names are short identifiers such as `standard` or `verified`.
+pub fn validate_tier(tier: &str) -> Result<(), OpenBankError> {
+    if tier.is_empty() || tier.len() > 64 || !tier.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(OpenBankError::InvalidRequest {
+            message: format!("Invalid tier {:?}: use up to 64 letters, digits, '-' or '_'", tier),
+        });
+    }
+    Ok(())
+}
+
+/// A limit given as a USDT decimal; it must be above zero.
+pub fn parse_limit(amount: &str) -> Result<Money, OpenBankError> {
+    let limit = Money::parse(amount, USDT)?;
+    if !limit.is_positive() {
+        return Err(OpenBankError::InvalidAmount { amount: amount.to_string() });
+    }
+    Ok(limit)
+}
+
+fn usdt_from_base_units(units: U256) -> String {
+    i64::try_from(units)
+        .ok()
+        .and_then(|units| Money::from_minor_units(units, USDT).ok())
+        .map(|amount| amount.to_string())
+        .unwrap_or_else(|| format!("{} USDT base units", units))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{LimitRepository, MemoryStorage, WithdrawalRepository};
+    use crate::types::{Withdrawal, DEFAULT_TIER};
+
+    fn usdt(amount: &str) -> Money {
+        Money::parse(amount, USDT).unwrap()
+    }
+
+    fn limits(per_transaction: Option<&str>, daily: Option<&str>, monthly: Option<&str>) -> Limits {
+        Limits {
+            defaults: TierLimits {
+                tier: "default".to_string(),
+                per_transaction: per_transaction.map(usdt),
+                daily: daily.map(usdt),
+                monthly: monthly.map(usdt),
+                updated_at: None,
+            },
+            global_defaults: GlobalLimits { hourly_outflow: None, updated_at: None },
+            samples: Mutex::new(HashMap::new()),
+            withdrawals: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    fn user(tier: &str) -> User {
+        User {
+            id: "user".to_string(),
+            email: "user@example.com".to_string(),
+            name: "User".to_string(),
+            wallet_address: None,
+            wallet_chain_id: None,
+            tier: tier.to_string(),
+            created_at: Utc::now(),
+            accounts: Vec::new(),
+        }
+    }
+
+    fn withdraw(storage: &MemoryStorage, id: &str, amount: &str, status: WithdrawalStatus, age: Duration) {
+        let created_at = Utc::now() - age;
+        storage
+            .insert_withdrawal(&Withdrawal {
+                id: id.to_string(),
+                user_id: "user".to_string(),
+                account_id: "account".to_string(),
+                wallet_address: "0x0000000000000000000000000000000000000001".to_string(),
+                chain_id: Some(1),
+                amount: Money::parse(amount, "USD").unwrap(),
+                usdt_amount: usdt(amount),
+                quote_id: None,
+                fees: Vec::new(),
+                description: "test".to_string(),
+                hold_id: "hold".to_string(),
+                status,
+                tx_hash: None,
+                nonce: None,
+                replaced_tx_hashes: Vec::new(),
+                block_number: None,
+                gas_used: None,
+                transaction_id: None,
+                failure_reason: None,
+                created_at,
+                updated_at: created_at,
+            }, None)
+            .unwrap();
+    }
+
+    #[test]
+    fn usage_covers_rolling_windows_of_live_withdrawals() {
+        let storage = MemoryStorage::new();
+        withdraw(&storage, "recent", "60", WithdrawalStatus::Requested, Duration::hours(1));
+        withdraw(&storage, "failed", "1000", WithdrawalStatus::Failed, Duration::hours(1));
+        withdraw(&storage, "replaced", "1000", WithdrawalStatus::Replaced, Duration::hours(2));
+        withdraw(&storage, "yesterday", "500", WithdrawalStatus::Confirmed, Duration::hours(25));
+        withdraw(&storage, "old", "7", WithdrawalStatus::Confirmed, Duration::days(31));
+
+        let (daily, monthly) = limits(None, None, None).usage(&storage, "user").unwrap();
+        assert_eq!(daily, usdt("60"));
+        assert_eq!(monthly, usdt("560"));
+    }
+
+    #[test]
+    fn daily_and_monthly_limits_include_the_request() {
+        let storage = MemoryStorage::new();
+        withdraw(&storage, "recent", "60", WithdrawalStatus::Submitted, Duration::hours(3));
+        withdraw(&storage, "last_week", "500", WithdrawalStatus::Confirmed, Duration::days(7));
+        let limits = limits(Some("50"), Some("100"), Some("600"));
+        let user = user(DEFAULT_TIER);
+
+        limits.check_user(&storage, &user, &usdt("40")).unwrap();
+        assert!(matches!(
+            limits.check_user(&storage, &user, &usdt("50.000001")),
+            Err(OpenBankError::TransactionLimitExceeded { .. })
+        ));
+        assert!(matches!(
+            limits.check_user(&storage, &user, &usdt("40.000001")),
+            Err(OpenBankError::DailyLimitExceeded { .. })
+        ));
+
+        withdraw(&storage, "two_days_ago", "30", WithdrawalStatus::Confirmed, Duration::days(2));
+        assert!(matches!(
+            limits.check_user(&storage, &user, &usdt("20")),
+            Err(OpenBankError::MonthlyLimitExceeded { .. })
+        ));
+        limits.check_user(&storage, &user, &usdt("10")).unwrap();
+    }
+
+    #[test]
+    fn tiers_override_the_defaults() {
+        let storage = MemoryStorage::new();
+        let limits = limits(Some("10"), None, None);
+        assert_eq!(limits.tier_limits(&storage, "vip").unwrap().per_transaction, Some(usdt("10")));
+
+        storage
+            .set_tier_limits(&TierLimits {
+                tier: "vip".to_string(),
+                per_transaction: None,
+                daily: Some(usdt("1000")),
+                monthly: None,
+                updated_at: Some(Utc::now()),
+            })
+            .unwrap();
+        limits.check_user(&storage, &user("vip"), &usdt("500")).unwrap();
+        assert!(limits.check_user(&storage, &user(DEFAULT_TIER), &usdt("500")).is_err());
+    }
+
+    #[test]
+    fn outflow_is_measured_from_an_hour_ago() {
+        let limits = limits(None, None, None);
+        let now = Utc::now();
+        limits.samples.lock().unwrap().entry(1).or_default().extend([
+            (now - Duration::minutes(90), U256::from(100)),
+            (now - Duration::minutes(61), U256::from(150)),
+            (now - Duration::minutes(30), U256::from(400)),
+        ]);
+        assert_eq!(limits.record_sample(1, U256::from(500)), U256::from(150));
+        assert_eq!(limits.samples.lock().unwrap()[&1].len(), 3);
+        // Without an hour of history the oldest sample is the baseline
+        assert_eq!(limits.record_sample(2, U256::from(10)), U256::from(10));
+    }
+
+    #[test]
+    fn outflow_adds_pending_withdrawals_on_the_chain() {
+        let storage = MemoryStorage::new();
+        let mut limits = limits(None, None, None);
+        limits.check_outflow(&storage, 1, U256::from(u64::MAX), &usdt("1000")).unwrap();
+
+        limits.global_defaults.hourly_outflow = Some(usdt("100"));
+        withdraw(&storage, "pending", "60", WithdrawalStatus::Submitted, Duration::minutes(5));
+        withdraw(&storage, "confirmed", "500", WithdrawalStatus::Confirmed, Duration::minutes(5));
+        let chain_outflow = U256::from(usdt("30").to_usdt_base_units().unwrap());
+        limits.check_outflow(&storage, 1, chain_outflow, &usdt("10")).unwrap();
+        let err = limits.check_outflow(&storage, 1, chain_outflow, &usdt("10.000001")).unwrap_err();
+        assert!(matches!(&err, OpenBankError::HourlyOutflowLimitExceeded { used, .. } if used == "90.000000 USDT"), "{:?}", err);
+        // Withdrawals pending on another chain don't count
+        limits.check_outflow(&storage, 2, chain_outflow, &usdt("70")).unwrap();
+    }
+
+    #[test]
+    fn limits_and_tiers_are_validated() {
+        assert!(parse_limit("0").is_err());
+        assert!(parse_limit("1.0000001").is_err());
+        assert_eq!(parse_limit("2.5").unwrap(), usdt("2.5"));
+        validate_tier("verified_2").unwrap();
+        assert!(validate_tier("").is_err());
+        assert!(validate_tier("vip tier").is_err());
+    }
+}
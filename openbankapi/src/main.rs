@@ -1,65 +1,141 @@
 mod error;
 mod types;
 mod contract;
+mod money;
+mod storage;
+mod idempotency;
+mod withdrawals;
+mod indexer;
+mod reconciliation;
+mod auth;
+mod bindings;
+mod gas;
+mod signer;
+mod rpc;
+mod request_id;
+mod extract;
+mod wallet;
+mod ledger;
+mod fx;
+mod fees;
+mod limits;
 
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Extension, State},
+    http::{HeaderMap, HeaderName, StatusCode},
+    middleware,
     response::Json,
-    routing::{get, post},
+    routing::{get, post, put},
     Router,
 };
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use ethers::types::Address;
+use std::sync::Arc;
 use tower_http::cors::{Any, CorsLayer};
 use uuid::Uuid;
 use dotenv::dotenv;
 
 use crate::error::OpenBankError;
+use crate::extract::{ApiJson, ApiPath, ApiQuery};
 use crate::types::*;
-use crate::contract::ContractClient;
+use crate::contract::{ContractClient, ContractClients};
+use crate::gas::GasPolicy;
+use crate::rpc::RpcPolicy;
+use crate::signer::SignerConfig;
+use crate::money::{Money, USDT};
+use crate::storage::Storage;
+use crate::auth::{AuthConfig, Principal};
+use crate::fees::FeeSchedule;
+use crate::fx::Quoter;
+use crate::limits::Limits;
 
 // App state
 #[derive(Clone)]
 pub struct AppState {
-    pub users: Arc<RwLock<HashMap<String, User>>>,
-    pub accounts: Arc<RwLock<HashMap<String, Account>>>,
-    pub transactions: Arc<RwLock<HashMap<String, Vec<Transaction>>>>,
-    pub contract_client: Option<Arc<ContractClient>>,
+    pub storage: Arc<dyn Storage>,
+    pub contracts: Option<Arc<ContractClients>>,
+    pub auth: Arc<AuthConfig>,
+    pub fx: Arc<Quoter>,
+    pub fees: Arc<FeeSchedule>,
+    pub limits: Arc<Limits>,
 }
 
 impl AppState {
-    pub fn new() -> Self {
+    pub fn new(storage: Arc<dyn Storage>, fx: Quoter, fees: FeeSchedule, limits: Limits) -> Self {
         Self {
-            users: Arc::new(RwLock::new(HashMap::new())),
-            accounts: Arc::new(RwLock::new(HashMap::new())),
-            transactions: Arc::new(RwLock::new(HashMap::new())),
-            contract_client: None,
+            storage,
+            contracts: None,
+            auth: Arc::new(AuthConfig::from_env()),
+            fx: Arc::new(fx),
+            fees: Arc::new(fees),
+            limits: Arc::new(limits),
         }
     }
     
+    // Connects to every configured chain. CHAINS lists chain ids, each with
+    // CHAIN_<id>_RPC_URL, CHAIN_<id>_CONTRACT_ADDRESS and optionally
+    // CHAIN_<id>_WS_URL and CHAIN_<id>_CONFIRMATIONS. Without CHAINS a single chain
+    // is read from CONTRACT_ADDRESS, RPC_URL, WS_URL and CHAIN_ID. RPC URLs can be
+    // comma separated fallbacks. The owner key, gas and RPC policies are shared.
     pub async fn with_contract(mut self) -> Result<Self, OpenBankError> {
         dotenv().ok();
         
-        let contract_config = SmartContractConfig {
-            contract_address: std::env::var("CONTRACT_ADDRESS")
-                .map_err(|_| OpenBankError::SmartContractError { 
-                    message: "CONTRACT_ADDRESS not found in .env file".to_string() 
-                })?,
-            owner_private_key: std::env::var("OWNER_PRIVATE_KEY")
-                .map_err(|_| OpenBankError::SmartContractError { 
-                    message: "OWNER_PRIVATE_KEY not found in .env file".to_string() 
-                })?,
-            rpc_url: std::env::var("RPC_URL")
-                .unwrap_or_else(|_| "http://localhost:8545".to_string()),
-            chain_id: std::env::var("CHAIN_ID")
-                .unwrap_or_else(|_| "31337".to_string())
+        let signer = SignerConfig::from_env()?;
+        let gas_policy = GasPolicy::from_env()?;
+        let rpc_policy = RpcPolicy::from_env()?;
+        let confirmations = |name: &str| {
+            std::env::var(name)
+                .or_else(|_| std::env::var("CONFIRMATIONS"))
+                .unwrap_or_else(|_| "1".to_string())
                 .parse()
-                .unwrap_or(31337),
+                .unwrap_or(1)
         };
         
-        let contract_client = ContractClient::new(contract_config).await?;
-        self.contract_client = Some(Arc::new(contract_client));
+        let mut configs = Vec::new();
+        match std::env::var("CHAINS") {
+            Ok(chains) => {
+                for chain in chains.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+                    let chain_id: u64 = chain.parse().map_err(|_| OpenBankError::SmartContractError { 
+                        message: format!("Invalid chain id in CHAINS: {}", chain) 
+                    })?;
+                    let chain_var = |name: &str| {
+                        let var = format!("CHAIN_{}_{}", chain_id, name);
+                        std::env::var(&var).map_err(|_| OpenBankError::SmartContractError { 
+                            message: format!("{} not found in .env file", var) 
+                        })
+                    };
+                    configs.push(SmartContractConfig {
+                        contract_address: chain_var("CONTRACT_ADDRESS")?,
+                        signer: signer.clone(),
+                        rpc_urls: rpc::parse_urls(&chain_var("RPC_URL")?),
+                        ws_url: chain_var("WS_URL").ok(),
+                        rpc_policy: rpc_policy.clone(),
+                        chain_id,
+                        confirmations: confirmations(&format!("CHAIN_{}_CONFIRMATIONS", chain_id)),
+                        gas_policy: gas_policy.clone(),
+                    });
+                }
+            }
+            Err(_) => configs.push(SmartContractConfig {
+                contract_address: std::env::var("CONTRACT_ADDRESS")
+                    .map_err(|_| OpenBankError::SmartContractError { 
+                        message: "CONTRACT_ADDRESS not found in .env file".to_string() 
+                    })?,
+                signer,
+                rpc_urls: rpc::parse_urls(&std::env::var("RPC_URL")
+                    .unwrap_or_else(|_| "http://localhost:8545".to_string())),
+                ws_url: std::env::var("WS_URL").ok(),
+                rpc_policy,
+                chain_id: std::env::var("CHAIN_ID")
+                    .unwrap_or_else(|_| "31337".to_string())
+                    .parse()
+                    .unwrap_or(31337),
+                confirmations: confirmations("CONFIRMATIONS"),
+                gas_policy,
+            }),
+        }
+        
+        let contracts = ContractClients::connect(configs).await?;
+        self.contracts = Some(Arc::new(contracts));
         
         Ok(self)
     }
@@ -68,113 +144,241 @@ impl AppState {
 // API handlers
 async fn create_user(
     State(state): State<AppState>,
-    Json(payload): Json<CreateUserRequest>,
-) -> Result<(StatusCode, Json<ApiResponse<User>>), (StatusCode, Json<OpenBankError>)> {
+    ApiJson(payload): ApiJson<CreateUserRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<CreateUserResponse>>), OpenBankError> {
     let user_id = Uuid::new_v4().to_string();
     
-    // Validate wallet address if provided
-    if let Some(ref wallet_address) = payload.wallet_address {
-        // Basic Ethereum address validation
-        if !wallet_address.starts_with("0x") || wallet_address.len() != 42 {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                Json(OpenBankError::InvalidWalletAddress { address: wallet_address.clone() }),
-            ));
-        }
+    // Validate wallet address if provided, checksum included
+    let wallet_address = payload.wallet_address.as_deref().map(wallet::parse_address).transpose()?;
+    
+    // The wallet's chain must be one this instance can send to
+    if let Some(chain_id) = payload.wallet_chain_id
+        && let Some(ref contracts) = state.contracts
+    {
+        contracts.get(Some(chain_id))?;
     }
     
     // Check if user already exists (by email)
+    if state.storage.find_user_by_email(&payload.email)?.is_some() {
+        return Err(OpenBankError::UserAlreadyExists { email: payload.email });
+    }
+    
+    // Check if wallet address is already associated with another user
+    if let Some(ref address) = wallet_address
+        && state.storage.find_user_by_wallet(&wallet::checksum(address))?.is_some()
     {
-        let users = state.users.read().unwrap();
-        if users.values().any(|u| u.email == payload.email) {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                Json(OpenBankError::UserAlreadyExists { email: payload.email }),
-            ));
-        }
-        
-        // Check if wallet address is already associated with another user
-        if let Some(ref wallet_address) = payload.wallet_address {
-            if users.values().any(|u| u.wallet_address.as_ref() == Some(wallet_address)) {
-                return Err((
-                    StatusCode::BAD_REQUEST,
-                    Json(OpenBankError::InvalidWalletAddress { address: wallet_address.clone() }),
-                ));
-            }
-        }
+        return Err(OpenBankError::InvalidWalletAddress { address: wallet::checksum(address) });
     }
     
+    // The wallet is only bound once it signs the challenge below
     let user = User {
         id: user_id.clone(),
         email: payload.email,
         name: payload.name,
-        wallet_address: payload.wallet_address,
+        wallet_address: None,
+        wallet_chain_id: None,
+        tier: DEFAULT_TIER.to_string(),
         created_at: chrono::Utc::now(),
         accounts: Vec::new(),
     };
     
-    {
-        let mut users = state.users.write().unwrap();
-        users.insert(user_id.clone(), user.clone());
-    }
+    state.storage.insert_user(&user)?;
     
-    // If wallet address is provided, try to get balance from smart contract
-    if let Some(ref wallet_address) = user.wallet_address {
-        if let Some(ref contract_client) = state.contract_client {
-            match contract_client.get_user_balance(wallet_address.clone()).await {
-                Ok(balance) => {
-                    println!("User {} has contract balance: deposited={}, withdrawn={}", 
-                        user.email, balance.deposited, balance.withdrawn);
-                }
-                Err(e) => {
-                    println!("Warning: Could not get contract balance for {}: {:?}", wallet_address, e);
-                }
-            }
-        }
-    }
+    // Issue the user's API key; only its hash is stored
+    let api_key = auth::generate_key();
+    state.storage.insert_api_key(&ApiKey {
+        key_hash: auth::hash_key(&api_key),
+        user_id: user.id.clone(),
+        created_at: chrono::Utc::now(),
+    })?;
+    
+    let wallet_challenge = match wallet_address {
+        Some(address) => Some(issue_wallet_challenge(&state, &user.id, &address, payload.wallet_chain_id)?),
+        None => None,
+    };
     
     Ok((StatusCode::OK, Json(ApiResponse {
         success: true,
-        data: Some(user),
+        data: Some(CreateUserResponse { user, api_key, wallet_challenge }),
         error: None,
     })))
 }
 
 async fn get_user(
     State(state): State<AppState>,
-    Path(user_id): Path<String>,
-) -> Result<(StatusCode, Json<ApiResponse<User>>), (StatusCode, Json<OpenBankError>)> {
-    let users = state.users.read().unwrap();
+    Extension(principal): Extension<Principal>,
+    ApiPath(user_id): ApiPath<String>,
+) -> Result<(StatusCode, Json<ApiResponse<User>>), OpenBankError> {
+    principal.require_read(&user_id)?;
     
-    match users.get(&user_id) {
+    match state.storage.get_user(&user_id)? {
         Some(user) => Ok((StatusCode::OK, Json(ApiResponse {
             success: true,
-            data: Some(user.clone()),
+            data: Some(user),
             error: None,
         }))),
-        None => Err((
-            StatusCode::NOT_FOUND,
-            Json(OpenBankError::UserNotFound { user_id }),
-        )),
+        None => Err(OpenBankError::UserNotFound { user_id }),
     }
 }
 
-async fn create_account(
+async fn update_user(
     State(state): State<AppState>,
-    Path(user_id): Path<String>,
-    Json(payload): Json<CreateAccountRequest>,
-) -> Result<(StatusCode, Json<ApiResponse<Account>>), (StatusCode, Json<OpenBankError>)> {
-    // Validate user exists
+    Extension(principal): Extension<Principal>,
+    ApiPath(user_id): ApiPath<String>,
+    ApiJson(payload): ApiJson<UpdateUserRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<User>>), OpenBankError> {
+    principal.require_user(&user_id)?;
+    
+    let mut user = state.storage.get_user(&user_id)?
+        .ok_or_else(|| OpenBankError::UserNotFound { user_id: user_id.clone() })?;
+    
+    if let Some(email) = payload.email {
+        if email.trim().is_empty() {
+            return Err(OpenBankError::InvalidRequest { message: "email must not be empty".to_string() });
+        }
+        // The email must stay unique across users
+        if email != user.email && state.storage.find_user_by_email(&email)?.is_some() {
+            return Err(OpenBankError::UserAlreadyExists { email });
+        }
+        user.email = email;
+    }
+    if let Some(name) = payload.name {
+        if name.trim().is_empty() {
+            return Err(OpenBankError::InvalidRequest { message: "name must not be empty".to_string() });
+        }
+        user.name = name;
+    }
+    
+    state.storage.update_user(&user)?;
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(user),
+        error: None,
+    })))
+}
+
+fn issue_wallet_challenge(
+    state: &AppState,
+    user_id: &str,
+    address: &Address,
+    wallet_chain_id: Option<u64>,
+) -> Result<WalletChallengeResponse, OpenBankError> {
+    let challenge = wallet::new_challenge(user_id, address, wallet_chain_id);
+    state.storage.insert_wallet_challenge(&challenge)?;
+    
+    Ok(WalletChallengeResponse {
+        message: wallet::challenge_message(&challenge),
+        typed_data: wallet::challenge_typed_data(&challenge),
+        challenge,
+    })
+}
+
+// First step of binding or changing a wallet: a nonce for the wallet to sign
+async fn create_wallet_challenge(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(user_id): ApiPath<String>,
+    ApiJson(payload): ApiJson<WalletChallengeRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<WalletChallengeResponse>>), OpenBankError> {
+    principal.require_user(&user_id)?;
+    
+    if state.storage.get_user(&user_id)?.is_none() {
+        return Err(OpenBankError::UserNotFound { user_id });
+    }
+    
+    let address = wallet::parse_address(&payload.wallet_address)?;
+    
+    // The wallet's chain must be one this instance can send to
+    if let Some(chain_id) = payload.wallet_chain_id
+        && let Some(ref contracts) = state.contracts
+    {
+        contracts.get(Some(chain_id))?;
+    }
+    
+    let challenge = issue_wallet_challenge(&state, &user_id, &address, payload.wallet_chain_id)?;
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(challenge),
+        error: None,
+    })))
+}
+
+// Second step: binds the challenged wallet once its signature checks out, so a
+// user can't route withdrawals to an address they don't control
+async fn verify_wallet(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(user_id): ApiPath<String>,
+    ApiJson(payload): ApiJson<VerifyWalletRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<User>>), OpenBankError> {
+    principal.require_user(&user_id)?;
+    
+    let mut user = state.storage.get_user(&user_id)?
+        .ok_or_else(|| OpenBankError::UserNotFound { user_id: user_id.clone() })?;
+    
+    // Taken up front, so a nonce gets one attempt whatever the outcome
+    let challenge = state.storage.take_wallet_challenge(&payload.nonce)?
+        .filter(|challenge| challenge.user_id == user.id)
+        .ok_or_else(|| OpenBankError::WalletChallengeNotFound { nonce: payload.nonce.clone() })?;
+    if challenge.expires_at < chrono::Utc::now() {
+        return Err(OpenBankError::WalletChallengeExpired { nonce: payload.nonce });
+    }
+    
+    let address = wallet::verify_challenge(&challenge, &payload.signature, payload.signature_type)?;
+    
+    // A wallet can only belong to one user
+    if let Some(owner) = state.storage.find_user_by_wallet(&challenge.wallet_address)?
+        && owner.id != user.id
     {
-        let users = state.users.read().unwrap();
-        if !users.contains_key(&user_id) {
-            return Err((
-                StatusCode::NOT_FOUND,
-                Json(OpenBankError::UserNotFound { user_id: user_id.clone() }),
-            ));
+        return Err(OpenBankError::InvalidWalletAddress { address: challenge.wallet_address });
+    }
+    
+    user.wallet_address = Some(challenge.wallet_address);
+    user.wallet_chain_id = challenge.wallet_chain_id;
+    state.storage.update_user(&user)?;
+    println!("User {} linked wallet {:?}", user.id, address);
+    
+    // Log the on-chain history of the newly bound wallet
+    if let Some(ref wallet_address) = user.wallet_address
+        && let Some(ref contracts) = state.contracts
+        && let Ok(contract_client) = contracts.get(user.wallet_chain_id)
+    {
+        match contract_client.get_user_balance(wallet_address.clone()).await {
+            Ok(balance) => {
+                println!("User {} has contract balance: deposited={}, withdrawn={}",
+                    user.email, balance.deposited, balance.withdrawn);
+            }
+            Err(e) => {
+                println!("Warning: Could not get contract balance for {}: {:?}", wallet_address, e);
+            }
         }
     }
     
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(user),
+        error: None,
+    })))
+}
+
+async fn create_account(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(user_id): ApiPath<String>,
+    ApiJson(payload): ApiJson<CreateAccountRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<Account>>), OpenBankError> {
+    principal.require_user(&user_id)?;
+    
+    // Validate user exists
+    if state.storage.get_user(&user_id)?.is_none() {
+        return Err(OpenBankError::UserNotFound { user_id: user_id.clone() });
+    }
+    
+    // Only currencies with a known minor unit can hold balances
+    let balance = Money::zero(&payload.currency)?;
+    
     // Always create a deposit tracking account
     let account_type = AccountType::Deposit;
     
@@ -183,31 +387,52 @@ async fn create_account(
         id: account_id.clone(),
         user_id: user_id.clone(),
         account_type,
-        balance: 0.0,
+        held_balance: balance.clone(),
+        balance,
         currency: payload.currency,
         created_at: chrono::Utc::now(),
         is_active: true,
     };
     
-    // Add account to state
-    {
-        let mut accounts = state.accounts.write().unwrap();
-        accounts.insert(account_id.clone(), account.clone());
-    }
+    // Persist the account and link it to the user
+    state.storage.insert_account(&account)?;
     
-    // Add account to user
-    {
-        let mut users = state.users.write().unwrap();
-        if let Some(user) = users.get_mut(&user_id) {
-            user.accounts.push(account_id.clone());
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(account),
+        error: None,
+    })))
+}
+
+async fn get_account(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(account_id): ApiPath<String>,
+) -> Result<(StatusCode, Json<ApiResponse<Account>>), OpenBankError> {
+    match state.storage.get_account(&account_id)? {
+        Some(account) => {
+            principal.require_read(&account.user_id)?;
+            Ok((StatusCode::OK, Json(ApiResponse {
+                success: true,
+                data: Some(account),
+                error: None,
+            })))
         }
+        None => Err(OpenBankError::AccountNotFound { account_id }),
     }
+}
+
+// Closing is permanent; the account must be emptied first
+async fn close_account(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(account_id): ApiPath<String>,
+) -> Result<(StatusCode, Json<ApiResponse<Account>>), OpenBankError> {
+    let account = state.storage.get_account(&account_id)?
+        .ok_or_else(|| OpenBankError::AccountNotFound { account_id: account_id.clone() })?;
+    principal.require_user(&account.user_id)?;
     
-    // Initialize transactions list
-    {
-        let mut transactions = state.transactions.write().unwrap();
-        transactions.insert(account_id.clone(), Vec::new());
-    }
+    let account = state.storage.close_account(&account.id)?;
     
     Ok((StatusCode::OK, Json(ApiResponse {
         success: true,
@@ -216,188 +441,824 @@ async fn create_account(
     })))
 }
 
-async fn get_account(
+async fn deposit(
     State(state): State<AppState>,
-    Path(account_id): Path<String>,
-) -> Result<(StatusCode, Json<ApiResponse<Account>>), (StatusCode, Json<OpenBankError>)> {
-    let accounts = state.accounts.read().unwrap();
+    Extension(principal): Extension<Principal>,
+    ApiPath(account_id): ApiPath<String>,
+    headers: HeaderMap,
+    ApiJson(payload): ApiJson<DepositRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<Transaction>>), OpenBankError> {
+    // Deposits mirror fiat received by the bank, so only operators record them
+    principal.require_operator()?;
     
-    match accounts.get(&account_id) {
-        Some(account) => Ok((StatusCode::OK, Json(ApiResponse {
-            success: true,
-            data: Some(account.clone()),
-            error: None,
-        }))),
-        None => Err((
-            StatusCode::NOT_FOUND,
-            Json(OpenBankError::AccountNotFound { account_id }),
-        )),
-    }
+    // Retries carrying the same Idempotency-Key get the first response back
+    let key = idempotency::scoped_key(&principal, idempotency::key_from_headers(&headers)?);
+    let fingerprint = idempotency::fingerprint("deposit", &(&account_id, &payload));
+    let storage = state.storage.clone();
+    idempotency::run_once(storage.as_ref(), key, fingerprint, |reservation| {
+        process_deposit(state, account_id, payload, reservation)
+    }).await
 }
 
-async fn deposit(
-    State(state): State<AppState>,
-    Path(account_id): Path<String>,
-    Json(payload): Json<DepositRequest>,
-) -> Result<(StatusCode, Json<ApiResponse<Transaction>>), (StatusCode, Json<OpenBankError>)> {
-    if payload.amount <= 0.0 {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(OpenBankError::InvalidAmount { amount: payload.amount }),
-        ));
+async fn process_deposit(
+    state: AppState,
+    account_id: String,
+    payload: DepositRequest,
+    reservation: Option<idempotency::Reservation>,
+) -> Result<(StatusCode, Json<ApiResponse<Transaction>>), OpenBankError> {
+    let account = state.storage.get_account(&account_id)?
+        .ok_or_else(|| OpenBankError::AccountNotFound { account_id: account_id.clone() })?;
+    if !account.is_active {
+        return Err(OpenBankError::AccountInactive { account_id });
     }
     
-    let transaction_id = Uuid::new_v4().to_string();
-    
-    // Update account balance and get user_id
-    let (_balance_after, user_id) = {
-        let mut accounts = state.accounts.write().unwrap();
-        match accounts.get_mut(&account_id) {
-            Some(account) => {
-                account.balance += payload.amount;
-                (account.balance, account.user_id.clone())
-            }
-            None => {
-                return Err((
-                    StatusCode::NOT_FOUND,
-                    Json(OpenBankError::AccountNotFound { account_id }),
-                ));
-            }
-        }
-    };
+    // Amounts are parsed exactly in the account currency
+    let amount = Money::parse(&payload.amount, &account.currency)?;
+    if !amount.is_positive() {
+        return Err(OpenBankError::InvalidAmount { amount: payload.amount });
+    }
     
-    // Create transaction record
+    // Post the ledger entry and record the transaction in one step
+    let description = payload.description.unwrap_or_else(|| "Deposit".to_string());
+    let transaction_id = Uuid::new_v4().to_string();
+    let entry = ledger::deposit_entry(&account, &amount, &transaction_id, &description);
     let transaction = Transaction {
-        id: transaction_id.clone(),
-        user_id,
+        id: transaction_id,
+        user_id: account.user_id,
         account_id: account_id.clone(),
-        amount: payload.amount,
+        amount,
         transaction_type: TransactionType::Deposit,
-        description: payload.description.unwrap_or_else(|| "Deposit".to_string()),
+        description,
         timestamp: chrono::Utc::now(),
-        balance_after: _balance_after,
+        balance_after: account.balance,
+        tx_hash: None,
+        transfer_id: None,
     };
+    let completion = reservation.map(|reservation| reservation.completion(StatusCode::OK));
+    let transaction = state.storage.apply_transaction(transaction, &entry, completion.as_ref())?;
     
-    // Add transaction to history
-    {
-        let mut transactions = state.transactions.write().unwrap();
-        if let Some(account_transactions) = transactions.get_mut(&account_id) {
-            account_transactions.push(transaction.clone());
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(transaction),
+        error: None,
+    })))
+}
+
+async fn transfer(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    headers: HeaderMap,
+    ApiJson(payload): ApiJson<TransferRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<Transfer>>), OpenBankError> {
+    // Checked before a stored response can be replayed
+    let from = state.storage.get_account(&payload.from_account_id)?
+        .ok_or_else(|| OpenBankError::AccountNotFound { account_id: payload.from_account_id.clone() })?;
+    principal.require_user(&from.user_id)?;
+    
+    let key = idempotency::scoped_key(&principal, idempotency::key_from_headers(&headers)?);
+    let fingerprint = idempotency::fingerprint("transfer", &(&principal.user_id, &payload));
+    let storage = state.storage.clone();
+    idempotency::run_once(storage.as_ref(), key, fingerprint, |reservation| {
+        process_transfer(state, principal, payload, reservation)
+    }).await
+}
+
+async fn process_transfer(
+    state: AppState,
+    principal: Principal,
+    payload: TransferRequest,
+    reservation: Option<idempotency::Reservation>,
+) -> Result<(StatusCode, Json<ApiResponse<Transfer>>), OpenBankError> {
+    let load = |account_id: &str| -> Result<Account, OpenBankError> {
+        let account = state.storage.get_account(account_id)?
+            .ok_or_else(|| OpenBankError::AccountNotFound { account_id: account_id.to_string() })?;
+        if !account.is_active {
+            return Err(OpenBankError::AccountInactive { account_id: account_id.to_string() });
         }
+        Ok(account)
+    };
+    let from = load(&payload.from_account_id)?;
+    let to = load(&payload.to_account_id)?;
+    if from.id == to.id {
+        return Err(OpenBankError::InvalidRequest { message: "Cannot transfer to the same account".to_string() });
+    }
+    
+    let amount = Money::parse(&payload.amount, &from.currency)?;
+    if !amount.is_positive() {
+        return Err(OpenBankError::InvalidAmount { amount: payload.amount });
+    }
+    
+    // Same-currency transfers move the amount as is. Between currencies a quote
+    // locks the rate; setting one directly is left to operators.
+    let transfer_id = Uuid::new_v4().to_string();
+    let (converted, fx_rate, quote_id, fees) = if from.currency == to.currency {
+        (amount.clone(), None, None, Vec::new())
+    } else if let Some(quote_id) = &payload.quote_id {
+        let quote = state.storage.get_quote(quote_id)?
+            .ok_or_else(|| OpenBankError::QuoteNotFound { quote_id: quote_id.clone() })?;
+        fx::check_quote(&quote, QuotePurpose::Transfer, &from.id, &amount, &to.currency)?;
+        (quote.converted_amount, Some(quote.rate), Some(quote.id), quote.fees)
+    } else if let Some(rate) = payload.fx_rate {
+        principal.require_operator()?;
+        (amount.convert(&rate, &to.currency)?, Some(rate), None, Vec::new())
+    } else {
+        return Err(OpenBankError::FxQuoteRequired { from: from.currency, to: to.currency });
+    };
+    if !converted.is_positive() {
+        return Err(OpenBankError::InvalidAmount { amount: converted.to_string() });
+    }
+    
+    // A failed transfer still spends its quote; quotes are cheap to get again
+    if let Some(quote_id) = &quote_id {
+        state.storage.use_quote(quote_id, &transfer_id)?;
     }
     
+    // Both sides and their ledger entry are recorded in one step
+    let description = payload.description.unwrap_or_else(|| "Transfer".to_string());
+    let entry = ledger::transfer_entry(&from, &to, &amount, &fees, &converted, &transfer_id, &description)?;
+    let now = chrono::Utc::now();
+    let debit = Transaction {
+        id: Uuid::new_v4().to_string(),
+        user_id: from.user_id.clone(),
+        account_id: from.id.clone(),
+        amount: amount.checked_neg()?,
+        transaction_type: TransactionType::Transfer,
+        description: description.clone(),
+        timestamp: now,
+        balance_after: from.balance.clone(),
+        tx_hash: None,
+        transfer_id: Some(transfer_id.clone()),
+    };
+    let credit = Transaction {
+        id: Uuid::new_v4().to_string(),
+        user_id: to.user_id.clone(),
+        account_id: to.id.clone(),
+        amount: converted.clone(),
+        transaction_type: TransactionType::Transfer,
+        description,
+        timestamp: now,
+        balance_after: to.balance.clone(),
+        tx_hash: None,
+        transfer_id: Some(transfer_id.clone()),
+    };
+    let transfer = Transfer {
+        id: transfer_id,
+        from_account_id: from.id,
+        to_account_id: to.id,
+        amount,
+        converted_amount: converted,
+        fx_rate,
+        quote_id,
+        fees,
+        debit,
+        credit,
+    };
+    let completion = reservation.map(|reservation| reservation.completion(StatusCode::OK));
+    let transfer = state.storage.apply_transfer(transfer, &entry, completion.as_ref())?;
+    
     Ok((StatusCode::OK, Json(ApiResponse {
         success: true,
-        data: Some(transaction),
+        data: Some(transfer),
         error: None,
     })))
 }
 
 async fn get_transactions(
     State(state): State<AppState>,
-    Path(account_id): Path<String>,
-) -> Result<(StatusCode, Json<ApiResponse<Vec<Transaction>>>), (StatusCode, Json<OpenBankError>)> {
-    let transactions = state.transactions.read().unwrap();
+    Extension(principal): Extension<Principal>,
+    ApiPath(account_id): ApiPath<String>,
+) -> Result<(StatusCode, Json<ApiResponse<Vec<Transaction>>>), OpenBankError> {
+    let account = state.storage.get_account(&account_id)?
+        .ok_or_else(|| OpenBankError::AccountNotFound { account_id: account_id.clone() })?;
+    principal.require_read(&account.user_id)?;
     
-    match transactions.get(&account_id) {
-        Some(account_transactions) => Ok((StatusCode::OK, Json(ApiResponse {
-            success: true,
-            data: Some(account_transactions.clone()),
-            error: None,
-        }))),
-        None => Err((
-            StatusCode::NOT_FOUND,
-            Json(OpenBankError::AccountNotFound { account_id }),
-        )),
-    }
+    let account_transactions = state.storage.list_transactions(&account_id)?;
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(account_transactions),
+        error: None,
+    })))
 }
 
 async fn get_user_accounts(
     State(state): State<AppState>,
-    Path(user_id): Path<String>,
-) -> Result<(StatusCode, Json<ApiResponse<Vec<Account>>>), (StatusCode, Json<OpenBankError>)> {
-    let users = state.users.read().unwrap();
-    let accounts = state.accounts.read().unwrap();
-    
-    match users.get(&user_id) {
-        Some(user) => {
-            let user_accounts: Vec<Account> = user.accounts
-                .iter()
-                .filter_map(|account_id| accounts.get(account_id).cloned())
-                .collect();
-            
-            Ok((StatusCode::OK, Json(ApiResponse {
-                success: true,
-                data: Some(user_accounts),
-                error: None,
-            })))
+    Extension(principal): Extension<Principal>,
+    ApiPath(user_id): ApiPath<String>,
+) -> Result<(StatusCode, Json<ApiResponse<Vec<Account>>>), OpenBankError> {
+    principal.require_read(&user_id)?;
+    
+    if state.storage.get_user(&user_id)?.is_none() {
+        return Err(OpenBankError::UserNotFound { user_id });
+    }
+    
+    let user_accounts = state.storage.list_user_accounts(&user_id)?;
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(user_accounts),
+        error: None,
+    })))
+}
+
+// Locks a rate for converting an amount out of an account, by default into USDT
+// for a withdrawal. The quote is spent by the withdrawal or transfer naming it.
+async fn create_quote(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiJson(payload): ApiJson<QuoteRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<Quote>>), OpenBankError> {
+    let account = state.storage.get_account(&payload.account_id)?
+        .ok_or_else(|| OpenBankError::AccountNotFound { account_id: payload.account_id.clone() })?;
+    principal.require_user(&account.user_id)?;
+    if !account.is_active {
+        return Err(OpenBankError::AccountInactive { account_id: account.id });
+    }
+    
+    let amount = Money::parse(&payload.amount, &account.currency)?;
+    if !amount.is_positive() {
+        return Err(OpenBankError::InvalidAmount { amount: payload.amount });
+    }
+    
+    // Withdrawal quotes also carry the withdrawal fee for the chain the USDT will go out on
+    let currency = payload.currency.as_deref().unwrap_or(USDT);
+    let purpose = payload.purpose.unwrap_or(if currency == USDT { QuotePurpose::Withdrawal } else { QuotePurpose::Transfer });
+    let (chain_id, fees) = if purpose == QuotePurpose::Withdrawal {
+        if currency != USDT {
+            return Err(OpenBankError::InvalidRequest { message: "Withdrawal quotes convert into USDT".to_string() });
         }
-        None => Err((
-            StatusCode::NOT_FOUND,
-            Json(OpenBankError::UserNotFound { user_id }),
-        )),
+        let wallet_chain_id = state.storage.get_user(&account.user_id)?
+            .ok_or_else(|| OpenBankError::UserNotFound { user_id: account.user_id.clone() })?
+            .wallet_chain_id;
+        if let (Some(wallet_chain_id), Some(chain_id)) = (wallet_chain_id, payload.chain_id)
+            && wallet_chain_id != chain_id
+        {
+            return Err(OpenBankError::WalletChainMismatch { wallet_chain_id, chain_id });
+        }
+        let chain_id = require_contract(&state, payload.chain_id.or(wallet_chain_id))?.chain_id();
+        (Some(chain_id), withdrawal_fees(&state, &amount, chain_id)?)
+    } else {
+        (None, state.fees.fee(FeeKind::Conversion, &amount, None)?.into_iter().collect())
+    };
+    let quote = state.fx.quote(&account, &amount, currency, purpose, chain_id, fees).await?;
+    state.storage.insert_quote(&quote)?;
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(quote),
+        error: None,
+    })))
+}
+
+// Converting into USDT and paying it out on `chain_id`
+fn withdrawal_fees(state: &AppState, amount: &Money, chain_id: u64) -> Result<Vec<FeeLine>, OpenBankError> {
+    let mut fees: Vec<FeeLine> = state.fees.fee(FeeKind::Conversion, amount, None)?.into_iter().collect();
+    fees.extend(state.fees.fee(FeeKind::Withdrawal, amount, Some(chain_id))?);
+    Ok(fees)
+}
+
+// USDT sent and fees charged for a withdrawal without a quote. USDT accounts
+// pay out what is left after the withdrawal fee as is; USD ones are priced like
+// a quote at the current rate.
+async fn price_withdrawal(
+    state: &AppState,
+    account: &Account,
+    amount: &Money,
+    chain_id: u64,
+) -> Result<(Money, Vec<FeeLine>), OpenBankError> {
+    if account.currency == USDT {
+        let fees: Vec<FeeLine> = state.fees.fee(FeeKind::Withdrawal, amount, Some(chain_id))?.into_iter().collect();
+        return Ok((fees::net_of_fees(amount, &fees)?, fees));
     }
+    let fees = withdrawal_fees(state, amount, chain_id)?;
+    let priced = state.fx.quote(account, amount, USDT, QuotePurpose::Withdrawal, Some(chain_id), fees).await?;
+    Ok((priced.converted_amount, priced.fees))
+}
+
+async fn get_quote(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(quote_id): ApiPath<String>,
+) -> Result<(StatusCode, Json<ApiResponse<Quote>>), OpenBankError> {
+    let quote = state.storage.get_quote(&quote_id)?
+        .ok_or(OpenBankError::QuoteNotFound { quote_id })?;
+    principal.require_read(&quote.user_id)?;
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(quote),
+        error: None,
+    })))
 }
 
 #[axum::debug_handler]
 async fn withdraw_to_wallet(
     State(state): State<AppState>,
-    Json(payload): Json<WithdrawRequest>,
-) -> Result<(StatusCode, Json<ApiResponse<String>>), (StatusCode, Json<OpenBankError>)> {
+    Extension(principal): Extension<Principal>,
+    headers: HeaderMap,
+    ApiJson(payload): ApiJson<WithdrawRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<Withdrawal>>), OpenBankError> {
+    // Withdrawals spend the owner key's USDT
+    principal.require_operator()?;
+    
+    // A retried withdrawal must never send USDT twice
+    let key = idempotency::scoped_key(&principal, idempotency::key_from_headers(&headers)?);
+    let fingerprint = idempotency::fingerprint("withdraw", &payload);
+    let storage = state.storage.clone();
+    idempotency::run_once(storage.as_ref(), key, fingerprint, |reservation| {
+        process_withdrawal(state, payload, reservation)
+    }).await
+}
+
+async fn process_withdrawal(
+    state: AppState,
+    payload: WithdrawRequest,
+    reservation: Option<idempotency::Reservation>,
+) -> Result<(StatusCode, Json<ApiResponse<Withdrawal>>), OpenBankError> {
     // Get user to check if they have a wallet address
-    let wallet_address = {
-        let users = state.users.read().unwrap();
-        let user = users.get(&payload.user_id)
-            .ok_or_else(|| (
-                StatusCode::NOT_FOUND,
-                Json(OpenBankError::UserNotFound { user_id: payload.user_id.clone() })
-            ))?;
-        
-        user.wallet_address.as_ref()
-            .ok_or_else(|| (
-                StatusCode::BAD_REQUEST,
-                Json(OpenBankError::NoWalletAddress)
-            ))?
-            .clone()
-    };
+    let user = state.storage.get_user(&payload.user_id)?
+        .ok_or_else(|| OpenBankError::UserNotFound { user_id: payload.user_id.clone() })?;
+    let wallet_address = user.wallet_address.clone()
+        .ok_or(OpenBankError::NoWalletAddress)?;
+    
+    // A wallet registered on one chain can only be paid there
+    if let (Some(wallet_chain_id), Some(chain_id)) = (user.wallet_chain_id, payload.chain_id)
+        && wallet_chain_id != chain_id
+    {
+        return Err(OpenBankError::WalletChainMismatch { wallet_chain_id, chain_id });
+    }
+    let chain_id = payload.chain_id.or(user.wallet_chain_id);
+    
+    // Load the fiat account being debited and make sure it belongs to the user
+    let account = state.storage.get_account(&payload.account_id)?
+        .ok_or_else(|| OpenBankError::AccountNotFound { account_id: payload.account_id.clone() })?;
+    if account.user_id != payload.user_id {
+        return Err(OpenBankError::AccountOwnershipMismatch {
+            account_id: account.id,
+            user_id: payload.user_id,
+        });
+    }
     
     // Validate amount
-    if payload.amount <= 0.0 {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(OpenBankError::InvalidAmount { amount: payload.amount }),
-        ));
+    let amount = Money::parse(&payload.amount, &account.currency)?;
+    if !amount.is_positive() {
+        return Err(OpenBankError::InvalidAmount { amount: payload.amount });
     }
     
-    // Convert amount to USDT smallest unit (6 decimals)
-    let amount_usdt = (payload.amount * 1_000_000.0) as u64;
+    let contract_client = require_contract(&state, chain_id)?;
     
-    // Send transaction to smart contract
-    if let Some(ref contract_client) = state.contract_client {
-        let description = payload.description.unwrap_or_else(|| "API withdrawal".to_string());
-        
-        contract_client.send_usdt_to_address(
-            wallet_address.clone(),
-            amount_usdt,
-            description
-        ).await.map_err(|e| (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(e)
-        ))?;
-        
-        Ok((StatusCode::OK, Json(ApiResponse {
-            success: true,
-            data: Some(format!("Successfully sent {} USDT to {}", payload.amount, wallet_address)),
-            error: None,
-        })))
-    } else {
-        Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(OpenBankError::SmartContractError { 
-                message: "Smart contract client not configured".to_string() 
-            })
-        ))
+    // The quote fixes exactly how much USDT is sent and what it costs. USDT
+    // accounts need none, and USD ones may still withdraw without one.
+    let quote = match &payload.quote_id {
+        Some(quote_id) => {
+            let quote = state.storage.get_quote(quote_id)?
+                .ok_or_else(|| OpenBankError::QuoteNotFound { quote_id: quote_id.clone() })?;
+            fx::check_quote(&quote, QuotePurpose::Withdrawal, &account.id, &amount, USDT)?;
+            if quote.chain_id != Some(contract_client.chain_id()) {
+                return Err(OpenBankError::QuoteMismatch {
+                    quote_id: quote.id,
+                    reason: format!("withdrawal fee was not priced for chain {}", contract_client.chain_id()),
+                });
+            }
+            Some(quote)
+        }
+        None if account.currency == USDT || account.currency == "USD" => None,
+        None => return Err(OpenBankError::FxQuoteRequired { from: account.currency.clone(), to: USDT.to_string() }),
+    };
+    let (usdt_amount, fees) = match &quote {
+        Some(quote) => (quote.converted_amount.clone(), quote.fees.clone()),
+        None => price_withdrawal(&state, &account, &amount, contract_client.chain_id()).await?,
+    };
+    let amount_usdt = usdt_amount.to_usdt_base_units()?;
+    let description = payload.description.unwrap_or_else(|| "API withdrawal".to_string());
+    
+    // Refuse while gas is above the configured ceiling, before any funds are held
+    contract_client
+        .check_usdt_transfer_gas(wallet_address.clone(), amount_usdt, description.clone())
+        .await?;
+    
+    // Limits count stored withdrawals, so no other withdrawal may be checked
+    // until this one is stored too. The chain is read before locking, so the
+    // lock only covers the checks and the insert.
+    let chain_outflow = state.limits.chain_outflow(state.storage.as_ref(), &contract_client).await?;
+    let _limits_guard = state.limits.lock().await;
+    state.limits.check_user(state.storage.as_ref(), &user, &usdt_amount)?;
+    state.limits.check_outflow(state.storage.as_ref(), contract_client.chain_id(), chain_outflow, &usdt_amount)?;
+    
+    // Reserve the funds now; fails if the balance can't cover it
+    let hold = state.storage.place_hold(&account.id, &amount)?;
+    let withdrawal_id = Uuid::new_v4().to_string();
+    let release = |e: OpenBankError| {
+        if let Err(release_error) = state.storage.release_hold(&hold.id) {
+            println!("Warning: Could not release hold {}: {:?}", hold.id, release_error);
+        }
+        e
+    };
+    
+    // Spend the quote only once the funds are held, so a short balance leaves it usable
+    if let Some(quote) = &quote {
+        state.storage.use_quote(&quote.id, &withdrawal_id).map_err(release)?;
     }
+    
+    // The background worker picks it up from here and sends it on-chain
+    let now = chrono::Utc::now();
+    let withdrawal = Withdrawal {
+        id: withdrawal_id,
+        user_id: account.user_id,
+        account_id: account.id,
+        wallet_address,
+        chain_id: Some(contract_client.chain_id()),
+        amount,
+        usdt_amount,
+        quote_id: quote.map(|quote| quote.id),
+        fees,
+        description,
+        hold_id: hold.id.clone(),
+        status: WithdrawalStatus::Requested,
+        tx_hash: None,
+        nonce: None,
+        replaced_tx_hashes: Vec::new(),
+        block_number: None,
+        gas_used: None,
+        transaction_id: None,
+        failure_reason: None,
+        created_at: now,
+        updated_at: now,
+    };
+    let completion = reservation.map(|reservation| reservation.completion(StatusCode::ACCEPTED));
+    state.storage.insert_withdrawal(&withdrawal, completion.as_ref()).map_err(release)?;
+    
+    Ok((StatusCode::ACCEPTED, Json(ApiResponse {
+        success: true,
+        data: Some(withdrawal),
+        error: None,
+    })))
+}
+
+async fn get_withdrawal(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(withdrawal_id): ApiPath<String>,
+) -> Result<(StatusCode, Json<ApiResponse<Withdrawal>>), OpenBankError> {
+    match state.storage.get_withdrawal(&withdrawal_id)? {
+        Some(withdrawal) => {
+            principal.require_read(&withdrawal.user_id)?;
+            Ok((StatusCode::OK, Json(ApiResponse {
+                success: true,
+                data: Some(withdrawal),
+                error: None,
+            })))
+        }
+        None => Err(OpenBankError::WithdrawalNotFound { withdrawal_id }),
+    }
+}
+
+async fn get_user_withdrawals(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(user_id): ApiPath<String>,
+) -> Result<(StatusCode, Json<ApiResponse<Vec<Withdrawal>>>), OpenBankError> {
+    principal.require_read(&user_id)?;
+    
+    if state.storage.get_user(&user_id)?.is_none() {
+        return Err(OpenBankError::UserNotFound { user_id });
+    }
+    
+    let withdrawals = state.storage.list_user_withdrawals(&user_id)?;
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(withdrawals),
+        error: None,
+    })))
+}
+
+// Client for the requested chain, or the default chain when none is given
+fn require_contract(
+    state: &AppState,
+    chain_id: Option<u64>,
+) -> Result<Arc<ContractClient>, OpenBankError> {
+    let contracts = state.contracts.as_ref().ok_or_else(|| OpenBankError::SmartContractError { 
+        message: "Smart contract client not configured".to_string() 
+    })?;
+    contracts.get(chain_id)
+}
+
+async fn get_contract_stats(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiQuery(query): ApiQuery<ChainQuery>,
+) -> Result<(StatusCode, Json<ApiResponse<ContractStats>>), OpenBankError> {
+    principal.require_staff()?;
+    let contract_client = require_contract(&state, query.chain_id)?;
+    
+    let stats = contract_client.get_contract_stats().await?;
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(stats),
+        error: None,
+    })))
+}
+
+async fn get_contract_transaction(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(transaction_id): ApiPath<u64>,
+    ApiQuery(query): ApiQuery<ChainQuery>,
+) -> Result<(StatusCode, Json<ApiResponse<ContractTransaction>>), OpenBankError> {
+    principal.require_staff()?;
+    let contract_client = require_contract(&state, query.chain_id)?;
+    
+    let transaction = contract_client.get_transaction(transaction_id).await?;
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(transaction),
+        error: None,
+    })))
+}
+
+async fn get_contract_user_transactions(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(wallet_address): ApiPath<String>,
+    ApiQuery(query): ApiQuery<ContractTransactionsQuery>,
+) -> Result<(StatusCode, Json<ApiResponse<Vec<ContractTransaction>>>), OpenBankError> {
+    principal.require_staff()?;
+    let contract_client = require_contract(&state, query.chain_id)?;
+    
+    let ids = contract_client
+        .get_user_transactions(wallet_address, query.limit.unwrap_or(20))
+        .await?;
+    let mut transactions = Vec::with_capacity(ids.len());
+    for id in ids {
+        transactions.push(contract_client.get_transaction(id).await?);
+    }
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(transactions),
+        error: None,
+    })))
+}
+
+async fn get_contract_available_balance(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(wallet_address): ApiPath<String>,
+    ApiQuery(query): ApiQuery<ChainQuery>,
+) -> Result<(StatusCode, Json<ApiResponse<String>>), OpenBankError> {
+    principal.require_staff()?;
+    let contract_client = require_contract(&state, query.chain_id)?;
+    
+    let available = contract_client.get_available_balance(wallet_address).await?;
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(available.to_string()), // USDT base units as a decimal string
+        error: None,
+    })))
+}
+
+// Served from the indexed events, so it works without touching the RPC node
+async fn get_contract_events(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiQuery(query): ApiQuery<ChainEventsQuery>,
+) -> Result<(StatusCode, Json<ApiResponse<Vec<ChainEvent>>>), OpenBankError> {
+    principal.require_staff()?;
+    
+    let events = state.storage
+        .list_chain_events(query.chain_id, query.kind, query.address.as_deref(), query.limit.unwrap_or(100))?;
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(events),
+        error: None,
+    })))
+}
+
+async fn get_reconciliation_report(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+) -> Result<(StatusCode, Json<ApiResponse<ReconciliationReport>>), OpenBankError> {
+    principal.require_staff()?;
+    
+    let report = state.storage
+        .latest_reconciliation_report()?
+        .ok_or(OpenBankError::ReconciliationReportNotFound)?;
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(report),
+        error: None,
+    })))
+}
+
+// Recomputed on every call from the journal
+async fn check_ledger(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+) -> Result<(StatusCode, Json<ApiResponse<LedgerCheckReport>>), OpenBankError> {
+    principal.require_staff()?;
+    
+    let report = ledger::check(state.storage.as_ref())?;
+    if !report.violations.is_empty() {
+        println!("Warning: Ledger check found {} violations", report.violations.len());
+    }
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(report),
+        error: None,
+    })))
+}
+
+// Limits per user tier, plus the global one. Tiers without their own limits use the defaults.
+async fn get_limits(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+) -> Result<(StatusCode, Json<ApiResponse<LimitSettings>>), OpenBankError> {
+    principal.require_staff()?;
+    
+    let settings = LimitSettings {
+        defaults: state.limits.defaults().clone(),
+        tiers: state.storage.list_tier_limits()?,
+        global: state.limits.global_limits(state.storage.as_ref())?,
+    };
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(settings),
+        error: None,
+    })))
+}
+
+async fn set_tier_limits(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(tier): ApiPath<String>,
+    ApiJson(payload): ApiJson<SetTierLimitsRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<TierLimits>>), OpenBankError> {
+    principal.require_operator()?;
+    limits::validate_tier(&tier)?;
+    
+    let tier_limits = TierLimits {
+        tier,
+        per_transaction: payload.per_transaction.as_deref().map(limits::parse_limit).transpose()?,
+        daily: payload.daily.as_deref().map(limits::parse_limit).transpose()?,
+        monthly: payload.monthly.as_deref().map(limits::parse_limit).transpose()?,
+        updated_at: Some(chrono::Utc::now()),
+    };
+    state.storage.set_tier_limits(&tier_limits)?;
+    println!("Withdrawal limits of tier {} set", tier_limits.tier);
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(tier_limits),
+        error: None,
+    })))
+}
+
+async fn set_global_limits(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiJson(payload): ApiJson<SetGlobalLimitsRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<GlobalLimits>>), OpenBankError> {
+    principal.require_operator()?;
+    
+    let global_limits = GlobalLimits {
+        hourly_outflow: payload.hourly_outflow.as_deref().map(limits::parse_limit).transpose()?,
+        updated_at: Some(chrono::Utc::now()),
+    };
+    state.storage.set_global_limits(&global_limits)?;
+    println!("Global withdrawal limits set");
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(global_limits),
+        error: None,
+    })))
+}
+
+async fn get_user_limits(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(user_id): ApiPath<String>,
+) -> Result<(StatusCode, Json<ApiResponse<UserLimits>>), OpenBankError> {
+    principal.require_staff()?;
+    let user = state.storage.get_user(&user_id)?
+        .ok_or(OpenBankError::UserNotFound { user_id })?;
+    
+    let (daily_used, monthly_used) = state.limits.usage(state.storage.as_ref(), &user.id)?;
+    let user_limits = UserLimits {
+        limits: state.limits.tier_limits(state.storage.as_ref(), &user.tier)?,
+        user_id: user.id,
+        daily_used,
+        monthly_used,
+    };
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(user_limits),
+        error: None,
+    })))
+}
+
+async fn set_user_tier(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiPath(user_id): ApiPath<String>,
+    ApiJson(payload): ApiJson<SetUserTierRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<User>>), OpenBankError> {
+    principal.require_operator()?;
+    limits::validate_tier(&payload.tier)?;
+    
+    state.storage.set_user_tier(&user_id, &payload.tier)?;
+    let user = state.storage.get_user(&user_id)?
+        .ok_or(OpenBankError::UserNotFound { user_id })?;
+    println!("User {} moved to tier {}", user.id, user.tier);
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: true,
+        data: Some(user),
+        error: None,
+    })))
+}
+
+// Admin routes, sent from the owner key
+async fn pause_contract(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiQuery(query): ApiQuery<ChainQuery>,
+) -> Result<(StatusCode, Json<ApiResponse<TxReceipt>>), OpenBankError> {
+    principal.require_operator()?;
+    let contract_client = require_contract(&state, query.chain_id)?;
+    
+    let receipt = contract_client.pause().await?;
+    println!("Contract on chain {} paused in {}", contract_client.chain_id(), receipt.tx_hash);
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: receipt.status == TxStatus::Success,
+        data: Some(receipt),
+        error: None,
+    })))
+}
+
+async fn unpause_contract(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiQuery(query): ApiQuery<ChainQuery>,
+) -> Result<(StatusCode, Json<ApiResponse<TxReceipt>>), OpenBankError> {
+    principal.require_operator()?;
+    let contract_client = require_contract(&state, query.chain_id)?;
+    
+    let receipt = contract_client.unpause().await?;
+    println!("Contract on chain {} unpaused in {}", contract_client.chain_id(), receipt.tx_hash);
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: receipt.status == TxStatus::Success,
+        data: Some(receipt),
+        error: None,
+    })))
+}
+
+async fn emergency_withdraw(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiQuery(query): ApiQuery<ChainQuery>,
+) -> Result<(StatusCode, Json<ApiResponse<TxReceipt>>), OpenBankError> {
+    principal.require_operator()?;
+    let contract_client = require_contract(&state, query.chain_id)?;
+    
+    let receipt = contract_client.emergency_withdraw().await?;
+    println!("Emergency withdrawal to owner on chain {} in {}", contract_client.chain_id(), receipt.tx_hash);
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: receipt.status == TxStatus::Success,
+        data: Some(receipt),
+        error: None,
+    })))
+}
+
+async fn set_usdt_token(
+    State(state): State<AppState>,
+    Extension(principal): Extension<Principal>,
+    ApiQuery(query): ApiQuery<ChainQuery>,
+    ApiJson(payload): ApiJson<SetUsdtTokenRequest>,
+) -> Result<(StatusCode, Json<ApiResponse<TxReceipt>>), OpenBankError> {
+    principal.require_operator()?;
+    let contract_client = require_contract(&state, query.chain_id)?;
+    
+    let receipt = contract_client.set_usdt_token(payload.token_address.clone()).await?;
+    println!("USDT token on chain {} set to {} in {}", contract_client.chain_id(), payload.token_address, receipt.tx_hash);
+    
+    Ok((StatusCode::OK, Json(ApiResponse {
+        success: receipt.status == TxStatus::Success,
+        data: Some(receipt),
+        error: None,
+    })))
 }
 
 async fn health_check() -> Json<ApiResponse<&'static str>> {
@@ -411,49 +1272,382 @@ async fn health_check() -> Json<ApiResponse<&'static str>> {
 #[tokio::main]
 async fn main() {
     println!("OnrampTee & OpenBank Mock API...");
+    dotenv().ok();
     
-    let state = AppState::new();
+    // Open storage and run pending migrations before serving anything
+    let storage = storage::from_env().expect("Failed to open storage. Please check STORAGE_BACKEND and DATABASE_PATH in your .env file");
+    let fx = Quoter::from_env().expect("Failed to load FX rates. Please check FX_RATES or FX_RATES_FILE and FX_SPREAD_BPS in your .env file");
+    println!("FX quotes priced from {}", fx.provider_name());
+    let fees = FeeSchedule::from_env().expect("Failed to load the fee schedule. Please check FEE_SCHEDULE or FEE_SCHEDULE_FILE in your .env file");
+    println!("Fee schedule has {} rules", fees.rule_count());
+    let limits = Limits::from_env().expect("Failed to load withdrawal limits. Please check the LIMIT_*_USDT settings in your .env file");
+    let state = AppState::new(storage, fx, fees, limits);
     
     // Initialize contract client (REQUIRED - API won't work without it)
-    let state = state.with_contract().await.expect("Failed to initialize smart contract integration. Please check your .env file with CONTRACT_ADDRESS, OWNER_PRIVATE_KEY, RPC_URL, and CHAIN_ID");
-    println!("Smart contract integration enabled!");
+    let state = state.with_contract().await.expect("Failed to initialize smart contract integration. Please check your .env file with CONTRACT_ADDRESS, RPC_URL and CHAIN_ID (or CHAINS), and the SIGNER_BACKEND settings");
+    if let Some(ref contracts) = state.contracts {
+        let chain_ids: Vec<u64> = contracts.all().map(|contract_client| contract_client.chain_id()).collect();
+        println!("Smart contract integration enabled on chains {:?}!", chain_ids);
+        for contract_client in contracts.all() {
+            println!("   Chain {} RPC endpoints: {}", contract_client.chain_id(), contract_client.rpc_endpoints().join(", "));
+            if let Some(ws_url) = contract_client.ws_url() {
+                println!("   Chain {} subscriptions via {}", contract_client.chain_id(), rpc::redact(ws_url));
+            }
+        }
+    }
+    
+    // Background jobs: withdrawal sending, event indexing per chain, reconciliation
+    // and sampling the contracts' outflow for the hourly limit
+    if let Some(ref contracts) = state.contracts {
+        withdrawals::spawn_worker(state.storage.clone(), contracts.clone());
+        for contract_client in contracts.all() {
+            indexer::spawn_indexer(state.storage.clone(), contract_client.clone());
+        }
+        reconciliation::spawn_reconciler(state.storage.clone(), contracts.clone(), state.fx.clone());
+        limits::spawn_sampler(state.limits.clone(), contracts.clone());
+    }
     
     // Configure CORS
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
-        .allow_headers(Any);
+        .allow_headers(Any)
+        .expose_headers([HeaderName::from_static(request_id::REQUEST_ID_HEADER)]);
     
     // Build router
     let app = Router::new()
-        //Openbank API mocking
-        .route("/health", get(health_check))
-        .route("/users", post(create_user))
-        .route("/users/{user_id}", get(get_user))
+        //Openbank API mocking, requires an API key
+        .route("/users/{user_id}", get(get_user).patch(update_user))
+        .route("/users/{user_id}/wallet/challenge", post(create_wallet_challenge))
+        .route("/users/{user_id}/wallet/verify", post(verify_wallet))
         .route("/users/{user_id}/accounts", get(get_user_accounts))
         .route("/users/register/{user_id}", post(create_account))
         .route("/accounts/{account_id}", get(get_account))
+        .route("/accounts/{account_id}/close", post(close_account))
         .route("/accounts/{account_id}/deposit", post(deposit))
         .route("/accounts/{account_id}/transactions", get(get_transactions))
+        .route("/transfers", post(transfer))
+        .route("/quotes", post(create_quote))
+        .route("/quotes/{quote_id}", get(get_quote))
         .route("/withdraw", post(withdraw_to_wallet))
+        .route("/withdrawals/{withdrawal_id}", get(get_withdrawal))
+        .route("/users/{user_id}/withdrawals", get(get_user_withdrawals))
+        .route("/contract/stats", get(get_contract_stats))
+        .route("/contract/transactions/{transaction_id}", get(get_contract_transaction))
+        .route("/contract/users/{wallet_address}/transactions", get(get_contract_user_transactions))
+        .route("/contract/users/{wallet_address}/available-balance", get(get_contract_available_balance))
+        .route("/contract/events", get(get_contract_events))
+        .route("/admin/contract/pause", post(pause_contract))
+        .route("/admin/contract/unpause", post(unpause_contract))
+        .route("/admin/contract/emergency-withdraw", post(emergency_withdraw))
+        .route("/admin/contract/usdt-token", post(set_usdt_token))
+        .route("/admin/reconciliation", get(get_reconciliation_report))
+        .route("/admin/ledger/check", get(check_ledger))
+        .route("/admin/limits", get(get_limits))
+        .route("/admin/limits/tiers/{tier}", put(set_tier_limits))
+        .route("/admin/limits/global", put(set_global_limits))
+        .route("/admin/users/{user_id}/limits", get(get_user_limits))
+        .route("/admin/users/{user_id}/tier", put(set_user_tier))
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth::authenticate))
+        
+        // Public routes
+        .route("/health", get(health_check))
+        .route("/users", post(create_user))
         
         //OnrampTee routes
         
         .layer(cors)
+        // Outermost, so even rejected requests carry an X-Request-Id
+        .layer(middleware::from_fn(request_id::assign))
         .with_state(state);
     
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
     println!("RampTee running on http://127.0.0.1:3000");
-    println!("Available endpoints:");
-    println!("   GET  /health - Health check");
-    println!("   POST /users - Create user");
+    println!("Available endpoints (Authorization: Bearer <api key> unless public):");
+    println!("   GET  /health - Health check (public)");
+    println!("   POST /users - Create user and issue its API key, plus a wallet challenge (public)");
     println!("   GET  /users/:user_id - Get user");
+    println!("   PATCH /users/:user_id - Update user email and name");
+    println!("   POST /users/:user_id/wallet/challenge - Get a nonce for a wallet to sign");
+    println!("   POST /users/:user_id/wallet/verify - Bind the wallet with a personal_sign or EIP-712 signature");
     println!("   GET  /users/:user_id/accounts - Get user accounts");
     println!("   POST /users/register/:user_id - Create account");
     println!("   GET  /accounts/:account_id - Get account");
-    println!("   POST /accounts/:account_id/deposit - Deposit money");
+    println!("   POST /accounts/:account_id/close - Close an empty account");
+    println!("   POST /accounts/:account_id/deposit - Deposit money (operator only)");
     println!("   GET  /accounts/:account_id/transactions - Get transaction history");
-    println!("   POST /withdraw - Withdraw USDT to user wallet (owner only)");
+    println!("   POST /transfers - Move fiat between accounts, with a quote_id (or fx_rate, operator only) between currencies");
+    println!("   POST /quotes - Lock an FX rate and fees for a withdrawal into USDT (default) or a transfer");
+    println!("   GET  /quotes/:quote_id - Get quote");
+    println!("   POST /withdraw - Withdraw USDT to user wallet, quote_id required unless the account is in USD (operator only)");
+    println!("   GET  /withdrawals/:withdrawal_id - Get withdrawal status");
+    println!("   GET  /users/:user_id/withdrawals - Get user withdrawals");
+    println!("   Contract and admin contract routes take ?chain_id=<id>, the first configured chain by default");
+    println!("   GET  /contract/stats - Get contract totals (operator/auditor)");
+    println!("   GET  /contract/transactions/:transaction_id - Get on-chain transaction (operator/auditor)");
+    println!("   GET  /contract/users/:wallet_address/transactions - Get on-chain user transactions (operator/auditor)");
+    println!("   GET  /contract/users/:wallet_address/available-balance - Get on-chain available balance (operator/auditor)");
+    println!("   GET  /contract/events - List indexed contract events, filter by kind/address (operator/auditor)");
+    println!("   POST /admin/contract/pause - Pause the contract (operator only)");
+    println!("   POST /admin/contract/unpause - Unpause the contract (operator only)");
+    println!("   POST /admin/contract/emergency-withdraw - Move all contract USDT to the owner (operator only)");
+    println!("   POST /admin/contract/usdt-token - Set the contract's USDT token (operator only)");
+    println!("   GET  /admin/reconciliation - Latest ledger/contract reconciliation report (operator/auditor)");
+    println!("   GET  /admin/ledger/check - Check the double-entry ledger invariants (operator/auditor)");
+    println!("   GET  /admin/limits - Withdrawal limits per tier and the global hourly outflow limit (operator/auditor)");
+    println!("   PUT  /admin/limits/tiers/:tier - Set a tier's per-transaction, daily and monthly USDT limits (operator only)");
+    println!("   PUT  /admin/limits/global - Set the hourly USDT outflow limit per chain (operator only)");
+    println!("   GET  /admin/users/:user_id/limits - A user's limits and what they used (operator/auditor)");
+    println!("   PUT  /admin/users/:user_id/tier - Move a user to another tier (operator only)");
     
     axum::serve(listener, app).await.unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::signers::{LocalWallet, Signer};
+    use ethers::types::Signature;
+    use crate::auth::Role;
+    use crate::storage::MemoryStorage;
+
+    fn test_state() -> AppState {
+        AppState::new(
+            Arc::new(MemoryStorage::new()),
+            Quoter::from_env().unwrap(),
+            FeeSchedule::default(),
+            Limits::from_env().unwrap(),
+        )
+    }
+
+    fn user_principal(user_id: &str) -> Principal {
+        Principal { role: Role::User, user_id: Some(user_id.to_string()) }
+    }
+
+    // A fresh account of `user_id`, funded by an operator deposit of `balance`
+    async fn open_account(state: &AppState, user_id: &str, currency: &str, balance: &str) -> Account {
+        if state.storage.get_user(user_id).unwrap().is_none() {
+            state.storage.insert_user(&User {
+                id: user_id.to_string(),
+                email: format!("{}@example.com", user_id),
+                name: user_id.to_string(),
+                wallet_address: None,
+                wallet_chain_id: None,
+                tier: DEFAULT_TIER.to_string(),
+                created_at: chrono::Utc::now(),
+                accounts: Vec::new(),
+            }).unwrap();
+        }
+        let (_, Json(response)) = create_account(
+            State(state.clone()),
+            Extension(user_principal(user_id)),
+            ApiPath(user_id.to_string()),
+            ApiJson(CreateAccountRequest { currency: currency.to_string() }),
+        ).await.unwrap();
+        let account = response.data.unwrap();
+        if balance != "0" {
+            let payload = DepositRequest { amount: balance.to_string(), description: None };
+            let _ = process_deposit(state.clone(), account.id.clone(), payload, None).await.unwrap();
+        }
+        state.storage.get_account(&account.id).unwrap().unwrap()
+    }
+
+    fn transfer_request(from: &Account, to: &Account, amount: &str) -> TransferRequest {
+        TransferRequest {
+            from_account_id: from.id.clone(),
+            to_account_id: to.id.clone(),
+            amount: amount.to_string(),
+            description: None,
+            quote_id: None,
+            fx_rate: None,
+        }
+    }
+
+    fn balance(state: &AppState, account_id: &str) -> String {
+        state.storage.get_account(account_id).unwrap().unwrap().balance.to_string()
+    }
+
+    #[tokio::test]
+    async fn same_currency_transfers_move_the_amount_as_is() {
+        let state = test_state();
+        let from = open_account(&state, "alice", "USD", "100").await;
+        let to = open_account(&state, "bob", "USD", "0").await;
+
+        let (_, Json(response)) = process_transfer(state.clone(), user_principal("alice"), transfer_request(&from, &to, "40.50"), None)
+            .await
+            .unwrap();
+        let transfer = response.data.unwrap();
+        assert_eq!(transfer.converted_amount.to_string(), "40.50 USD");
+        assert!(transfer.fx_rate.is_none() && transfer.fees.is_empty());
+        assert_eq!(transfer.debit.balance_after.to_string(), "59.50 USD");
+        assert_eq!(transfer.credit.balance_after.to_string(), "40.50 USD");
+        assert_eq!(balance(&state, &from.id), "59.50 USD");
+        assert_eq!(balance(&state, &to.id), "40.50 USD");
+        assert!(ledger::check(state.storage.as_ref()).unwrap().violations.is_empty());
+    }
+
+    #[tokio::test]
+    async fn cross_currency_transfers_need_a_quote() {
+        let state = test_state();
+        let from = open_account(&state, "alice", "USD", "100").await;
+        let to = open_account(&state, "alice", "EUR", "0").await;
+
+        let result = process_transfer(state.clone(), user_principal("alice"), transfer_request(&from, &to, "10"), None).await;
+        assert!(matches!(result, Err(OpenBankError::FxQuoteRequired { from, to }) if from == "USD" && to == "EUR"));
+
+        // Only operators may name a rate instead
+        let mut request = transfer_request(&from, &to, "10");
+        request.fx_rate = Some(money::Rate::parse("0.9").unwrap());
+        let result = process_transfer(state.clone(), user_principal("alice"), request, None).await;
+        assert!(matches!(result, Err(OpenBankError::Forbidden)));
+        assert_eq!(balance(&state, &from.id), "100.00 USD");
+        assert_eq!(balance(&state, &to.id), "0.00 EUR");
+    }
+
+    #[tokio::test]
+    async fn transfers_only_spend_the_available_balance() {
+        let state = test_state();
+        let from = open_account(&state, "alice", "USD", "100").await;
+        let to = open_account(&state, "bob", "USD", "0").await;
+        state.storage.place_hold(&from.id, &Money::parse("70", "USD").unwrap()).unwrap();
+
+        let result = process_transfer(state.clone(), user_principal("alice"), transfer_request(&from, &to, "30.01"), None).await;
+        assert!(matches!(result, Err(OpenBankError::InsufficientFunds { available, .. }) if available == "30.00 USD"));
+        assert_eq!(balance(&state, &from.id), "100.00 USD");
+        assert_eq!(balance(&state, &to.id), "0.00 USD");
+        assert!(state.storage.list_transactions(&to.id).unwrap().is_empty());
+
+        let _ = process_transfer(state.clone(), user_principal("alice"), transfer_request(&from, &to, "30"), None).await.unwrap();
+        assert_eq!(balance(&state, &from.id), "70.00 USD");
+    }
+
+    #[tokio::test]
+    async fn writes_made_before_a_failure_are_replayed_not_repeated() {
+        let state = test_state();
+        let from = open_account(&state, "alice", "USD", "100").await;
+        let to = open_account(&state, "alice", "USD", "0").await;
+        let calls = &std::cell::Cell::new(0);
+        let key = || Some("k".to_string());
+
+        // The deposit is written, then the request fails before answering
+        let deposit = |reservation| {
+            calls.set(calls.get() + 1);
+            let payload = DepositRequest { amount: "10".to_string(), description: None };
+            process_deposit(state.clone(), from.id.clone(), payload, reservation)
+        };
+        let result = idempotency::run_once(state.storage.as_ref(), key(), "deposit".to_string(), |reservation| async {
+            let _ = deposit(reservation).await?;
+            Err::<(StatusCode, Json<ApiResponse<Transaction>>), _>(OpenBankError::NoWalletAddress)
+        }).await;
+        assert!(matches!(result, Err(OpenBankError::NoWalletAddress)));
+
+        let (_, Json(response)) = idempotency::run_once(state.storage.as_ref(), key(), "deposit".to_string(), deposit).await.unwrap();
+        assert_eq!(response.data.unwrap().amount.to_string(), "10.00 USD");
+        assert_eq!(calls.get(), 1);
+        assert_eq!(balance(&state, &from.id), "110.00 USD");
+        assert_eq!(state.storage.list_transactions(&from.id).unwrap().len(), 2);
+
+        // Same for a transfer
+        let transfer = |reservation| {
+            calls.set(calls.get() + 1);
+            process_transfer(state.clone(), user_principal("alice"), transfer_request(&from, &to, "30"), reservation)
+        };
+        let key = || Some("t".to_string());
+        let result = idempotency::run_once(state.storage.as_ref(), key(), "transfer".to_string(), |reservation| async {
+            let _ = transfer(reservation).await?;
+            Err::<(StatusCode, Json<ApiResponse<Transfer>>), _>(OpenBankError::NoWalletAddress)
+        }).await;
+        assert!(matches!(result, Err(OpenBankError::NoWalletAddress)));
+
+        let (_, Json(response)) = idempotency::run_once(state.storage.as_ref(), key(), "transfer".to_string(), transfer).await.unwrap();
+        assert_eq!(response.data.unwrap().credit.balance_after.to_string(), "30.00 USD");
+        assert_eq!(calls.get(), 2);
+        assert_eq!((balance(&state, &from.id), balance(&state, &to.id)), ("80.00 USD".to_string(), "30.00 USD".to_string()));
+    }
+
+    #[tokio::test]
+    async fn accounts_close_only_when_empty_and_then_take_no_deposits() {
+        let state = test_state();
+        let account = open_account(&state, "alice", "USD", "25").await;
+        let other = open_account(&state, "alice", "USD", "0").await;
+        let close = |account: &Account| {
+            close_account(State(state.clone()), Extension(user_principal("alice")), ApiPath(account.id.clone()))
+        };
+
+        let result = close(&account).await;
+        assert!(matches!(result, Err(OpenBankError::AccountNotEmpty { balance, .. }) if balance == "25.00 USD"));
+        assert!(state.storage.get_account(&account.id).unwrap().unwrap().is_active);
+
+        let _ = process_transfer(state.clone(), user_principal("alice"), transfer_request(&account, &other, "25"), None).await.unwrap();
+        let (_, Json(response)) = close(&account).await.unwrap();
+        assert!(!response.data.unwrap().is_active);
+
+        let payload = DepositRequest { amount: "10".to_string(), description: None };
+        let result = process_deposit(state.clone(), account.id.clone(), payload, None).await;
+        assert!(matches!(result, Err(OpenBankError::AccountInactive { .. })));
+        assert_eq!(balance(&state, &account.id), "0.00 USD");
+        assert!(matches!(close(&account).await, Err(OpenBankError::AccountInactive { .. })));
+    }
+
+    #[tokio::test]
+    async fn wallets_rotate_only_with_a_signature_from_the_new_address() {
+        let state = test_state();
+        let _ = open_account(&state, "alice", "USD", "0").await;
+        let old_wallet: LocalWallet = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80".parse().unwrap();
+        let new_wallet: LocalWallet = "59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d".parse().unwrap();
+        let mut user = state.storage.get_user("alice").unwrap().unwrap();
+        user.wallet_address = Some(wallet::checksum(&old_wallet.address()));
+        state.storage.update_user(&user).unwrap();
+
+        let challenge = || async {
+            let payload = WalletChallengeRequest { wallet_address: wallet::checksum(&new_wallet.address()), wallet_chain_id: None };
+            let (_, Json(response)) = create_wallet_challenge(
+                State(state.clone()),
+                Extension(user_principal("alice")),
+                ApiPath("alice".to_string()),
+                ApiJson(payload),
+            ).await.unwrap();
+            response.data.unwrap()
+        };
+        let verify = |nonce: String, signature: Signature| {
+            let payload = VerifyWalletRequest { nonce, signature: signature.to_string(), signature_type: SignatureType::PersonalSign };
+            verify_wallet(State(state.clone()), Extension(user_principal("alice")), ApiPath("alice".to_string()), ApiJson(payload))
+        };
+
+        // The old wallet can't sign for the new one
+        let response = challenge().await;
+        let signature = old_wallet.sign_message(&response.message).await.unwrap();
+        let result = verify(response.challenge.nonce, signature).await;
+        assert!(matches!(result, Err(OpenBankError::InvalidWalletSignature { .. })));
+        let user = state.storage.get_user("alice").unwrap().unwrap();
+        assert_eq!(user.wallet_address, Some(wallet::checksum(&old_wallet.address())));
+
+        let response = challenge().await;
+        let signature = new_wallet.sign_message(&response.message).await.unwrap();
+        let (_, Json(verified)) = verify(response.challenge.nonce.clone(), signature).await.unwrap();
+        assert_eq!(verified.data.unwrap().wallet_address, Some(wallet::checksum(&new_wallet.address())));
+        // Each challenge is good for one attempt
+        let result = verify(response.challenge.nonce, signature).await;
+        assert!(matches!(result, Err(OpenBankError::WalletChallengeNotFound { .. })));
+    }
+
+    #[tokio::test]
+    async fn usdt_accounts_withdraw_without_conversion_but_pay_fees() {
+        let mut state = test_state();
+        state.fees = Arc::new(FeeSchedule::parse(
+            r#"[{"kind": "withdrawal", "currency": "USDT", "fixed": "1.50"}, {"kind": "conversion", "bps": 100}]"#,
+        ).unwrap());
+        let account = open_account(&state, "alice", USDT, "100").await;
+
+        let amount = Money::parse("40", USDT).unwrap();
+        let (usdt_amount, fees) = price_withdrawal(&state, &account, &amount, 1).await.unwrap();
+        assert_eq!(usdt_amount.to_string(), "38.500000 USDT");
+        // Nothing is converted, so only the withdrawal fee is charged
+        assert_eq!(fees.len(), 1);
+        assert_eq!(fees[0].kind, FeeKind::Withdrawal);
+        assert_eq!(fees[0].amount.to_string(), "1.500000 USDT");
+
+        let result = price_withdrawal(&state, &account, &Money::parse("1.5", USDT).unwrap(), 1).await;
+        assert!(matches!(result, Err(OpenBankError::AmountBelowFees { .. })));
+    }
+}
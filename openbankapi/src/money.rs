@@ -0,0 +1,362 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use crate::error::OpenBankError;
+
+pub const USDT: &str = "USDT";
+pub const USDT_DECIMALS: u32 = 6;
+
+// Currencies the onramp accepts and the number of decimals in their minor unit
+pub fn currency_decimals(currency: &str) -> Option<u32> {
+    match currency {
+        "USD" | "EUR" | "GBP" => Some(2),
+        USDT => Some(USDT_DECIMALS),
+        _ => None,
+    }
+}
+
+/// Fixed-point amount of a currency, stored as an integer count of minor units
+/// (cents for fiat, 10^-6 for USDT). Negative values are allowed so the type can
+/// also express balance changes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Money {
+    minor_units: i64,
+    decimals: u32,
+    currency: String,
+}
+
+impl Money {
+    pub fn zero(currency: &str) -> Result<Self, OpenBankError> {
+        Self::from_minor_units(0, currency)
+    }
+
+    pub fn from_minor_units(minor_units: i64, currency: &str) -> Result<Self, OpenBankError> {
+        let decimals = currency_decimals(currency)
+            .ok_or_else(|| OpenBankError::UnsupportedCurrency { currency: currency.to_string() })?;
+        Ok(Self { minor_units, decimals, currency: currency.to_string() })
+    }
+
+    /// Parses a decimal string such as "12.34" exactly. Amounts with more
+    /// fractional digits than the currency supports are rejected, never rounded.
+    pub fn parse(amount: &str, currency: &str) -> Result<Self, OpenBankError> {
+        let decimals = currency_decimals(currency)
+            .ok_or_else(|| OpenBankError::UnsupportedCurrency { currency: currency.to_string() })?;
+        let invalid = || OpenBankError::InvalidAmount { amount: amount.to_string() };
+
+        let (negative, digits) = match amount.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, amount),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+
+        if whole.is_empty() || !whole.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        if digits.contains('.') && fraction.is_empty() {
+            return Err(invalid());
+        }
+        if !fraction.bytes().all(|b| b.is_ascii_digit()) || fraction.len() > decimals as usize {
+            return Err(invalid());
+        }
+
+        let out_of_range = || OpenBankError::AmountOutOfRange { amount: amount.to_string() };
+        let mut minor_units: i64 = 0;
+        let padded = fraction.chars().chain(std::iter::repeat('0')).take(decimals as usize);
+        for c in whole.chars().chain(padded) {
+            let digit = c.to_digit(10).ok_or_else(invalid)? as i64;
+            minor_units = minor_units
+                .checked_mul(10)
+                .and_then(|v| v.checked_add(digit))
+                .ok_or_else(out_of_range)?;
+        }
+
+        if negative {
+            minor_units = -minor_units;
+        }
+        Ok(Self { minor_units, decimals, currency: currency.to_string() })
+    }
+
+    pub fn minor_units(&self) -> i64 {
+        self.minor_units
+    }
+
+    pub fn currency(&self) -> &str {
+        &self.currency
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.minor_units > 0
+    }
+
+    pub fn checked_add(&self, other: &Money) -> Result<Money, OpenBankError> {
+        self.ensure_same_currency(other)?;
+        let minor_units = self.minor_units
+            .checked_add(other.minor_units)
+            .ok_or_else(|| OpenBankError::AmountOutOfRange { amount: format!("{} + {}", self, other) })?;
+        Ok(Self { minor_units, ..self.clone() })
+    }
+
+    pub fn checked_sub(&self, other: &Money) -> Result<Money, OpenBankError> {
+        self.ensure_same_currency(other)?;
+        let minor_units = self.minor_units
+            .checked_sub(other.minor_units)
+            .ok_or_else(|| OpenBankError::AmountOutOfRange { amount: format!("{} - {}", self, other) })?;
+        Ok(Self { minor_units, ..self.clone() })
+    }
+
+    pub fn checked_neg(&self) -> Result<Money, OpenBankError> {
+        let minor_units = self.minor_units
+            .checked_neg()
+            .ok_or_else(|| OpenBankError::AmountOutOfRange { amount: self.to_string() })?;
+        Ok(Self { minor_units, ..self.clone() })
+    }
+
+    /// Converts the amount into the 6-decimal base unit used by the USDT
+    /// contract, failing instead of truncating when it cannot be represented.
+    pub fn to_usdt_base_units(&self) -> Result<u64, OpenBankError> {
+        let out_of_range = || OpenBankError::AmountOutOfRange { amount: self.to_string() };
+        if self.minor_units < 0 {
+            return Err(OpenBankError::InvalidAmount { amount: self.to_string() });
+        }
+
+        let minor_units = self.minor_units as u64;
+        if self.decimals <= USDT_DECIMALS {
+            let factor = 10u64.pow(USDT_DECIMALS - self.decimals);
+            minor_units.checked_mul(factor).ok_or_else(out_of_range)
+        } else {
+            let factor = 10u64.pow(self.decimals - USDT_DECIMALS);
+            if !minor_units.is_multiple_of(factor) {
+                return Err(OpenBankError::InvalidAmount { amount: self.to_string() });
+            }
+            Ok(minor_units / factor)
+        }
+    }
+
+    /// Converts a non-negative amount into `currency` at `rate`. The result is
+    /// rounded down to the target currency's minor unit, so conversion never
+    /// creates value.
+    pub fn convert(&self, rate: &Rate, currency: &str) -> Result<Money, OpenBankError> {
+        let decimals = currency_decimals(currency)
+            .ok_or_else(|| OpenBankError::UnsupportedCurrency { currency: currency.to_string() })?;
+        if self.minor_units < 0 {
+            return Err(OpenBankError::InvalidAmount { amount: self.to_string() });
+        }
+        let out_of_range = || OpenBankError::AmountOutOfRange { amount: format!("{} * {}", self, rate) };
+
+        // minor * rate * 10^decimals / 10^self.decimals, with the rate's own scale taken out
+        let numerator = (self.minor_units as u128)
+            .checked_mul(rate.mantissa)
+            .and_then(|v| v.checked_mul(10u128.pow(decimals)))
+            .ok_or_else(out_of_range)?;
+        let denominator = 10u128
+            .checked_pow(rate.scale + self.decimals)
+            .ok_or_else(out_of_range)?;
+        let minor_units = i64::try_from(numerator / denominator).map_err(|_| out_of_range())?;
+
+        Money::from_minor_units(minor_units, currency)
+    }
+
+    fn ensure_same_currency(&self, other: &Money) -> Result<(), OpenBankError> {
+        if self.currency != other.currency {
+            return Err(OpenBankError::CurrencyMismatch {
+                expected: self.currency.clone(),
+                found: other.currency.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    // Decimal representation without the currency code, e.g. "-12.30"
+    pub fn amount_string(&self) -> String {
+        let sign = if self.minor_units < 0 { "-" } else { "" };
+        let abs = self.minor_units.unsigned_abs();
+        if self.decimals == 0 {
+            return format!("{}{}", sign, abs);
+        }
+        let factor = 10u64.pow(self.decimals);
+        format!("{}{}.{:0width$}", sign, abs / factor, abs % factor, width = self.decimals as usize)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.amount_string(), self.currency)
+    }
+}
+
+// On the wire money is `{ "amount": "12.34", "currency": "USD" }` so clients
+// never round-trip amounts through floating point.
+#[derive(Serialize, Deserialize)]
+struct MoneyRepr {
+    amount: String,
+    currency: String,
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        MoneyRepr { amount: self.amount_string(), currency: self.currency.clone() }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = MoneyRepr::deserialize(deserializer)?;
+        Money::parse(&repr.amount, &repr.currency).map_err(serde::de::Error::custom)
+    }
+}
+
+// Most fractional digits an exchange rate may have
+const MAX_RATE_DECIMALS: u32 = 18;
+
+/// Exchange rate as an exact decimal: units of the target currency per unit of
+/// the source currency. Parsed like Money, never through floating point.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rate {
+    mantissa: u128,
+    scale: u32, // Number of fractional digits in the mantissa
+}
+
+impl Rate {
+    pub fn parse(rate: &str) -> Result<Self, OpenBankError> {
+        let invalid = || OpenBankError::InvalidRate { rate: rate.to_string() };
+        let (whole, fraction) = rate.split_once('.').unwrap_or((rate, ""));
+        if whole.is_empty() || !whole.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        if rate.contains('.') && fraction.is_empty() {
+            return Err(invalid());
+        }
+        if !fraction.bytes().all(|b| b.is_ascii_digit()) || fraction.len() > MAX_RATE_DECIMALS as usize {
+            return Err(invalid());
+        }
+
+        let mut mantissa: u128 = 0;
+        for c in whole.chars().chain(fraction.chars()) {
+            let digit = c.to_digit(10).ok_or_else(invalid)? as u128;
+            mantissa = mantissa.checked_mul(10).and_then(|v| v.checked_add(digit)).ok_or_else(invalid)?;
+        }
+        if mantissa == 0 {
+            return Err(invalid());
+        }
+        Ok(Self { mantissa, scale: fraction.len() as u32 })
+    }
+
+    /// The rate lowered by `bps` basis points, e.g. to take the onramp's spread.
+    /// Exact up to MAX_RATE_DECIMALS digits, rounded down beyond that.
+    pub fn less_bps(&self, bps: u32) -> Result<Rate, OpenBankError> {
+        let invalid = || OpenBankError::InvalidRate { rate: format!("{} less {} bps", self, bps) };
+        if bps >= 10_000 {
+            return Err(invalid());
+        }
+        let mut mantissa = self.mantissa.checked_mul(u128::from(10_000 - bps)).ok_or_else(invalid)?;
+        let mut scale = self.scale + 4;
+        while scale > 0 && (scale > MAX_RATE_DECIMALS || mantissa % 10 == 0) {
+            mantissa /= 10;
+            scale -= 1;
+        }
+        if mantissa == 0 {
+            return Err(invalid());
+        }
+        Ok(Self { mantissa, scale })
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.scale == 0 {
+            return write!(f, "{}", self.mantissa);
+        }
+        let factor = 10u128.pow(self.scale);
+        write!(f, "{}.{:0width$}", self.mantissa / factor, self.mantissa % factor, width = self.scale as usize)
+    }
+}
+
+impl Serialize for Rate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Rate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let rate = String::deserialize(deserializer)?;
+        Rate::parse(&rate).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Serde helpers for on-chain integers (`U256`), written as decimal strings so
+/// 18-decimal token amounts and large totals survive JSON clients intact.
+pub mod u256_decimal {
+    use ethers::types::U256;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &U256, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<U256, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        U256::from_dec_str(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usd(amount: &str) -> Money {
+        Money::parse(amount, "USD").unwrap()
+    }
+
+    #[test]
+    fn parse_is_exact() {
+        assert_eq!(usd("12.34").minor_units(), 1234);
+        assert_eq!(usd("12.3").minor_units(), 1230);
+        assert_eq!(usd("7").minor_units(), 700);
+        assert_eq!(usd("-0.05").minor_units(), -5);
+        assert_eq!(Money::parse("0.000001", USDT).unwrap().minor_units(), 1);
+        assert_eq!(usd("12.30").to_string(), "12.30 USD");
+    }
+
+    #[test]
+    fn parse_rejects_malformed_and_over_precise_amounts() {
+        for amount in ["", ".5", "5.", "1.234", "1e3", "+1", "1,00", " 1", "0x10"] {
+            assert!(matches!(Money::parse(amount, "USD"), Err(OpenBankError::InvalidAmount { .. })), "{}", amount);
+        }
+        assert!(matches!(Money::parse("92233720368547758.08", "USD"), Err(OpenBankError::AmountOutOfRange { .. })));
+        assert!(matches!(Money::parse("1", "XYZ"), Err(OpenBankError::UnsupportedCurrency { .. })));
+    }
+
+    #[test]
+    fn arithmetic_needs_the_same_currency() {
+        assert_eq!(usd("1.10").checked_add(&usd("2.25")).unwrap(), usd("3.35"));
+        assert_eq!(usd("1.10").checked_sub(&usd("2.25")).unwrap(), usd("-1.15"));
+        let eur = Money::parse("1", "EUR").unwrap();
+        assert!(matches!(usd("1").checked_add(&eur), Err(OpenBankError::CurrencyMismatch { .. })));
+    }
+
+    #[test]
+    fn convert_rounds_down_to_the_target_minor_unit() {
+        let rate = Rate::parse("1.0849999").unwrap();
+        // 10.01 * 1.0849999 = 10.860848999 USDT
+        assert_eq!(usd("10.01").convert(&rate, USDT).unwrap().amount_string(), "10.860848");
+        // 0.01 * 0.333 = 0.00333 USD
+        assert_eq!(usd("0.01").convert(&Rate::parse("0.333").unwrap(), "USD").unwrap().minor_units(), 0);
+        assert_eq!(usd("100").convert(&Rate::parse("1").unwrap(), USDT).unwrap().minor_units(), 100_000_000);
+        assert!(matches!(usd("-1").convert(&rate, USDT), Err(OpenBankError::InvalidAmount { .. })));
+    }
+
+    #[test]
+    fn rate_less_bps_stays_exact() {
+        let rate = Rate::parse("1.08").unwrap();
+        assert_eq!(rate.less_bps(50).unwrap().to_string(), "1.0746");
+        assert_eq!(rate.less_bps(0).unwrap(), rate);
+        assert!(rate.less_bps(10_000).is_err());
+        assert!(Rate::parse("0").is_err());
+    }
+
+    #[test]
+    fn usdt_base_units_never_truncate() {
+        assert_eq!(usd("1.23").to_usdt_base_units().unwrap(), 1_230_000);
+        assert!(usd("-1").to_usdt_base_units().is_err());
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::Utc;
+use ethers::types::U256;
+use uuid::Uuid;
+use crate::contract::ContractClients;
+use crate::error::OpenBankError;
+use crate::fx::Quoter;
+use crate::money::{Money, USDT};
+use crate::storage::Storage;
+use crate::types::{ChainEventKind, Discrepancy, DiscrepancyKind, ReconciliationReport, User, Withdrawal, WithdrawalStatus};
+
+const DEFAULT_INTERVAL_SECS: u64 = 300;
+
+// Starts the background task that periodically reconciles the fiat ledger
+// against the contract. Interval comes from RECONCILIATION_INTERVAL_SECS.
+pub fn spawn_reconciler(storage: Arc<dyn Storage>, contracts: Arc<ContractClients>, fx: Arc<Quoter>) {
+    let interval = std::env::var("RECONCILIATION_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_INTERVAL_SECS);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            match reconcile(storage.as_ref(), &contracts, &fx).await {
+                Ok(report) if !report.discrepancies.is_empty() => {
+                    println!(
+                        "Warning: Reconciliation {} found {} discrepancies",
+                        report.id,
+                        report.discrepancies.len()
+                    );
+                }
+                Ok(_) => {}
+                Err(e) => println!("Warning: Reconciliation failed: {:?}", e),
+            }
+        }
+    });
+}
+
+// Compares the USDT each user's settled withdrawals sent to every wallet they
+// used with the WithdrawalMade events the indexer stored for that wallet on the
+// chain it was paid on, and the contracts' USDT against all fiat balances at
+// the provider's mid rates. The contract keeps no per-recipient payout total,
+// so the events are the on-chain record; the indexer has to start at or before
+// the contract's first payout. The reserve is added up over every configured
+// chain, since one fiat balance can be withdrawn on any of them. The report is
+// stored even when everything matches.
+pub async fn reconcile(
+    storage: &dyn Storage,
+    contracts: &ContractClients,
+    fx: &Quoter,
+) -> Result<ReconciliationReport, OpenBankError> {
+    let mut discrepancies = Vec::new();
+    let mut liabilities = U256::zero();
+    let mut total_in_flight = U256::zero();
+    let mut users_checked = 0;
+    let default_chain_id = contracts.get(None)?.chain_id();
+    let chain_ids: Vec<u64> = contracts.all().map(|contract_client| contract_client.chain_id()).collect();
+    let indexed = IndexedWithdrawals::load(storage, &chain_ids)?;
+
+    for user in storage.list_users()? {
+        for account in storage.list_user_accounts(&user.id)? {
+            liabilities += U256::from(usdt_value(fx, &account.balance).await?.to_usdt_base_units()?);
+        }
+
+        let wallets = user_wallets(&user, &storage.list_user_withdrawals(&user.id)?, &chain_ids, default_chain_id, &indexed)?;
+        if !wallets.is_empty() {
+            users_checked += 1;
+        }
+        for ((chain_id, wallet), tally) in wallets {
+            total_in_flight += tally.in_flight;
+            if !chain_ids.contains(&chain_id) {
+                println!(
+                    "Warning: Skipping withdrawals of user {} on chain {}, which is no longer configured",
+                    user.id, chain_id
+                );
+                continue;
+            }
+            let withdrawn = indexed.totals.get(&(chain_id, wallet)).copied().unwrap_or_default();
+            discrepancies.extend(compare_withdrawn(&user.id, chain_id, tally, withdrawn));
+        }
+    }
+
+    let mut contract_usdt_balance = U256::zero();
+    for contract_client in contracts.all() {
+        contract_usdt_balance += contract_client.get_usdt_reserve().await?;
+    }
+    if contract_usdt_balance + total_in_flight < liabilities {
+        discrepancies.push(Discrepancy {
+            kind: DiscrepancyKind::ReserveShortfall,
+            user_id: None,
+            wallet_address: None,
+            expected: liabilities,
+            actual: contract_usdt_balance,
+            message: format!(
+                "Contract holds {} USDT base units ({} in flight) against {} owed",
+                contract_usdt_balance, total_in_flight, liabilities
+            ),
+        });
+    }
+
+    let report = ReconciliationReport {
+        id: Uuid::new_v4().to_string(),
+        generated_at: Utc::now(),
+        chain_ids,
+        users_checked,
+        contract_usdt_balance,
+        outstanding_liabilities: liabilities,
+        in_flight: total_in_flight,
+        discrepancies,
+    };
+    storage.insert_reconciliation_report(&report)?;
+    Ok(report)
+}
+
+// WithdrawalMade events stored so far: their totals per chain and lowercased
+// recipient, and the last block indexed on each chain
+#[derive(Debug, Default)]
+struct IndexedWithdrawals {
+    totals: BTreeMap<(u64, String), U256>,
+    indexed_to: BTreeMap<u64, u64>,
+}
+
+impl IndexedWithdrawals {
+    fn load(storage: &dyn Storage, chain_ids: &[u64]) -> Result<Self, OpenBankError> {
+        let mut indexed = Self::default();
+        for &chain_id in chain_ids {
+            if let Some(cursor) = storage.get_indexer_cursor(chain_id)? {
+                indexed.indexed_to.insert(chain_id, cursor.block_number);
+            }
+            // SQLite takes the limit as an i64
+            let events = storage.list_chain_events(Some(chain_id), Some(ChainEventKind::WithdrawalMade), None, i64::MAX as usize)?;
+            for event in events {
+                *indexed.totals.entry((chain_id, event.address.to_lowercase())).or_default() += event.amount;
+            }
+        }
+        Ok(indexed)
+    }
+
+    // Whether the indexer has already gone past the block a withdrawal was mined in
+    fn covers(&self, chain_id: u64, block_number: Option<u64>) -> bool {
+        match self.indexed_to.get(&chain_id) {
+            Some(indexed_to) => block_number.is_none_or(|block_number| block_number <= *indexed_to),
+            None => false,
+        }
+    }
+}
+
+// USDT a user's withdrawals sent to one wallet on one chain
+#[derive(Debug, Default, PartialEq)]
+struct WalletTally {
+    wallet_address: String,
+    debited: U256,
+    unindexed: U256, // Part of `debited` mined after the last indexed block
+    in_flight: U256,
+}
+
+// Settled and in-flight withdrawals keyed by chain and lowercased wallet.
+// Withdrawals from before multi-chain support went out on the default chain.
+// Submitted ones are broadcast but not settled yet, so the chain may be
+// ahead of the ledger by up to their amount.
+fn tally_withdrawals(
+    withdrawals: &[Withdrawal],
+    default_chain_id: u64,
+    indexed: &IndexedWithdrawals,
+) -> Result<BTreeMap<(u64, String), WalletTally>, OpenBankError> {
+    let mut wallets: BTreeMap<(u64, String), WalletTally> = BTreeMap::new();
+    for withdrawal in withdrawals {
+        let amount = U256::from(withdrawal.usdt_amount.to_usdt_base_units()?);
+        let chain_id = withdrawal.chain_id.unwrap_or(default_chain_id);
+        let (debited, in_flight) = match withdrawal.status {
+            WithdrawalStatus::Confirmed => (amount, U256::zero()),
+            WithdrawalStatus::Submitted => (U256::zero(), amount),
+            _ => continue,
+        };
+        let tally = wallets
+            .entry((chain_id, withdrawal.wallet_address.to_lowercase()))
+            .or_insert_with(|| WalletTally { wallet_address: withdrawal.wallet_address.clone(), ..WalletTally::default() });
+        tally.debited += debited;
+        tally.in_flight += in_flight;
+        if !debited.is_zero() && !indexed.covers(chain_id, withdrawal.block_number) {
+            tally.unindexed += debited;
+        }
+    }
+    Ok(wallets)
+}
+
+// The user's tallies, plus the current wallet on its chain (or every chain)
+// even before its first withdrawal. Withdrawals stay with the wallet and chain
+// they were sent to, so a rotated wallet keeps being compared with what was sent to it.
+fn user_wallets(
+    user: &User,
+    withdrawals: &[Withdrawal],
+    chain_ids: &[u64],
+    default_chain_id: u64,
+    indexed: &IndexedWithdrawals,
+) -> Result<BTreeMap<(u64, String), WalletTally>, OpenBankError> {
+    let mut wallets = tally_withdrawals(withdrawals, default_chain_id, indexed)?;
+    if let Some(ref wallet_address) = user.wallet_address {
+        let wallet_chain_ids = match user.wallet_chain_id {
+            Some(chain_id) => vec![chain_id],
+            None => chain_ids.to_vec(),
+        };
+        for chain_id in wallet_chain_ids {
+            wallets.entry((chain_id, wallet_address.to_lowercase())).or_insert_with(|| WalletTally {
+                wallet_address: wallet_address.clone(),
+                ..WalletTally::default()
+            });
+        }
+    }
+    Ok(wallets)
+}
+
+// A mismatch unless the indexed payouts to the wallet are at least what the
+// ledger settled (less what the indexer hasn't reached yet) and at most that
+// plus what is in flight
+fn compare_withdrawn(user_id: &str, chain_id: u64, tally: WalletTally, withdrawn: U256) -> Option<Discrepancy> {
+    let low = tally.debited - tally.unindexed;
+    let high = tally.debited + tally.in_flight;
+    if withdrawn >= low && withdrawn <= high {
+        return None;
+    }
+    Some(Discrepancy {
+        kind: DiscrepancyKind::WithdrawnMismatch,
+        message: format!(
+            "Ledger debited {} ({} not indexed yet, {} in flight) but WithdrawalMade events on chain {} add up to {}",
+            tally.debited, tally.unindexed, tally.in_flight, chain_id, withdrawn
+        ),
+        user_id: Some(user_id.to_string()),
+        wallet_address: Some(tally.wallet_address),
+        expected: tally.debited,
+        actual: withdrawn,
+    })
+}
+
+// What a fiat balance is worth in USDT at the mid rate
+async fn usdt_value(fx: &Quoter, balance: &Money) -> Result<Money, OpenBankError> {
+    if balance.currency() == USDT {
+        return Ok(balance.clone());
+    }
+    let rate = fx.mid_rate(balance.currency(), USDT).await?;
+    balance.convert(&rate, USDT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{ChainEventRepository, MemoryStorage};
+    use crate::types::{ChainEvent, IndexerCursor, DEFAULT_TIER};
+
+    fn withdrawal(wallet_address: &str, chain_id: Option<u64>, usdt: &str, status: WithdrawalStatus) -> Withdrawal {
+        let now = Utc::now();
+        Withdrawal {
+            id: Uuid::new_v4().to_string(),
+            user_id: "user".to_string(),
+            account_id: "account".to_string(),
+            wallet_address: wallet_address.to_string(),
+            chain_id,
+            amount: Money::parse(usdt, "USD").unwrap(),
+            usdt_amount: Money::parse(usdt, USDT).unwrap(),
+            quote_id: None,
+            fees: Vec::new(),
+            description: "test".to_string(),
+            hold_id: "hold".to_string(),
+            status,
+            tx_hash: None,
+            nonce: None,
+            replaced_tx_hashes: Vec::new(),
+            block_number: None,
+            gas_used: None,
+            transaction_id: None,
+            failure_reason: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn user(wallet_address: Option<&str>) -> User {
+        User {
+            id: "user".to_string(),
+            email: "user@example.com".to_string(),
+            name: "User".to_string(),
+            wallet_address: wallet_address.map(str::to_string),
+            wallet_chain_id: None,
+            tier: DEFAULT_TIER.to_string(),
+            created_at: Utc::now(),
+            accounts: Vec::new(),
+        }
+    }
+
+    fn mined(mut withdrawal: Withdrawal, block_number: u64) -> Withdrawal {
+        withdrawal.block_number = Some(block_number);
+        withdrawal
+    }
+
+    fn withdrawal_made(chain_id: u64, to: &str, usdt: &str, block_number: u64) -> ChainEvent {
+        let tx_hash = format!("0x{:064x}", block_number);
+        ChainEvent {
+            id: format!("{}:{}:0", chain_id, tx_hash),
+            chain_id,
+            kind: ChainEventKind::WithdrawalMade,
+            address: to.to_lowercase(),
+            amount: U256::from(Money::parse(usdt, USDT).unwrap().to_usdt_base_units().unwrap()),
+            description: None,
+            timestamp: None,
+            block_number,
+            block_hash: format!("0x{:064x}", block_number),
+            tx_hash,
+            log_index: 0,
+        }
+    }
+
+    fn index(storage: &MemoryStorage, chain_id: u64, events: &[ChainEvent], block_number: u64) {
+        let cursor = IndexerCursor { chain_id, block_number, block_hash: format!("0x{:064x}", block_number) };
+        storage.record_chain_events(events, &cursor).unwrap();
+    }
+
+    // The discrepancies of the user's wallets against what the storage has indexed
+    fn compare(storage: &MemoryStorage, user: &User, withdrawals: &[Withdrawal]) -> Vec<Discrepancy> {
+        let indexed = IndexedWithdrawals::load(storage, &[1]).unwrap();
+        user_wallets(user, withdrawals, &[1], 1, &indexed)
+            .unwrap()
+            .into_iter()
+            .filter_map(|((chain_id, wallet), tally)| {
+                let withdrawn = indexed.totals.get(&(chain_id, wallet)).copied().unwrap_or_default();
+                compare_withdrawn(&user.id, chain_id, tally, withdrawn)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn confirmed_withdrawals_are_compared_with_indexed_payouts() {
+        let wallet = "0x00000000000000000000000000000000000000aA";
+        let user = user(Some(wallet));
+        let confirmed = mined(withdrawal(wallet, Some(1), "10", WithdrawalStatus::Confirmed), 5);
+
+        // Nothing indexed yet: the withdrawal can't be checked, and isn't flagged
+        let storage = MemoryStorage::new();
+        assert!(compare(&storage, &user, std::slice::from_ref(&confirmed)).is_empty());
+
+        // The indexer passed its block without seeing the payout
+        index(&storage, 1, &[], 8);
+        let discrepancies = compare(&storage, &user, std::slice::from_ref(&confirmed));
+        assert_eq!(discrepancies.len(), 1);
+        assert_eq!(discrepancies[0].kind, DiscrepancyKind::WithdrawnMismatch);
+        assert_eq!((discrepancies[0].expected, discrepancies[0].actual), (U256::from(10_000_000), U256::zero()));
+
+        // Once its event is stored the ledger and the chain agree
+        index(&storage, 1, &[withdrawal_made(1, wallet, "10", 9)], 9);
+        assert!(compare(&storage, &user, std::slice::from_ref(&confirmed)).is_empty());
+        // A payout on another chain doesn't count for this one
+        index(&storage, 2, &[withdrawal_made(2, wallet, "10", 3)], 3);
+        assert!(compare(&storage, &user, std::slice::from_ref(&confirmed)).is_empty());
+
+        // A submitted withdrawal may already be on chain, a payout the ledger never made may not
+        let submitted = withdrawal(wallet, Some(1), "4", WithdrawalStatus::Submitted);
+        index(&storage, 1, &[withdrawal_made(1, wallet, "4", 10)], 10);
+        assert!(compare(&storage, &user, &[confirmed.clone(), submitted]).is_empty());
+        let discrepancies = compare(&storage, &user, std::slice::from_ref(&confirmed));
+        assert_eq!(discrepancies[0].actual, U256::from(14_000_000));
+    }
+
+    #[test]
+    fn withdrawals_are_tallied_per_wallet_and_chain() {
+        let old = "0x00000000000000000000000000000000000000aA";
+        let new = "0x00000000000000000000000000000000000000bB";
+        let wallets = tally_withdrawals(&[
+            withdrawal(old, Some(1), "10", WithdrawalStatus::Confirmed),
+            withdrawal(&old.to_lowercase(), Some(1), "5", WithdrawalStatus::Confirmed),
+            withdrawal(old, Some(10), "7", WithdrawalStatus::Confirmed),
+            withdrawal(new, Some(1), "3", WithdrawalStatus::Confirmed),
+            withdrawal(new, Some(1), "2", WithdrawalStatus::Submitted),
+            withdrawal(new, Some(1), "100", WithdrawalStatus::Failed),
+            withdrawal(new, Some(1), "100", WithdrawalStatus::Requested),
+            withdrawal(new, None, "1", WithdrawalStatus::Confirmed),
+        ], 1, &IndexedWithdrawals::default())
+        .unwrap();
+
+        let tally = |chain_id, wallet: &str| &wallets[&(chain_id, wallet.to_lowercase())];
+        assert_eq!(wallets.len(), 3);
+        assert_eq!(tally(1, old).wallet_address, old);
+        assert_eq!((tally(1, old).debited, tally(1, old).in_flight), (U256::from(15_000_000), U256::zero()));
+        assert_eq!(tally(10, old).debited, U256::from(7_000_000));
+        // Nothing is indexed, so no settled amount is expected on chain yet
+        assert_eq!(tally(1, old).unindexed, tally(1, old).debited);
+        assert_eq!((tally(1, new).debited, tally(1, new).in_flight), (U256::from(4_000_000), U256::from(2_000_000)));
+    }
+}
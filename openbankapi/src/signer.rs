@@ -0,0 +1,275 @@
+use async_trait::async_trait;
+use ethers::core::types::{
+    transaction::{eip2718::TypedTransaction, eip712::Eip712},
+    Address, Signature, H256,
+};
+use ethers::signers::{to_eip155_v, LocalWallet, Signer, WalletError};
+use ethers::utils::hash_message;
+use serde::Deserialize;
+use serde_json::json;
+use std::fmt;
+use thiserror::Error;
+use crate::error::OpenBankError;
+
+// Where the owner key lives, picked with SIGNER_BACKEND:
+//   local    - OWNER_PRIVATE_KEY (default)
+//   keystore - encrypted JSON keystore at KEYSTORE_PATH, unlocked with
+//              KEYSTORE_PASSWORD or the contents of KEYSTORE_PASSWORD_FILE
+//   remote   - HTTP signer at REMOTE_SIGNER_URL (e.g. inside the TEE), with an
+//              optional bearer token in REMOTE_SIGNER_TOKEN
+#[derive(Clone)]
+pub enum SignerConfig {
+    Local { private_key: String },
+    Keystore { path: String, password: String },
+    Remote { url: String, token: Option<String> },
+}
+
+// Keys and passwords never end up in logs
+impl fmt::Debug for SignerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignerConfig::Local { .. } => f.write_str("Local"),
+            SignerConfig::Keystore { path, .. } => f.debug_struct("Keystore").field("path", path).finish(),
+            SignerConfig::Remote { url, .. } => f.debug_struct("Remote").field("url", url).finish(),
+        }
+    }
+}
+
+impl SignerConfig {
+    pub fn from_env() -> Result<Self, OpenBankError> {
+        let backend = std::env::var("SIGNER_BACKEND").unwrap_or_else(|_| "local".to_string());
+
+        match backend.as_str() {
+            "local" => Ok(SignerConfig::Local { private_key: required_env("OWNER_PRIVATE_KEY")? }),
+            "keystore" => {
+                let password = match std::env::var("KEYSTORE_PASSWORD_FILE") {
+                    Ok(file) => std::fs::read_to_string(&file)
+                        .map(|password| password.trim_end_matches(['\r', '\n']).to_string())
+                        .map_err(|e| OpenBankError::SmartContractError {
+                            message: format!("Failed to read KEYSTORE_PASSWORD_FILE {}: {}", file, e),
+                        })?,
+                    Err(_) => required_env("KEYSTORE_PASSWORD")?,
+                };
+                Ok(SignerConfig::Keystore { path: required_env("KEYSTORE_PATH")?, password })
+            }
+            "remote" => Ok(SignerConfig::Remote {
+                url: required_env("REMOTE_SIGNER_URL")?.trim_end_matches('/').to_string(),
+                token: std::env::var("REMOTE_SIGNER_TOKEN").ok(),
+            }),
+            other => Err(OpenBankError::SmartContractError {
+                message: format!("Unknown SIGNER_BACKEND: {}", other),
+            }),
+        }
+    }
+}
+
+fn required_env(name: &str) -> Result<String, OpenBankError> {
+    std::env::var(name).map_err(|_| OpenBankError::SmartContractError {
+        message: format!("{} not found in .env file", name),
+    })
+}
+
+#[derive(Debug, Error)]
+pub enum SignerError {
+    #[error(transparent)]
+    Wallet(#[from] WalletError),
+
+    #[error("Remote signer error: {0}")]
+    Remote(String),
+}
+
+// The owner key behind whichever backend is configured. Keystores decrypt to
+// a local wallet, so only the remote signer needs its own implementation.
+#[derive(Debug)]
+pub enum OwnerSigner {
+    Local(LocalWallet),
+    Remote(RemoteSigner),
+}
+
+impl OwnerSigner {
+    pub async fn connect(config: SignerConfig, chain_id: u64) -> Result<Self, OpenBankError> {
+        let signer = match config {
+            SignerConfig::Local { private_key } => {
+                let wallet = private_key
+                    .parse::<LocalWallet>()
+                    .map_err(|e| OpenBankError::SmartContractError {
+                        message: format!("Invalid private key: {}", e),
+                    })?;
+                OwnerSigner::Local(wallet)
+            }
+            SignerConfig::Keystore { path, password } => {
+                // Scrypt is slow on purpose, keep it off the async runtime
+                let wallet = tokio::task::spawn_blocking(move || LocalWallet::decrypt_keystore(&path, password))
+                    .await
+                    .map_err(|e| OpenBankError::SmartContractError {
+                        message: format!("Keystore decryption task failed: {}", e),
+                    })?
+                    .map_err(|e| OpenBankError::SmartContractError {
+                        message: format!("Failed to decrypt keystore: {}", e),
+                    })?;
+                OwnerSigner::Local(wallet)
+            }
+            SignerConfig::Remote { url, token } => {
+                let signer = RemoteSigner::connect(url, token).await.map_err(|e| OpenBankError::SmartContractError {
+                    message: e.to_string(),
+                })?;
+                OwnerSigner::Remote(signer)
+            }
+        };
+
+        Ok(signer.with_chain_id(chain_id))
+    }
+}
+
+#[async_trait]
+impl Signer for OwnerSigner {
+    type Error = SignerError;
+
+    async fn sign_message<S: Send + Sync + AsRef<[u8]>>(&self, message: S) -> Result<Signature, Self::Error> {
+        match self {
+            OwnerSigner::Local(wallet) => Ok(wallet.sign_message(message).await?),
+            OwnerSigner::Remote(remote) => remote.sign_message(message.as_ref()).await,
+        }
+    }
+
+    async fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Signature, Self::Error> {
+        match self {
+            OwnerSigner::Local(wallet) => Ok(wallet.sign_transaction(tx).await?),
+            OwnerSigner::Remote(remote) => remote.sign_transaction(tx).await,
+        }
+    }
+
+    async fn sign_typed_data<T: Eip712 + Send + Sync>(&self, payload: &T) -> Result<Signature, Self::Error> {
+        match self {
+            OwnerSigner::Local(wallet) => Ok(wallet.sign_typed_data(payload).await?),
+            OwnerSigner::Remote(_) => Err(SignerError::Remote("typed data signing is not supported".to_string())),
+        }
+    }
+
+    fn address(&self) -> Address {
+        match self {
+            OwnerSigner::Local(wallet) => wallet.address(),
+            OwnerSigner::Remote(remote) => remote.address,
+        }
+    }
+
+    fn chain_id(&self) -> u64 {
+        match self {
+            OwnerSigner::Local(wallet) => wallet.chain_id(),
+            OwnerSigner::Remote(remote) => remote.chain_id,
+        }
+    }
+
+    fn with_chain_id<T: Into<u64>>(self, chain_id: T) -> Self {
+        match self {
+            OwnerSigner::Local(wallet) => OwnerSigner::Local(wallet.with_chain_id(chain_id)),
+            OwnerSigner::Remote(remote) => OwnerSigner::Remote(RemoteSigner { chain_id: chain_id.into(), ..remote }),
+        }
+    }
+}
+
+// Signs over HTTP; the key never leaves the signing service.
+//   GET  /address           -> { "address": "0x..." }
+//   POST /sign/transaction  { chain_id, transaction, hash } -> { "signature": "0x<65 bytes>" }
+//   POST /sign/message      { message (hex), hash } -> { "signature": "0x<65 bytes>" }
+// Every signature is checked against the address before it is used.
+pub struct RemoteSigner {
+    http: reqwest::Client,
+    url: String,
+    token: Option<String>,
+    address: Address,
+    chain_id: u64,
+}
+
+impl fmt::Debug for RemoteSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteSigner")
+            .field("url", &self.url)
+            .field("address", &self.address)
+            .field("chain_id", &self.chain_id)
+            .finish()
+    }
+}
+
+#[derive(Deserialize)]
+struct AddressResponse {
+    address: Address,
+}
+
+#[derive(Deserialize)]
+struct SignatureResponse {
+    signature: String,
+}
+
+impl RemoteSigner {
+    async fn connect(url: String, token: Option<String>) -> Result<Self, SignerError> {
+        let http = reqwest::Client::new();
+        let mut request = http.get(format!("{}/address", url));
+        if let Some(ref token) = token {
+            request = request.bearer_auth(token);
+        }
+        let response: AddressResponse = send(request).await?;
+
+        Ok(Self { http, url, token, address: response.address, chain_id: 1 })
+    }
+
+    async fn sign_transaction(&self, tx: &TypedTransaction) -> Result<Signature, SignerError> {
+        // Same chain id rules as LocalWallet: the signer's unless the transaction has one
+        let mut tx = tx.clone();
+        let chain_id = tx.chain_id().map(|id| id.as_u64()).unwrap_or(self.chain_id);
+        tx.set_chain_id(chain_id);
+        let hash = tx.sighash();
+
+        let body = json!({ "chain_id": chain_id, "transaction": tx, "hash": hash });
+        let mut signature = self.request_signature("sign/transaction", body, hash).await?;
+
+        // Legacy transactions carry the chain id in v (EIP-155)
+        if matches!(tx, TypedTransaction::Legacy(_)) {
+            let recovery_id = signature.recovery_id().map_err(|e| SignerError::Remote(e.to_string()))?;
+            signature.v = to_eip155_v(recovery_id.to_byte(), chain_id);
+        }
+        Ok(signature)
+    }
+
+    async fn sign_message(&self, message: &[u8]) -> Result<Signature, SignerError> {
+        let hash = hash_message(message);
+        let body = json!({ "message": format!("0x{}", hex::encode(message)), "hash": hash });
+        self.request_signature("sign/message", body, hash).await
+    }
+
+    async fn request_signature(
+        &self,
+        path: &str,
+        body: serde_json::Value,
+        hash: H256,
+    ) -> Result<Signature, SignerError> {
+        let mut request = self.http.post(format!("{}/{}", self.url, path)).json(&body);
+        if let Some(ref token) = self.token {
+            request = request.bearer_auth(token);
+        }
+        let response: SignatureResponse = send(request).await?;
+
+        let signature = response
+            .signature
+            .parse::<Signature>()
+            .map_err(|e| SignerError::Remote(format!("invalid signature: {}", e)))?;
+        let signer = signature
+            .recover(hash)
+            .map_err(|e| SignerError::Remote(format!("unrecoverable signature: {}", e)))?;
+        if signer != self.address {
+            return Err(SignerError::Remote(format!("signature is from {:?}, expected {:?}", signer, self.address)));
+        }
+        Ok(signature)
+    }
+}
+
+async fn send<T: serde::de::DeserializeOwned>(request: reqwest::RequestBuilder) -> Result<T, SignerError> {
+    request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| SignerError::Remote(e.to_string()))?
+        .json()
+        .await
+        .map_err(|e| SignerError::Remote(format!("invalid response: {}", e)))
+}
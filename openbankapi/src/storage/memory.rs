@@ -0,0 +1,695 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::error::OpenBankError;
+use crate::ledger;
+use crate::money::Money;
+use crate::types::{
+    Account, ApiKey, ChainEvent, ChainEventKind, Hold, HoldStatus, IdempotencyCompletion, IdempotencyRecord, IndexerCursor,
+    JournalEntry, GlobalLimits, PostingSide, Quote, ReconciliationReport, TierLimits, Transaction, Transfer, User,
+    WalletChallenge, Withdrawal, WithdrawalStatus,
+};
+use super::{
+    is_stale_reservation, response_body, AccountRepository, ApiKeyRepository, ChainEventRepository, HoldRepository, IdempotencyRepository, LedgerRepository,
+    LimitRepository, QuoteRepository, ReconciliationRepository, TransactionRepository, UserRepository, WalletChallengeRepository,
+    WithdrawalRepository,
+};
+
+// In-memory storage, lost on restart. Used for tests and local experiments.
+// Locks are always taken in field order to avoid deadlocks.
+#[derive(Default)]
+pub struct MemoryStorage {
+    users: RwLock<HashMap<String, User>>,
+    accounts: RwLock<HashMap<String, Account>>,
+    journal: RwLock<Vec<JournalEntry>>,
+    transactions: RwLock<HashMap<String, Vec<Transaction>>>,
+    holds: RwLock<HashMap<String, Hold>>,
+    idempotency_keys: RwLock<HashMap<String, IdempotencyRecord>>,
+    withdrawals: RwLock<HashMap<String, Withdrawal>>,
+    api_keys: RwLock<HashMap<String, ApiKey>>,
+    wallet_challenges: RwLock<HashMap<String, WalletChallenge>>,
+    quotes: RwLock<HashMap<String, Quote>>,
+    tier_limits: RwLock<HashMap<String, TierLimits>>,
+    global_limits: RwLock<Option<GlobalLimits>>,
+    chain_events: RwLock<ChainEventLog>,
+    reconciliation_reports: RwLock<Vec<ReconciliationReport>>,
+}
+
+// Indexed contract events in chain order, plus the indexer's cursor per chain
+#[derive(Default)]
+struct ChainEventLog {
+    events: Vec<ChainEvent>,
+    cursors: HashMap<u64, IndexerCursor>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+// Mirrors the UNIQUE columns of the SQLite `users` table, failing with the
+// same error SQLite reports so both backends reject the same duplicates
+fn ensure_unique_user(users: &HashMap<String, User>, user: &User) -> Result<(), OpenBankError> {
+    for other in users.values().filter(|other| other.id != user.id) {
+        let column = if other.email == user.email {
+            "email"
+        } else if other.wallet_address.is_some() && other.wallet_address == user.wallet_address {
+            "wallet_address"
+        } else {
+            continue;
+        };
+        return Err(OpenBankError::StorageError {
+            message: format!("UNIQUE constraint failed: users.{}", column),
+        });
+    }
+    Ok(())
+}
+
+// Records a journal entry and moves the balances of the user accounts it posts
+// to. Every account is checked before any balance changes.
+fn post_entry(
+    accounts: &mut HashMap<String, Account>,
+    journal: &mut Vec<JournalEntry>,
+    entry: &JournalEntry,
+) -> Result<(), OpenBankError> {
+    ledger::ensure_balanced(entry)?;
+
+    let mut balances: HashMap<String, Money> = HashMap::new();
+    for posting in &entry.postings {
+        let Some(account_id) = ledger::user_account_id(&posting.ledger_account) else {
+            continue;
+        };
+        let account = accounts
+            .get(account_id)
+            .ok_or_else(|| OpenBankError::AccountNotFound { account_id: account_id.to_string() })?;
+        if !account.is_active {
+            return Err(OpenBankError::AccountInactive { account_id: account.id.clone() });
+        }
+        let balance = balances.get(account_id).unwrap_or(&account.balance);
+        let balance = match posting.side {
+            PostingSide::Credit => balance.checked_add(&posting.amount)?,
+            PostingSide::Debit => balance.checked_sub(&posting.amount)?,
+        };
+        balances.insert(account_id.to_string(), balance);
+    }
+
+    for (account_id, balance) in balances {
+        if let Some(account) = accounts.get_mut(&account_id) {
+            account.balance = balance;
+        }
+    }
+    journal.push(entry.clone());
+    Ok(())
+}
+
+// Records a transaction whose entry is already posted, with locks already held
+fn record_transaction(
+    accounts: &HashMap<String, Account>,
+    transactions: &mut HashMap<String, Vec<Transaction>>,
+    mut transaction: Transaction,
+) -> Result<Transaction, OpenBankError> {
+    let account = accounts
+        .get(&transaction.account_id)
+        .ok_or_else(|| OpenBankError::AccountNotFound { account_id: transaction.account_id.clone() })?;
+    transaction.balance_after = account.balance.clone();
+
+    transactions
+        .entry(transaction.account_id.clone())
+        .or_default()
+        .push(transaction.clone());
+
+    Ok(transaction)
+}
+
+// Posts the entry behind a transaction and records the transaction, with locks already held
+fn apply_to_account(
+    accounts: &mut HashMap<String, Account>,
+    journal: &mut Vec<JournalEntry>,
+    transactions: &mut HashMap<String, Vec<Transaction>>,
+    transaction: Transaction,
+    entry: &JournalEntry,
+) -> Result<Transaction, OpenBankError> {
+    if !accounts.contains_key(&transaction.account_id) {
+        return Err(OpenBankError::AccountNotFound { account_id: transaction.account_id.clone() });
+    }
+    post_entry(accounts, journal, entry)?;
+    record_transaction(accounts, transactions, transaction)
+}
+
+// Whether `existing` is still the in-progress reservation made at `reserved_at`
+fn is_own_reservation(existing: &IdempotencyRecord, reserved_at: DateTime<Utc>) -> bool {
+    existing.response_status.is_none() && existing.created_at == reserved_at
+}
+
+// Fails unless the completion's reservation is still in progress. Checked
+// before a write, so a request that lost its key changes nothing.
+fn check_reservation(
+    keys: &HashMap<String, IdempotencyRecord>,
+    completion: Option<&IdempotencyCompletion>,
+) -> Result<(), OpenBankError> {
+    match completion {
+        Some(completion) if !keys.get(&completion.key).is_some_and(|existing| is_own_reservation(existing, completion.reserved_at)) => {
+            Err(OpenBankError::IdempotencyRequestInProgress { key: completion.key.clone() })
+        }
+        _ => Ok(()),
+    }
+}
+
+// Stores `data` as the response of a checked reservation, with the lock already held
+fn complete_reservation<T: serde::Serialize>(
+    keys: &mut HashMap<String, IdempotencyRecord>,
+    completion: Option<&IdempotencyCompletion>,
+    data: &T,
+) -> Result<(), OpenBankError> {
+    if let Some(completion) = completion
+        && let Some(record) = keys.get_mut(&completion.key)
+    {
+        record.response_status = Some(completion.status);
+        record.response_body = Some(response_body(data)?);
+    }
+    Ok(())
+}
+
+// Takes an active hold out of the account's held balance and marks it with `status`
+fn close_hold(
+    accounts: &mut HashMap<String, Account>,
+    holds: &mut HashMap<String, Hold>,
+    hold_id: &str,
+    status: HoldStatus,
+) -> Result<Hold, OpenBankError> {
+    let hold = holds
+        .get_mut(hold_id)
+        .filter(|hold| hold.status == HoldStatus::Active)
+        .ok_or_else(|| OpenBankError::HoldNotFound { hold_id: hold_id.to_string() })?;
+    let account = accounts
+        .get_mut(&hold.account_id)
+        .ok_or_else(|| OpenBankError::AccountNotFound { account_id: hold.account_id.clone() })?;
+
+    account.held_balance = account.held_balance.checked_sub(&hold.amount)?;
+    hold.status = status;
+    hold.updated_at = Utc::now();
+    Ok(hold.clone())
+}
+
+// Debits an active hold by recording `transaction` against its account
+fn capture(
+    accounts: &mut HashMap<String, Account>,
+    journal: &mut Vec<JournalEntry>,
+    transactions: &mut HashMap<String, Vec<Transaction>>,
+    holds: &mut HashMap<String, Hold>,
+    hold_id: &str,
+    mut transaction: Transaction,
+    entry: &JournalEntry,
+) -> Result<Transaction, OpenBankError> {
+    let hold = holds
+        .get(hold_id)
+        .filter(|hold| hold.status == HoldStatus::Active)
+        .ok_or_else(|| OpenBankError::HoldNotFound { hold_id: hold_id.to_string() })?;
+    transaction.account_id = hold.account_id.clone();
+    transaction.amount = hold.amount.checked_neg()?;
+
+    let transaction = apply_to_account(accounts, journal, transactions, transaction, entry)?;
+    close_hold(accounts, holds, hold_id, HoldStatus::Captured)?;
+    Ok(transaction)
+}
+
+impl UserRepository for MemoryStorage {
+    fn insert_user(&self, user: &User) -> Result<(), OpenBankError> {
+        let mut users = self.users.write().unwrap();
+        if users.contains_key(&user.id) {
+            return Err(OpenBankError::StorageError {
+                message: "UNIQUE constraint failed: users.id".to_string(),
+            });
+        }
+        ensure_unique_user(&users, user)?;
+        users.insert(user.id.clone(), user.clone());
+        Ok(())
+    }
+
+    fn get_user(&self, user_id: &str) -> Result<Option<User>, OpenBankError> {
+        let users = self.users.read().unwrap();
+        Ok(users.get(user_id).cloned())
+    }
+
+    fn find_user_by_email(&self, email: &str) -> Result<Option<User>, OpenBankError> {
+        let users = self.users.read().unwrap();
+        Ok(users.values().find(|u| u.email == email).cloned())
+    }
+
+    fn find_user_by_wallet(&self, wallet_address: &str) -> Result<Option<User>, OpenBankError> {
+        let users = self.users.read().unwrap();
+        Ok(users
+            .values()
+            .find(|u| u.wallet_address.as_deref().is_some_and(|address| address.eq_ignore_ascii_case(wallet_address)))
+            .cloned())
+    }
+
+    fn update_user(&self, user: &User) -> Result<(), OpenBankError> {
+        let mut users = self.users.write().unwrap();
+        if users.contains_key(&user.id) {
+            ensure_unique_user(&users, user)?;
+        }
+        let stored = users
+            .get_mut(&user.id)
+            .ok_or_else(|| OpenBankError::UserNotFound { user_id: user.id.clone() })?;
+        stored.email = user.email.clone();
+        stored.name = user.name.clone();
+        stored.wallet_address = user.wallet_address.clone();
+        stored.wallet_chain_id = user.wallet_chain_id;
+        Ok(())
+    }
+
+    fn set_user_tier(&self, user_id: &str, tier: &str) -> Result<(), OpenBankError> {
+        let mut users = self.users.write().unwrap();
+        let stored = users
+            .get_mut(user_id)
+            .ok_or_else(|| OpenBankError::UserNotFound { user_id: user_id.to_string() })?;
+        stored.tier = tier.to_string();
+        Ok(())
+    }
+
+    fn list_users(&self) -> Result<Vec<User>, OpenBankError> {
+        let users = self.users.read().unwrap();
+        let mut users: Vec<User> = users.values().cloned().collect();
+        users.sort_by_key(|u| u.created_at);
+        Ok(users)
+    }
+}
+
+impl AccountRepository for MemoryStorage {
+    fn insert_account(&self, account: &Account) -> Result<(), OpenBankError> {
+        let mut users = self.users.write().unwrap();
+        let user = users
+            .get_mut(&account.user_id)
+            .ok_or_else(|| OpenBankError::UserNotFound { user_id: account.user_id.clone() })?;
+        user.accounts.push(account.id.clone());
+
+        self.accounts.write().unwrap().insert(account.id.clone(), account.clone());
+        self.transactions.write().unwrap().insert(account.id.clone(), Vec::new());
+        Ok(())
+    }
+
+    fn get_account(&self, account_id: &str) -> Result<Option<Account>, OpenBankError> {
+        let accounts = self.accounts.read().unwrap();
+        Ok(accounts.get(account_id).cloned())
+    }
+
+    fn list_user_accounts(&self, user_id: &str) -> Result<Vec<Account>, OpenBankError> {
+        let users = self.users.read().unwrap();
+        let accounts = self.accounts.read().unwrap();
+
+        Ok(users
+            .get(user_id)
+            .map(|user| {
+                user.accounts
+                    .iter()
+                    .filter_map(|account_id| accounts.get(account_id).cloned())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    fn close_account(&self, account_id: &str) -> Result<Account, OpenBankError> {
+        let mut accounts = self.accounts.write().unwrap();
+        let account = accounts
+            .get_mut(account_id)
+            .ok_or_else(|| OpenBankError::AccountNotFound { account_id: account_id.to_string() })?;
+        if !account.is_active {
+            return Err(OpenBankError::AccountInactive { account_id: account_id.to_string() });
+        }
+        if account.balance.minor_units() != 0 || account.held_balance.minor_units() != 0 {
+            return Err(OpenBankError::AccountNotEmpty {
+                account_id: account_id.to_string(),
+                balance: account.balance.to_string(),
+            });
+        }
+        account.is_active = false;
+        Ok(account.clone())
+    }
+}
+
+impl TransactionRepository for MemoryStorage {
+    fn apply_transaction(
+        &self,
+        transaction: Transaction,
+        entry: &JournalEntry,
+        completion: Option<&IdempotencyCompletion>,
+    ) -> Result<Transaction, OpenBankError> {
+        let mut accounts = self.accounts.write().unwrap();
+        let mut journal = self.journal.write().unwrap();
+        let mut transactions = self.transactions.write().unwrap();
+        let mut keys = self.idempotency_keys.write().unwrap();
+        check_reservation(&keys, completion)?;
+
+        let transaction = apply_to_account(&mut accounts, &mut journal, &mut transactions, transaction, entry)?;
+        complete_reservation(&mut keys, completion, &transaction)?;
+        Ok(transaction)
+    }
+
+    fn apply_transfer(
+        &self,
+        mut transfer: Transfer,
+        entry: &JournalEntry,
+        completion: Option<&IdempotencyCompletion>,
+    ) -> Result<Transfer, OpenBankError> {
+        let mut accounts = self.accounts.write().unwrap();
+        let mut journal = self.journal.write().unwrap();
+        let mut transactions = self.transactions.write().unwrap();
+        let mut keys = self.idempotency_keys.write().unwrap();
+        check_reservation(&keys, completion)?;
+
+        let (debit, credit) = (&transfer.debit, &transfer.credit);
+        let from = accounts
+            .get(&debit.account_id)
+            .ok_or_else(|| OpenBankError::AccountNotFound { account_id: debit.account_id.clone() })?;
+        let available = from.available_balance()?;
+        if available.checked_add(&debit.amount)?.minor_units() < 0 {
+            return Err(OpenBankError::InsufficientFunds {
+                account_id: from.id.clone(),
+                available: available.to_string(),
+                requested: debit.amount.checked_neg()?.to_string(),
+            });
+        }
+        if !accounts.contains_key(&credit.account_id) {
+            return Err(OpenBankError::AccountNotFound { account_id: credit.account_id.clone() });
+        }
+
+        post_entry(&mut accounts, &mut journal, entry)?;
+        transfer.debit = record_transaction(&accounts, &mut transactions, transfer.debit)?;
+        transfer.credit = record_transaction(&accounts, &mut transactions, transfer.credit)?;
+        complete_reservation(&mut keys, completion, &transfer)?;
+        Ok(transfer)
+    }
+
+    fn list_transactions(&self, account_id: &str) -> Result<Vec<Transaction>, OpenBankError> {
+        let transactions = self.transactions.read().unwrap();
+        Ok(transactions.get(account_id).cloned().unwrap_or_default())
+    }
+}
+
+impl LedgerRepository for MemoryStorage {
+    fn list_journal_entries(&self) -> Result<Vec<JournalEntry>, OpenBankError> {
+        Ok(self.journal.read().unwrap().clone())
+    }
+}
+
+impl HoldRepository for MemoryStorage {
+    fn place_hold(&self, account_id: &str, amount: &Money) -> Result<Hold, OpenBankError> {
+        let mut accounts = self.accounts.write().unwrap();
+        let mut holds = self.holds.write().unwrap();
+
+        let account = accounts
+            .get_mut(account_id)
+            .ok_or_else(|| OpenBankError::AccountNotFound { account_id: account_id.to_string() })?;
+        if !account.is_active {
+            return Err(OpenBankError::AccountInactive { account_id: account_id.to_string() });
+        }
+        let available = account.available_balance()?;
+        if available.checked_sub(amount)?.minor_units() < 0 {
+            return Err(OpenBankError::InsufficientFunds {
+                account_id: account_id.to_string(),
+                available: available.to_string(),
+                requested: amount.to_string(),
+            });
+        }
+        account.held_balance = account.held_balance.checked_add(amount)?;
+
+        let now = Utc::now();
+        let hold = Hold {
+            id: Uuid::new_v4().to_string(),
+            account_id: account_id.to_string(),
+            amount: amount.clone(),
+            status: HoldStatus::Active,
+            created_at: now,
+            updated_at: now,
+        };
+        holds.insert(hold.id.clone(), hold.clone());
+        Ok(hold)
+    }
+
+    fn release_hold(&self, hold_id: &str) -> Result<Hold, OpenBankError> {
+        let mut accounts = self.accounts.write().unwrap();
+        let mut holds = self.holds.write().unwrap();
+        close_hold(&mut accounts, &mut holds, hold_id, HoldStatus::Released)
+    }
+
+    fn capture_hold(&self, hold_id: &str, transaction: Transaction, entry: &JournalEntry) -> Result<Transaction, OpenBankError> {
+        let mut accounts = self.accounts.write().unwrap();
+        let mut journal = self.journal.write().unwrap();
+        let mut transactions = self.transactions.write().unwrap();
+        let mut holds = self.holds.write().unwrap();
+        capture(&mut accounts, &mut journal, &mut transactions, &mut holds, hold_id, transaction, entry)
+    }
+}
+
+impl IdempotencyRepository for MemoryStorage {
+    fn reserve_idempotency_key(
+        &self,
+        record: &IdempotencyRecord,
+        stale_before: DateTime<Utc>,
+    ) -> Result<Option<IdempotencyRecord>, OpenBankError> {
+        let mut keys = self.idempotency_keys.write().unwrap();
+        if let Some(existing) = keys.get(&record.key)
+            && !is_stale_reservation(existing, record, stale_before)
+        {
+            return Ok(Some(existing.clone()));
+        }
+        keys.insert(record.key.clone(), record.clone());
+        Ok(None)
+    }
+
+    fn complete_idempotency_key(&self, completion: &IdempotencyCompletion, body: &str) -> Result<(), OpenBankError> {
+        let mut keys = self.idempotency_keys.write().unwrap();
+        check_reservation(&keys, Some(completion))?;
+        if let Some(record) = keys.get_mut(&completion.key) {
+            record.response_status = Some(completion.status);
+            record.response_body = Some(body.to_string());
+        }
+        Ok(())
+    }
+
+    fn remove_idempotency_key(&self, key: &str, reserved_at: DateTime<Utc>) -> Result<(), OpenBankError> {
+        let mut keys = self.idempotency_keys.write().unwrap();
+        if keys.get(key).is_some_and(|existing| is_own_reservation(existing, reserved_at)) {
+            keys.remove(key);
+        }
+        Ok(())
+    }
+}
+
+impl WithdrawalRepository for MemoryStorage {
+    fn insert_withdrawal(&self, withdrawal: &Withdrawal, completion: Option<&IdempotencyCompletion>) -> Result<(), OpenBankError> {
+        let mut keys = self.idempotency_keys.write().unwrap();
+        let mut withdrawals = self.withdrawals.write().unwrap();
+        check_reservation(&keys, completion)?;
+        withdrawals.insert(withdrawal.id.clone(), withdrawal.clone());
+        complete_reservation(&mut keys, completion, withdrawal)
+    }
+
+    fn get_withdrawal(&self, withdrawal_id: &str) -> Result<Option<Withdrawal>, OpenBankError> {
+        let withdrawals = self.withdrawals.read().unwrap();
+        Ok(withdrawals.get(withdrawal_id).cloned())
+    }
+
+    fn list_user_withdrawals(&self, user_id: &str) -> Result<Vec<Withdrawal>, OpenBankError> {
+        let withdrawals = self.withdrawals.read().unwrap();
+        let mut result: Vec<Withdrawal> = withdrawals.values().filter(|w| w.user_id == user_id).cloned().collect();
+        result.sort_by_key(|w| w.created_at);
+        Ok(result)
+    }
+
+    fn list_pending_withdrawals(&self) -> Result<Vec<Withdrawal>, OpenBankError> {
+        let withdrawals = self.withdrawals.read().unwrap();
+        let mut result: Vec<Withdrawal> = withdrawals
+            .values()
+            .filter(|w| matches!(w.status, WithdrawalStatus::Requested | WithdrawalStatus::Submitted))
+            .cloned()
+            .collect();
+        result.sort_by_key(|w| w.created_at);
+        Ok(result)
+    }
+
+    fn update_withdrawal(&self, withdrawal: &Withdrawal) -> Result<(), OpenBankError> {
+        let mut withdrawals = self.withdrawals.write().unwrap();
+        let stored = withdrawals
+            .get_mut(&withdrawal.id)
+            .ok_or_else(|| OpenBankError::WithdrawalNotFound { withdrawal_id: withdrawal.id.clone() })?;
+        *stored = withdrawal.clone();
+        Ok(())
+    }
+
+    fn settle_withdrawal(
+        &self,
+        withdrawal: &Withdrawal,
+        transaction: Transaction,
+        entry: &JournalEntry,
+    ) -> Result<Transaction, OpenBankError> {
+        let mut accounts = self.accounts.write().unwrap();
+        let mut journal = self.journal.write().unwrap();
+        let mut transactions = self.transactions.write().unwrap();
+        let mut holds = self.holds.write().unwrap();
+        let mut withdrawals = self.withdrawals.write().unwrap();
+
+        let stored = withdrawals
+            .get_mut(&withdrawal.id)
+            .ok_or_else(|| OpenBankError::WithdrawalNotFound { withdrawal_id: withdrawal.id.clone() })?;
+        let transaction = capture(
+            &mut accounts,
+            &mut journal,
+            &mut transactions,
+            &mut holds,
+            &withdrawal.hold_id,
+            transaction,
+            entry,
+        )?;
+        *stored = withdrawal.clone();
+        Ok(transaction)
+    }
+
+    fn cancel_withdrawal(&self, withdrawal: &Withdrawal) -> Result<(), OpenBankError> {
+        let mut accounts = self.accounts.write().unwrap();
+        let mut holds = self.holds.write().unwrap();
+        let mut withdrawals = self.withdrawals.write().unwrap();
+
+        let stored = withdrawals
+            .get_mut(&withdrawal.id)
+            .ok_or_else(|| OpenBankError::WithdrawalNotFound { withdrawal_id: withdrawal.id.clone() })?;
+        close_hold(&mut accounts, &mut holds, &withdrawal.hold_id, HoldStatus::Released)?;
+        *stored = withdrawal.clone();
+        Ok(())
+    }
+}
+
+impl ApiKeyRepository for MemoryStorage {
+    fn insert_api_key(&self, api_key: &ApiKey) -> Result<(), OpenBankError> {
+        let mut api_keys = self.api_keys.write().unwrap();
+        api_keys.insert(api_key.key_hash.clone(), api_key.clone());
+        Ok(())
+    }
+
+    fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, OpenBankError> {
+        let api_keys = self.api_keys.read().unwrap();
+        Ok(api_keys.get(key_hash).cloned())
+    }
+}
+
+impl WalletChallengeRepository for MemoryStorage {
+    fn insert_wallet_challenge(&self, challenge: &WalletChallenge) -> Result<(), OpenBankError> {
+        let mut challenges = self.wallet_challenges.write().unwrap();
+        challenges.insert(challenge.nonce.clone(), challenge.clone());
+        Ok(())
+    }
+
+    fn take_wallet_challenge(&self, nonce: &str) -> Result<Option<WalletChallenge>, OpenBankError> {
+        Ok(self.wallet_challenges.write().unwrap().remove(nonce))
+    }
+}
+
+impl QuoteRepository for MemoryStorage {
+    fn insert_quote(&self, quote: &Quote) -> Result<(), OpenBankError> {
+        self.quotes.write().unwrap().insert(quote.id.clone(), quote.clone());
+        Ok(())
+    }
+
+    fn get_quote(&self, quote_id: &str) -> Result<Option<Quote>, OpenBankError> {
+        Ok(self.quotes.read().unwrap().get(quote_id).cloned())
+    }
+
+    fn use_quote(&self, quote_id: &str, used_by: &str) -> Result<Quote, OpenBankError> {
+        let mut quotes = self.quotes.write().unwrap();
+        let quote = quotes
+            .get_mut(quote_id)
+            .ok_or_else(|| OpenBankError::QuoteNotFound { quote_id: quote_id.to_string() })?;
+        if quote.used_by.is_some() {
+            return Err(OpenBankError::QuoteAlreadyUsed { quote_id: quote_id.to_string() });
+        }
+        quote.used_by = Some(used_by.to_string());
+        Ok(quote.clone())
+    }
+}
+
+impl LimitRepository for MemoryStorage {
+    fn list_tier_limits(&self) -> Result<Vec<TierLimits>, OpenBankError> {
+        let mut limits: Vec<TierLimits> = self.tier_limits.read().unwrap().values().cloned().collect();
+        limits.sort_by(|a, b| a.tier.cmp(&b.tier));
+        Ok(limits)
+    }
+
+    fn get_tier_limits(&self, tier: &str) -> Result<Option<TierLimits>, OpenBankError> {
+        Ok(self.tier_limits.read().unwrap().get(tier).cloned())
+    }
+
+    fn set_tier_limits(&self, limits: &TierLimits) -> Result<(), OpenBankError> {
+        self.tier_limits.write().unwrap().insert(limits.tier.clone(), limits.clone());
+        Ok(())
+    }
+
+    fn get_global_limits(&self) -> Result<Option<GlobalLimits>, OpenBankError> {
+        Ok(self.global_limits.read().unwrap().clone())
+    }
+
+    fn set_global_limits(&self, limits: &GlobalLimits) -> Result<(), OpenBankError> {
+        *self.global_limits.write().unwrap() = Some(limits.clone());
+        Ok(())
+    }
+}
+
+impl ChainEventRepository for MemoryStorage {
+    fn get_indexer_cursor(&self, chain_id: u64) -> Result<Option<IndexerCursor>, OpenBankError> {
+        Ok(self.chain_events.read().unwrap().cursors.get(&chain_id).cloned())
+    }
+
+    fn record_chain_events(&self, events: &[ChainEvent], cursor: &IndexerCursor) -> Result<(), OpenBankError> {
+        let mut log = self.chain_events.write().unwrap();
+        for event in events {
+            if !log.events.iter().any(|e| e.id == event.id) {
+                log.events.push(event.clone());
+            }
+        }
+        log.cursors.insert(cursor.chain_id, cursor.clone());
+        Ok(())
+    }
+
+    fn rewind_chain_events(&self, chain_id: u64, cursor: Option<&IndexerCursor>) -> Result<(), OpenBankError> {
+        let mut log = self.chain_events.write().unwrap();
+        log.events.retain(|e| e.chain_id != chain_id || cursor.is_some_and(|cursor| e.block_number <= cursor.block_number));
+        match cursor {
+            Some(cursor) => log.cursors.insert(chain_id, cursor.clone()),
+            None => log.cursors.remove(&chain_id),
+        };
+        Ok(())
+    }
+
+    fn list_chain_events(
+        &self,
+        chain_id: Option<u64>,
+        kind: Option<ChainEventKind>,
+        address: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<ChainEvent>, OpenBankError> {
+        let log = self.chain_events.read().unwrap();
+        Ok(log
+            .events
+            .iter()
+            .rev()
+            .filter(|e| chain_id.is_none_or(|chain_id| e.chain_id == chain_id))
+            .filter(|e| kind.is_none_or(|kind| e.kind == kind))
+            .filter(|e| address.is_none_or(|address| e.address.eq_ignore_ascii_case(address)))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+}
+
+impl ReconciliationRepository for MemoryStorage {
+    fn insert_reconciliation_report(&self, report: &ReconciliationReport) -> Result<(), OpenBankError> {
+        self.reconciliation_reports.write().unwrap().push(report.clone());
+        Ok(())
+    }
+
+    fn latest_reconciliation_report(&self) -> Result<Option<ReconciliationReport>, OpenBankError> {
+        Ok(self.reconciliation_reports.read().unwrap().last().cloned())
+    }
+}
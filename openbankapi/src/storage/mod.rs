@@ -0,0 +1,616 @@
+mod memory;
+mod sqlite;
+
+pub use memory::MemoryStorage;
+pub use sqlite::SqliteStorage;
+
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use crate::error::OpenBankError;
+use crate::money::Money;
+use crate::types::{
+    Account, ApiKey, ChainEvent, ChainEventKind, GlobalLimits, Hold, IdempotencyCompletion, IdempotencyRecord,
+    IndexerCursor, JournalEntry, Quote, ReconciliationReport, TierLimits, Transaction, Transfer, User, WalletChallenge,
+    Withdrawal,
+};
+
+// Repository traits used by the API handlers. Every implementation must be
+// safe to share between requests, so methods take `&self` and lock internally.
+pub trait UserRepository {
+    fn insert_user(&self, user: &User) -> Result<(), OpenBankError>;
+    fn get_user(&self, user_id: &str) -> Result<Option<User>, OpenBankError>;
+    fn find_user_by_email(&self, email: &str) -> Result<Option<User>, OpenBankError>;
+    /// Case-insensitive, since older rows may not be checksummed.
+    fn find_user_by_wallet(&self, wallet_address: &str) -> Result<Option<User>, OpenBankError>;
+    /// Overwrites the user's email, name and wallet. Its account list is left alone.
+    fn update_user(&self, user: &User) -> Result<(), OpenBankError>;
+    fn set_user_tier(&self, user_id: &str, tier: &str) -> Result<(), OpenBankError>;
+    /// Every user, oldest first.
+    fn list_users(&self) -> Result<Vec<User>, OpenBankError>;
+}
+
+pub trait AccountRepository {
+    /// Inserts the account and links it to its owner.
+    fn insert_account(&self, account: &Account) -> Result<(), OpenBankError>;
+    fn get_account(&self, account_id: &str) -> Result<Option<Account>, OpenBankError>;
+    fn list_user_accounts(&self, user_id: &str) -> Result<Vec<Account>, OpenBankError>;
+    /// Marks the account inactive, failing with `AccountNotEmpty` while it has a
+    /// balance or active holds and with `AccountInactive` if it is already closed.
+    fn close_account(&self, account_id: &str) -> Result<Account, OpenBankError>;
+}
+
+// Account balances are never written directly: they follow from the postings
+// of journal entries, which are recorded together with the transactions they explain.
+pub trait TransactionRepository {
+    /// Records `entry` and the transaction in one step, returning the transaction
+    /// with `balance_after` filled in from the account's postings. Unbalanced
+    /// entries are rejected, and so are postings to closed accounts (`AccountInactive`).
+    /// With a completion the returned transaction is stored as the response of
+    /// its idempotency key in the same step.
+    fn apply_transaction(
+        &self,
+        transaction: Transaction,
+        entry: &JournalEntry,
+        completion: Option<&IdempotencyCompletion>,
+    ) -> Result<Transaction, OpenBankError>;
+    /// Like `apply_transaction` for both sides of a transfer at once. Fails with
+    /// `InsufficientFunds` unless the debited account's available balance covers
+    /// the debit, so holds are never spent twice.
+    fn apply_transfer(
+        &self,
+        transfer: Transfer,
+        entry: &JournalEntry,
+        completion: Option<&IdempotencyCompletion>,
+    ) -> Result<Transfer, OpenBankError>;
+    fn list_transactions(&self, account_id: &str) -> Result<Vec<Transaction>, OpenBankError>;
+}
+
+pub trait HoldRepository {
+    /// Reserves `amount` on the account, failing with `InsufficientFunds` when
+    /// the available balance (balance minus active holds) does not cover it, or
+    /// with `AccountInactive` when the account is closed.
+    fn place_hold(&self, account_id: &str, amount: &Money) -> Result<Hold, OpenBankError>;
+    /// Returns the held funds to the available balance.
+    fn release_hold(&self, hold_id: &str) -> Result<Hold, OpenBankError>;
+    /// Debits the held funds through `entry` and records `transaction` in the same
+    /// step. The transaction amount is set to the negated hold amount.
+    fn capture_hold(&self, hold_id: &str, transaction: Transaction, entry: &JournalEntry) -> Result<Transaction, OpenBankError>;
+}
+
+pub trait LedgerRepository {
+    /// Every journal entry, oldest first.
+    fn list_journal_entries(&self) -> Result<Vec<JournalEntry>, OpenBankError>;
+}
+
+pub trait IdempotencyRepository {
+    /// Claims `record.key` for a new request. Returns `None` when the key was
+    /// free, otherwise the record already stored under it. A reservation for
+    /// the same fingerprint that is still in progress but was made before
+    /// `stale_before` is taken over, as its request died without finishing.
+    fn reserve_idempotency_key(
+        &self,
+        record: &IdempotencyRecord,
+        stale_before: DateTime<Utc>,
+    ) -> Result<Option<IdempotencyRecord>, OpenBankError>;
+    /// Stores `body` as the response of the reservation. Fails with
+    /// `IdempotencyRequestInProgress` when the reservation is no longer in
+    /// progress, e.g. because a retry took it over.
+    fn complete_idempotency_key(&self, completion: &IdempotencyCompletion, body: &str) -> Result<(), OpenBankError>;
+    /// Frees a reservation still in progress so a failed request can be retried.
+    /// Completed keys and reservations taken over since are left alone.
+    fn remove_idempotency_key(&self, key: &str, reserved_at: DateTime<Utc>) -> Result<(), OpenBankError>;
+}
+
+// An unfinished reservation of the same request older than `stale_before`,
+// which both backends let a retry take over
+fn is_stale_reservation(existing: &IdempotencyRecord, record: &IdempotencyRecord, stale_before: DateTime<Utc>) -> bool {
+    existing.response_status.is_none() && existing.fingerprint == record.fingerprint && existing.created_at < stale_before
+}
+
+// Idempotency responses hold the response data as JSON
+fn response_body<T: Serialize>(data: &T) -> Result<String, OpenBankError> {
+    serde_json::to_string(data).map_err(|e| OpenBankError::StorageError {
+        message: format!("Failed to encode idempotency response: {}", e),
+    })
+}
+
+pub trait WithdrawalRepository {
+    /// With a completion the withdrawal is also stored as the response of its
+    /// idempotency key, in the same step.
+    fn insert_withdrawal(&self, withdrawal: &Withdrawal, completion: Option<&IdempotencyCompletion>) -> Result<(), OpenBankError>;
+    fn get_withdrawal(&self, withdrawal_id: &str) -> Result<Option<Withdrawal>, OpenBankError>;
+    fn list_user_withdrawals(&self, user_id: &str) -> Result<Vec<Withdrawal>, OpenBankError>;
+    /// Withdrawals still in `Requested` or `Submitted`, oldest first.
+    fn list_pending_withdrawals(&self) -> Result<Vec<Withdrawal>, OpenBankError>;
+    fn update_withdrawal(&self, withdrawal: &Withdrawal) -> Result<(), OpenBankError>;
+    /// Captures the withdrawal's hold with `transaction` and `entry` and stores
+    /// the withdrawal in the same step, so a crash can never debit twice.
+    fn settle_withdrawal(
+        &self,
+        withdrawal: &Withdrawal,
+        transaction: Transaction,
+        entry: &JournalEntry,
+    ) -> Result<Transaction, OpenBankError>;
+    /// Releases the withdrawal's hold and stores the withdrawal in the same step.
+    fn cancel_withdrawal(&self, withdrawal: &Withdrawal) -> Result<(), OpenBankError>;
+}
+
+pub trait WalletChallengeRepository {
+    fn insert_wallet_challenge(&self, challenge: &WalletChallenge) -> Result<(), OpenBankError>;
+    /// Removes and returns the challenge, so each nonce can be verified only once.
+    fn take_wallet_challenge(&self, nonce: &str) -> Result<Option<WalletChallenge>, OpenBankError>;
+}
+
+pub trait QuoteRepository {
+    fn insert_quote(&self, quote: &Quote) -> Result<(), OpenBankError>;
+    fn get_quote(&self, quote_id: &str) -> Result<Option<Quote>, OpenBankError>;
+    /// Marks the quote as spent by `used_by` and returns it, failing with
+    /// `QuoteAlreadyUsed` when it already was, so each quote is used only once.
+    fn use_quote(&self, quote_id: &str, used_by: &str) -> Result<Quote, OpenBankError>;
+}
+
+// Limits set by operators. Tiers without their own limits, and the global
+// limits until first set, fall back to the defaults from the environment.
+pub trait LimitRepository {
+    fn list_tier_limits(&self) -> Result<Vec<TierLimits>, OpenBankError>;
+    fn get_tier_limits(&self, tier: &str) -> Result<Option<TierLimits>, OpenBankError>;
+    fn set_tier_limits(&self, limits: &TierLimits) -> Result<(), OpenBankError>;
+    fn get_global_limits(&self) -> Result<Option<GlobalLimits>, OpenBankError>;
+    fn set_global_limits(&self, limits: &GlobalLimits) -> Result<(), OpenBankError>;
+}
+
+pub trait ApiKeyRepository {
+    fn insert_api_key(&self, api_key: &ApiKey) -> Result<(), OpenBankError>;
+    fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, OpenBankError>;
+}
+
+// Events and cursors are kept per chain; the chain comes from the event or cursor itself.
+pub trait ChainEventRepository {
+    fn get_indexer_cursor(&self, chain_id: u64) -> Result<Option<IndexerCursor>, OpenBankError>;
+    /// Stores a batch of events and advances the cursor in the same step.
+    /// Events already stored (same id) are ignored.
+    fn record_chain_events(&self, events: &[ChainEvent], cursor: &IndexerCursor) -> Result<(), OpenBankError>;
+    /// Drops every event of the chain above `cursor.block_number` and moves the
+    /// cursor back there, so blocks orphaned by a reorg get indexed again.
+    /// Without a cursor all of the chain's events and its cursor are dropped.
+    fn rewind_chain_events(&self, chain_id: u64, cursor: Option<&IndexerCursor>) -> Result<(), OpenBankError>;
+    /// Most recent events first, optionally filtered by chain, kind and address.
+    fn list_chain_events(
+        &self,
+        chain_id: Option<u64>,
+        kind: Option<ChainEventKind>,
+        address: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<ChainEvent>, OpenBankError>;
+}
+
+pub trait ReconciliationRepository {
+    fn insert_reconciliation_report(&self, report: &ReconciliationReport) -> Result<(), OpenBankError>;
+    fn latest_reconciliation_report(&self) -> Result<Option<ReconciliationReport>, OpenBankError>;
+}
+
+pub trait Storage:
+    UserRepository + AccountRepository + TransactionRepository + LedgerRepository + HoldRepository + IdempotencyRepository
+    + WithdrawalRepository + ApiKeyRepository + WalletChallengeRepository + QuoteRepository + LimitRepository
+    + ChainEventRepository + ReconciliationRepository + Send + Sync {}
+
+impl<T> Storage for T where
+    T: UserRepository + AccountRepository + TransactionRepository + LedgerRepository + HoldRepository + IdempotencyRepository
+    + WithdrawalRepository + ApiKeyRepository + WalletChallengeRepository + QuoteRepository + LimitRepository
+    + ChainEventRepository + ReconciliationRepository + Send + Sync {}
+
+// Picks the storage backend from the environment:
+// STORAGE_BACKEND=memory keeps everything in process (useful for tests),
+// otherwise an SQLite database is opened at DATABASE_PATH.
+pub fn from_env() -> Result<Arc<dyn Storage>, OpenBankError> {
+    let backend = std::env::var("STORAGE_BACKEND").unwrap_or_else(|_| "sqlite".to_string());
+
+    match backend.as_str() {
+        "memory" => Ok(Arc::new(MemoryStorage::new())),
+        "sqlite" => {
+            let path = std::env::var("DATABASE_PATH").unwrap_or_else(|_| "onramptee.db".to_string());
+            Ok(Arc::new(SqliteStorage::open(&path)?))
+        }
+        other => Err(OpenBankError::StorageError {
+            message: format!("Unknown STORAGE_BACKEND: {}", other),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+    use uuid::Uuid;
+    use crate::ledger;
+    use crate::money::USDT;
+    use ethers::types::U256;
+    use crate::types::{AccountType, HoldStatus, TransactionType, WithdrawalStatus, DEFAULT_TIER};
+
+    // Every check runs against both backends, so they can't drift apart
+    fn backends() -> Vec<(&'static str, Box<dyn Storage>)> {
+        let sqlite = SqliteStorage::with_connection(Connection::open_in_memory().unwrap()).unwrap();
+        vec![("memory", Box::new(MemoryStorage::new())), ("sqlite", Box::new(sqlite))]
+    }
+
+    fn user(id: &str, email: &str, wallet_address: Option<&str>) -> User {
+        User {
+            id: id.to_string(),
+            email: email.to_string(),
+            name: "User".to_string(),
+            wallet_address: wallet_address.map(str::to_string),
+            wallet_chain_id: None,
+            tier: DEFAULT_TIER.to_string(),
+            created_at: Utc::now(),
+            accounts: Vec::new(),
+        }
+    }
+
+    fn money(amount: &str, currency: &str) -> Money {
+        Money::parse(amount, currency).unwrap()
+    }
+
+    // A user with a USD account holding `balance`
+    fn funded_account(storage: &dyn Storage, balance: &str) -> Account {
+        if storage.get_user("user").unwrap().is_none() {
+            storage.insert_user(&user("user", "user@example.com", None)).unwrap();
+        }
+        let account = Account {
+            id: Uuid::new_v4().to_string(),
+            user_id: "user".to_string(),
+            account_type: AccountType::Deposit,
+            balance: Money::zero("USD").unwrap(),
+            held_balance: Money::zero("USD").unwrap(),
+            currency: "USD".to_string(),
+            created_at: Utc::now(),
+            is_active: true,
+        };
+        storage.insert_account(&account).unwrap();
+        let amount = money(balance, "USD");
+        if amount.is_positive() {
+            let entry = ledger::deposit_entry(&account, &amount, "deposit", "Deposit");
+            storage.apply_transaction(transaction(&account, TransactionType::Deposit, &amount), &entry, None).unwrap();
+        }
+        storage.get_account(&account.id).unwrap().unwrap()
+    }
+
+    fn transaction(account: &Account, transaction_type: TransactionType, amount: &Money) -> Transaction {
+        Transaction {
+            id: Uuid::new_v4().to_string(),
+            user_id: account.user_id.clone(),
+            account_id: account.id.clone(),
+            amount: amount.clone(),
+            transaction_type,
+            description: "test".to_string(),
+            timestamp: Utc::now(),
+            balance_after: account.balance.clone(),
+            tx_hash: None,
+            transfer_id: None,
+        }
+    }
+
+    fn withdrawal(account: &Account, hold: &Hold) -> Withdrawal {
+        let now = Utc::now();
+        Withdrawal {
+            id: Uuid::new_v4().to_string(),
+            user_id: account.user_id.clone(),
+            account_id: account.id.clone(),
+            wallet_address: "0x0000000000000000000000000000000000000001".to_string(),
+            chain_id: Some(1),
+            amount: hold.amount.clone(),
+            usdt_amount: Money::from_minor_units(hold.amount.minor_units() * 10_000, USDT).unwrap(),
+            quote_id: None,
+            fees: Vec::new(),
+            description: "test".to_string(),
+            hold_id: hold.id.clone(),
+            status: WithdrawalStatus::Requested,
+            tx_hash: None,
+            nonce: None,
+            replaced_tx_hashes: Vec::new(),
+            block_number: None,
+            gas_used: None,
+            transaction_id: None,
+            failure_reason: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    // (balance, held, available) of the account as stored
+    fn balances(storage: &dyn Storage, account_id: &str) -> (Money, Money, Money) {
+        let account = storage.get_account(account_id).unwrap().unwrap();
+        let available = account.available_balance().unwrap();
+        (account.balance, account.held_balance, available)
+    }
+
+    fn storage_error(result: Result<(), OpenBankError>) -> String {
+        match result {
+            Err(OpenBankError::StorageError { message }) => message,
+            other => panic!("expected a storage error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn duplicate_emails_and_wallets_are_rejected_alike() {
+        let wallet = "0x0000000000000000000000000000000000000001";
+        for (name, storage) in backends() {
+            storage.insert_user(&user("a", "a@example.com", Some(wallet))).unwrap();
+            storage.insert_user(&user("b", "b@example.com", None)).unwrap();
+            // Users without a wallet don't collide with each other
+            storage.insert_user(&user("c", "c@example.com", None)).unwrap();
+
+            let duplicate = storage_error(storage.insert_user(&user("d", "a@example.com", None)));
+            assert_eq!(duplicate, "UNIQUE constraint failed: users.email", "{}", name);
+            let duplicate = storage_error(storage.insert_user(&user("d", "d@example.com", Some(wallet))));
+            assert_eq!(duplicate, "UNIQUE constraint failed: users.wallet_address", "{}", name);
+            let duplicate = storage_error(storage.insert_user(&user("a", "e@example.com", None)));
+            assert_eq!(duplicate, "UNIQUE constraint failed: users.id", "{}", name);
+
+            let duplicate = storage_error(storage.update_user(&user("b", "a@example.com", None)));
+            assert_eq!(duplicate, "UNIQUE constraint failed: users.email", "{}", name);
+            let duplicate = storage_error(storage.update_user(&user("b", "b@example.com", Some(wallet))));
+            assert_eq!(duplicate, "UNIQUE constraint failed: users.wallet_address", "{}", name);
+            assert_eq!(storage.get_user("b").unwrap().unwrap().email, "b@example.com", "{}", name);
+
+            // Keeping its own email and wallet is not a conflict
+            storage.update_user(&user("a", "a@example.com", Some(wallet))).unwrap();
+            assert!(matches!(
+                storage.update_user(&user("missing", "x@example.com", None)),
+                Err(OpenBankError::UserNotFound { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn holds_reserve_funds_until_released() {
+        for (name, storage) in backends() {
+            let account = funded_account(storage.as_ref(), "100");
+
+            let hold = storage.place_hold(&account.id, &money("60", "USD")).unwrap();
+            assert_eq!(hold.status, HoldStatus::Active, "{}", name);
+            assert_eq!(balances(storage.as_ref(), &account.id), (money("100", "USD"), money("60", "USD"), money("40", "USD")), "{}", name);
+
+            // Held funds can't be held again
+            assert!(matches!(
+                storage.place_hold(&account.id, &money("40.01", "USD")),
+                Err(OpenBankError::InsufficientFunds { .. })
+            ), "{}", name);
+            assert_eq!(balances(storage.as_ref(), &account.id).1, money("60", "USD"), "{}", name);
+
+            let released = storage.release_hold(&hold.id).unwrap();
+            assert_eq!(released.status, HoldStatus::Released, "{}", name);
+            assert_eq!(balances(storage.as_ref(), &account.id), (money("100", "USD"), money("0", "USD"), money("100", "USD")), "{}", name);
+
+            // A hold is closed only once
+            assert!(matches!(storage.release_hold(&hold.id), Err(OpenBankError::HoldNotFound { .. })), "{}", name);
+            assert_eq!(balances(storage.as_ref(), &account.id).1, money("0", "USD"), "{}", name);
+            storage.place_hold(&account.id, &money("100", "USD")).unwrap();
+        }
+    }
+
+    #[test]
+    fn settling_a_withdrawal_captures_its_hold() {
+        for (name, storage) in backends() {
+            let account = funded_account(storage.as_ref(), "100");
+            let hold = storage.place_hold(&account.id, &money("30", "USD")).unwrap();
+            let mut withdrawal = withdrawal(&account, &hold);
+            storage.insert_withdrawal(&withdrawal, None).unwrap();
+
+            withdrawal.status = WithdrawalStatus::Confirmed;
+            let entry = ledger::withdrawal_entry(&account.id, &withdrawal.amount, &[], &withdrawal.usdt_amount, &withdrawal.id, "Withdrawal").unwrap();
+            let debit = storage
+                .settle_withdrawal(&withdrawal, transaction(&account, TransactionType::Withdrawal, &money("0", "USD")), &entry)
+                .unwrap();
+            assert_eq!(debit.amount, money("-30", "USD"), "{}", name);
+            assert_eq!(debit.balance_after, money("70", "USD"), "{}", name);
+            assert_eq!(balances(storage.as_ref(), &account.id), (money("70", "USD"), money("0", "USD"), money("70", "USD")), "{}", name);
+            assert_eq!(storage.get_withdrawal(&withdrawal.id).unwrap().unwrap().status, WithdrawalStatus::Confirmed, "{}", name);
+
+            // The captured hold can neither be captured again nor released
+            let entry = ledger::withdrawal_entry(&account.id, &withdrawal.amount, &[], &withdrawal.usdt_amount, &withdrawal.id, "Withdrawal").unwrap();
+            assert!(matches!(
+                storage.settle_withdrawal(&withdrawal, transaction(&account, TransactionType::Withdrawal, &money("0", "USD")), &entry),
+                Err(OpenBankError::HoldNotFound { .. })
+            ), "{}", name);
+            assert!(matches!(storage.cancel_withdrawal(&withdrawal), Err(OpenBankError::HoldNotFound { .. })), "{}", name);
+            assert_eq!(balances(storage.as_ref(), &account.id).0, money("70", "USD"), "{}", name);
+            assert_eq!(storage.list_transactions(&account.id).unwrap().len(), 2, "{}", name);
+        }
+    }
+
+    #[test]
+    fn cancelling_a_withdrawal_releases_its_hold() {
+        for (name, storage) in backends() {
+            let account = funded_account(storage.as_ref(), "100");
+            let hold = storage.place_hold(&account.id, &money("30", "USD")).unwrap();
+            let mut withdrawal = withdrawal(&account, &hold);
+            storage.insert_withdrawal(&withdrawal, None).unwrap();
+
+            withdrawal.status = WithdrawalStatus::Failed;
+            storage.cancel_withdrawal(&withdrawal).unwrap();
+            assert_eq!(balances(storage.as_ref(), &account.id), (money("100", "USD"), money("0", "USD"), money("100", "USD")), "{}", name);
+            assert_eq!(storage.get_withdrawal(&withdrawal.id).unwrap().unwrap().status, WithdrawalStatus::Failed, "{}", name);
+            assert_eq!(storage.list_transactions(&account.id).unwrap().len(), 1, "{}", name);
+        }
+    }
+
+    #[test]
+    fn holds_need_an_active_account_with_enough_funds() {
+        for (name, storage) in backends() {
+            let account = funded_account(storage.as_ref(), "10");
+            let err = storage.place_hold(&account.id, &money("10.01", "USD")).unwrap_err();
+            assert!(matches!(
+                &err,
+                OpenBankError::InsufficientFunds { available, requested, .. } if available == "10.00 USD" && requested == "10.01 USD"
+            ), "{}: {:?}", name, err);
+            assert_eq!(balances(storage.as_ref(), &account.id).1, money("0", "USD"), "{}", name);
+            assert!(matches!(
+                storage.place_hold("missing", &money("1", "USD")),
+                Err(OpenBankError::AccountNotFound { .. })
+            ), "{}", name);
+
+            let empty = funded_account(storage.as_ref(), "0");
+            storage.close_account(&empty.id).unwrap();
+            assert!(matches!(
+                storage.place_hold(&empty.id, &money("1", "USD")),
+                Err(OpenBankError::AccountInactive { .. })
+            ), "{}", name);
+        }
+    }
+
+    #[test]
+    fn only_empty_accounts_can_be_closed() {
+        for (name, storage) in backends() {
+            let account = funded_account(storage.as_ref(), "10");
+            let err = storage.close_account(&account.id).unwrap_err();
+            assert!(matches!(
+                &err,
+                OpenBankError::AccountNotEmpty { balance, .. } if balance == "10.00 USD"
+            ), "{}: {:?}", name, err);
+            assert!(storage.get_account(&account.id).unwrap().unwrap().is_active, "{}", name);
+
+            let empty = funded_account(storage.as_ref(), "0");
+            assert!(!storage.close_account(&empty.id).unwrap().is_active, "{}", name);
+            assert!(!storage.get_account(&empty.id).unwrap().unwrap().is_active, "{}", name);
+            assert!(matches!(
+                storage.close_account(&empty.id),
+                Err(OpenBankError::AccountInactive { .. })
+            ), "{}", name);
+            assert!(matches!(
+                storage.close_account("missing"),
+                Err(OpenBankError::AccountNotFound { .. })
+            ), "{}", name);
+        }
+    }
+
+    #[test]
+    fn transfers_are_checked_against_the_available_balance() {
+        for (name, storage) in backends() {
+            let from = funded_account(storage.as_ref(), "50");
+            let to = funded_account(storage.as_ref(), "0");
+            storage.place_hold(&from.id, &money("20", "USD")).unwrap();
+
+            let transfer = |amount: &str| {
+                let amount = money(amount, "USD");
+                let entry = ledger::transfer_entry(&from, &to, &amount, &[], &amount, "transfer", "Transfer").unwrap();
+                let transfer = Transfer {
+                    id: "transfer".to_string(),
+                    from_account_id: from.id.clone(),
+                    to_account_id: to.id.clone(),
+                    amount: amount.clone(),
+                    converted_amount: amount.clone(),
+                    fx_rate: None,
+                    quote_id: None,
+                    fees: Vec::new(),
+                    debit: transaction(&from, TransactionType::Transfer, &amount.checked_neg().unwrap()),
+                    credit: transaction(&to, TransactionType::Transfer, &amount),
+                };
+                storage.apply_transfer(transfer, &entry, None)
+            };
+            assert!(matches!(transfer("30.01"), Err(OpenBankError::InsufficientFunds { .. })), "{}", name);
+            assert_eq!(balances(storage.as_ref(), &from.id).0, money("50", "USD"), "{}", name);
+            assert!(storage.list_transactions(&to.id).unwrap().is_empty(), "{}", name);
+
+            let Transfer { debit, credit, .. } = transfer("30").unwrap();
+            assert_eq!((debit.balance_after, credit.balance_after), (money("20", "USD"), money("30", "USD")), "{}", name);
+            assert_eq!(balances(storage.as_ref(), &from.id), (money("20", "USD"), money("20", "USD"), money("0", "USD")), "{}", name);
+        }
+    }
+
+    #[test]
+    fn writes_complete_only_their_own_reservation() {
+        for (name, storage) in backends() {
+            let account = funded_account(storage.as_ref(), "50");
+            let reserve = |key: &str, created_at: DateTime<Utc>| {
+                let record = IdempotencyRecord {
+                    key: key.to_string(),
+                    fingerprint: "f".to_string(),
+                    response_status: None,
+                    response_body: None,
+                    created_at,
+                };
+                assert!(storage.reserve_idempotency_key(&record, created_at - chrono::Duration::seconds(1)).unwrap().is_none());
+                IdempotencyCompletion { key: key.to_string(), reserved_at: created_at, status: 200 }
+            };
+            let deposit = |completion: &IdempotencyCompletion| {
+                let amount = money("10", "USD");
+                let entry = ledger::deposit_entry(&account, &amount, "deposit", "Deposit");
+                storage.apply_transaction(transaction(&account, TransactionType::Deposit, &amount), &entry, Some(completion))
+            };
+
+            // A reservation taken over by a retry refuses the original's write
+            let original = reserve("k", Utc::now() - chrono::Duration::hours(1));
+            let retry = reserve("k", Utc::now());
+            assert!(matches!(deposit(&original), Err(OpenBankError::IdempotencyRequestInProgress { .. })), "{}", name);
+            assert_eq!(balances(storage.as_ref(), &account.id).0, money("50", "USD"), "{}", name);
+            assert_eq!(storage.list_transactions(&account.id).unwrap().len(), 1, "{}", name);
+
+            let transaction = deposit(&retry).unwrap();
+            let stored = storage.reserve_idempotency_key(&IdempotencyRecord {
+                key: "k".to_string(),
+                fingerprint: "f".to_string(),
+                response_status: None,
+                response_body: None,
+                created_at: Utc::now(),
+            }, Utc::now()).unwrap().unwrap();
+            assert_eq!(stored.response_status, Some(200), "{}", name);
+            assert_eq!(stored.response_body, Some(serde_json::to_string(&transaction).unwrap()), "{}", name);
+
+            // Completed keys outlive a late cleanup, and can't be completed twice
+            storage.remove_idempotency_key("k", retry.reserved_at).unwrap();
+            assert!(matches!(deposit(&retry), Err(OpenBankError::IdempotencyRequestInProgress { .. })), "{}", name);
+            assert_eq!(balances(storage.as_ref(), &account.id).0, money("60", "USD"), "{}", name);
+        }
+    }
+
+    fn chain_event(chain_id: u64, block_number: u64) -> ChainEvent {
+        ChainEvent {
+            id: format!("{}:0x{:x}:0", chain_id, block_number),
+            chain_id,
+            kind: ChainEventKind::DepositMade,
+            address: "0x0000000000000000000000000000000000000001".to_string(),
+            amount: U256::from(1_000_000),
+            description: None,
+            timestamp: None,
+            block_number,
+            block_hash: format!("0x{:x}", block_number),
+            tx_hash: format!("0x{:x}", block_number),
+            log_index: 0,
+        }
+    }
+
+    fn cursor(chain_id: u64, block_number: u64) -> IndexerCursor {
+        IndexerCursor { chain_id, block_number, block_hash: format!("0x{:x}", block_number) }
+    }
+
+    #[test]
+    fn rewinds_drop_events_after_the_cursor_or_all_of_them() {
+        for (name, storage) in backends() {
+            let events = [chain_event(1, 0), chain_event(1, 5), chain_event(1, 9)];
+            storage.record_chain_events(&events, &cursor(1, 9)).unwrap();
+            storage.record_chain_events(&[chain_event(2, 3)], &cursor(2, 3)).unwrap();
+            let blocks = |chain_id| {
+                let mut blocks: Vec<u64> = storage
+                    .list_chain_events(Some(chain_id), None, None, 10)
+                    .unwrap()
+                    .iter()
+                    .map(|e| e.block_number)
+                    .collect();
+                blocks.sort();
+                blocks
+            };
+
+            storage.rewind_chain_events(1, Some(&cursor(1, 5))).unwrap();
+            assert_eq!(blocks(1), vec![0, 5], "{}", name);
+            assert_eq!(storage.get_indexer_cursor(1).unwrap().unwrap().block_number, 5, "{}", name);
+
+            // Block 0 goes too, so the next pass starts over from the start block
+            storage.rewind_chain_events(1, None).unwrap();
+            assert!(blocks(1).is_empty(), "{}", name);
+            assert!(storage.get_indexer_cursor(1).unwrap().is_none(), "{}", name);
+            assert_eq!(blocks(2), vec![3], "{}", name);
+            assert_eq!(storage.get_indexer_cursor(2).unwrap().unwrap().block_number, 3, "{}", name);
+        }
+    }
+}
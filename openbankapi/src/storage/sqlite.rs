@@ -0,0 +1,1647 @@
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::sync::Mutex;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use crate::error::OpenBankError;
+use crate::ledger;
+use crate::money::{Money, Rate, USDT};
+use ethers::types::U256;
+use crate::types::{
+    Account, AccountType, ApiKey, ChainEvent, ChainEventKind, Hold, HoldStatus, IdempotencyCompletion, IdempotencyRecord,
+    IndexerCursor, GlobalLimits, JournalEntry, Posting, PostingSide, Quote, QuotePurpose, ReconciliationReport, TierLimits,
+    Transaction, TransactionType, Transfer, User, WalletChallenge, Withdrawal, WithdrawalStatus,
+};
+use super::{
+    is_stale_reservation, response_body, AccountRepository, ApiKeyRepository, ChainEventRepository, HoldRepository, IdempotencyRepository, LedgerRepository,
+    LimitRepository, QuoteRepository, ReconciliationRepository, TransactionRepository, UserRepository, WalletChallengeRepository,
+    WithdrawalRepository,
+};
+
+// Schema migrations, applied in order at startup. The index of the last applied
+// migration is tracked in `PRAGMA user_version`, so entries must never be
+// edited or reordered once released - add a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // 1: initial schema
+    "CREATE TABLE users (
+        id TEXT PRIMARY KEY,
+        email TEXT NOT NULL UNIQUE,
+        name TEXT NOT NULL,
+        wallet_address TEXT UNIQUE,
+        created_at TEXT NOT NULL
+    );
+    CREATE TABLE accounts (
+        id TEXT PRIMARY KEY,
+        user_id TEXT NOT NULL REFERENCES users(id),
+        account_type TEXT NOT NULL,
+        balance REAL NOT NULL,
+        currency TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        is_active INTEGER NOT NULL
+    );
+    CREATE INDEX accounts_user_id ON accounts(user_id);
+    CREATE TABLE transactions (
+        id TEXT PRIMARY KEY,
+        user_id TEXT NOT NULL REFERENCES users(id),
+        account_id TEXT NOT NULL REFERENCES accounts(id),
+        transaction_type TEXT NOT NULL,
+        amount REAL NOT NULL,
+        description TEXT NOT NULL,
+        timestamp TEXT NOT NULL,
+        balance_after REAL NOT NULL
+    );
+    CREATE INDEX transactions_account_id ON transactions(account_id);",
+    // 2: store money as integer minor units instead of REAL
+    "ALTER TABLE accounts ADD COLUMN balance_minor INTEGER NOT NULL DEFAULT 0;
+    UPDATE accounts SET balance_minor = CAST(ROUND(balance * CASE currency WHEN 'USDT' THEN 1000000 ELSE 100 END) AS INTEGER);
+    ALTER TABLE accounts DROP COLUMN balance;
+    ALTER TABLE accounts RENAME COLUMN balance_minor TO balance;
+    ALTER TABLE transactions ADD COLUMN currency TEXT NOT NULL DEFAULT '';
+    UPDATE transactions SET currency = (SELECT currency FROM accounts WHERE accounts.id = transactions.account_id);
+    ALTER TABLE transactions ADD COLUMN amount_minor INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE transactions ADD COLUMN balance_after_minor INTEGER NOT NULL DEFAULT 0;
+    UPDATE transactions SET
+        amount_minor = CAST(ROUND(amount * CASE currency WHEN 'USDT' THEN 1000000 ELSE 100 END) AS INTEGER),
+        balance_after_minor = CAST(ROUND(balance_after * CASE currency WHEN 'USDT' THEN 1000000 ELSE 100 END) AS INTEGER);
+    ALTER TABLE transactions DROP COLUMN amount;
+    ALTER TABLE transactions DROP COLUMN balance_after;
+    ALTER TABLE transactions RENAME COLUMN amount_minor TO amount;
+    ALTER TABLE transactions RENAME COLUMN balance_after_minor TO balance_after;",
+    // 3: holds placed by in-flight withdrawals
+    "ALTER TABLE accounts ADD COLUMN held_balance INTEGER NOT NULL DEFAULT 0;
+    CREATE TABLE holds (
+        id TEXT PRIMARY KEY,
+        account_id TEXT NOT NULL REFERENCES accounts(id),
+        amount INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        status TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    );
+    CREATE INDEX holds_account_id ON holds(account_id);",
+    // 4: idempotency keys for deposit and withdraw
+    "CREATE TABLE idempotency_keys (
+        key TEXT PRIMARY KEY,
+        fingerprint TEXT NOT NULL,
+        response_status INTEGER,
+        response_body TEXT,
+        created_at TEXT NOT NULL
+    );",
+    // 5: link withdrawals to the chain transaction that settled them
+    "ALTER TABLE transactions ADD COLUMN tx_hash TEXT;",
+    // 6: withdrawals tracked by the background worker
+    "CREATE TABLE withdrawals (
+        id TEXT PRIMARY KEY,
+        user_id TEXT NOT NULL REFERENCES users(id),
+        account_id TEXT NOT NULL REFERENCES accounts(id),
+        wallet_address TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        description TEXT NOT NULL,
+        hold_id TEXT NOT NULL REFERENCES holds(id),
+        status TEXT NOT NULL,
+        tx_hash TEXT,
+        nonce INTEGER,
+        block_number INTEGER,
+        gas_used INTEGER,
+        transaction_id TEXT REFERENCES transactions(id),
+        failure_reason TEXT,
+        created_at TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    );
+    CREATE INDEX withdrawals_user_id ON withdrawals(user_id);
+    CREATE INDEX withdrawals_status ON withdrawals(status);",
+    // 7: hashed API keys issued to users
+    "CREATE TABLE api_keys (
+        key_hash TEXT PRIMARY KEY,
+        user_id TEXT NOT NULL REFERENCES users(id),
+        created_at TEXT NOT NULL
+    );",
+    // 8: contract events followed by the indexer, and its cursor
+    "CREATE TABLE chain_events (
+        id TEXT PRIMARY KEY,
+        kind TEXT NOT NULL,
+        address TEXT NOT NULL,
+        amount TEXT NOT NULL,
+        description TEXT,
+        timestamp INTEGER,
+        block_number INTEGER NOT NULL,
+        block_hash TEXT NOT NULL,
+        tx_hash TEXT NOT NULL,
+        log_index INTEGER NOT NULL
+    );
+    CREATE INDEX chain_events_block_number ON chain_events(block_number);
+    CREATE INDEX chain_events_address ON chain_events(address);
+    CREATE TABLE indexer_cursor (
+        id INTEGER PRIMARY KEY CHECK (id = 1),
+        block_number INTEGER NOT NULL,
+        block_hash TEXT NOT NULL
+    );",
+    // 9: reconciliation reports, kept whole as JSON
+    "CREATE TABLE reconciliation_reports (
+        id TEXT PRIMARY KEY,
+        generated_at TEXT NOT NULL,
+        report TEXT NOT NULL
+    );",
+    // 10: hashes of withdrawal transactions that were replaced with higher gas
+    "ALTER TABLE withdrawals ADD COLUMN replaced_tx_hashes TEXT NOT NULL DEFAULT '[]';",
+    // 11: multi-chain. Indexed events were not tagged with their chain, so they
+    // are dropped together with the single cursor and indexed again per chain.
+    "ALTER TABLE users ADD COLUMN wallet_chain_id INTEGER;
+    ALTER TABLE withdrawals ADD COLUMN chain_id INTEGER;
+    DELETE FROM chain_events;
+    ALTER TABLE chain_events ADD COLUMN chain_id INTEGER NOT NULL DEFAULT 0;
+    CREATE INDEX chain_events_chain_block ON chain_events(chain_id, block_number);
+    DROP TABLE indexer_cursor;
+    CREATE TABLE indexer_cursors (
+        chain_id INTEGER PRIMARY KEY,
+        block_number INTEGER NOT NULL,
+        block_hash TEXT NOT NULL
+    );",
+    // 12: nonces a wallet must sign before it is bound to a user
+    "CREATE TABLE wallet_challenges (
+        nonce TEXT PRIMARY KEY,
+        user_id TEXT NOT NULL REFERENCES users(id),
+        wallet_address TEXT NOT NULL,
+        wallet_chain_id INTEGER,
+        created_at TEXT NOT NULL,
+        expires_at TEXT NOT NULL
+    );
+    CREATE INDEX users_wallet_address_lower ON users(lower(wallet_address));",
+    // 13: double-entry ledger. Account balances are derived from postings from
+    // now on, so existing balances are carried over as opening entries against
+    // the fiat clearing account before the column is dropped.
+    "CREATE TABLE journal_entries (
+        id TEXT PRIMARY KEY,
+        description TEXT NOT NULL,
+        reference TEXT,
+        created_at TEXT NOT NULL
+    );
+    CREATE TABLE postings (
+        entry_id TEXT NOT NULL REFERENCES journal_entries(id),
+        position INTEGER NOT NULL,
+        ledger_account TEXT NOT NULL,
+        side TEXT NOT NULL,
+        amount INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        PRIMARY KEY (entry_id, position)
+    );
+    CREATE INDEX postings_ledger_account ON postings(ledger_account);
+    INSERT INTO journal_entries (id, description, reference, created_at)
+        SELECT 'opening:' || id, 'Opening balance', id, created_at FROM accounts WHERE balance != 0;
+    INSERT INTO postings (entry_id, position, ledger_account, side, amount, currency)
+        SELECT 'opening:' || id, 0, 'clearing:' || currency,
+            CASE WHEN balance > 0 THEN 'Debit' ELSE 'Credit' END, abs(balance), currency
+        FROM accounts WHERE balance != 0;
+    INSERT INTO postings (entry_id, position, ledger_account, side, amount, currency)
+        SELECT 'opening:' || id, 1, 'user:' || id,
+            CASE WHEN balance > 0 THEN 'Credit' ELSE 'Debit' END, abs(balance), currency
+        FROM accounts WHERE balance != 0;
+    ALTER TABLE accounts DROP COLUMN balance;",
+    // 14: both sides of an internal transfer share its id
+    "ALTER TABLE transactions ADD COLUMN transfer_id TEXT;
+    CREATE INDEX transactions_transfer_id ON transactions(transfer_id);",
+    // 15: FX quotes, and the USDT amount each withdrawal sends. Earlier
+    // withdrawals paid one USDT per account unit.
+    "CREATE TABLE quotes (
+        id TEXT PRIMARY KEY,
+        user_id TEXT NOT NULL REFERENCES users(id),
+        account_id TEXT NOT NULL REFERENCES accounts(id),
+        amount INTEGER NOT NULL,
+        currency TEXT NOT NULL,
+        converted_amount INTEGER NOT NULL,
+        converted_currency TEXT NOT NULL,
+        mid_rate TEXT NOT NULL,
+        spread_bps INTEGER NOT NULL,
+        rate TEXT NOT NULL,
+        provider TEXT NOT NULL,
+        created_at TEXT NOT NULL,
+        expires_at TEXT NOT NULL,
+        used_by TEXT
+    );
+    ALTER TABLE withdrawals ADD COLUMN quote_id TEXT REFERENCES quotes(id);
+    ALTER TABLE withdrawals ADD COLUMN usdt_amount INTEGER NOT NULL DEFAULT 0;
+    UPDATE withdrawals SET usdt_amount = CASE currency WHEN 'USDT' THEN amount ELSE amount * 10000 END;",
+    // 16: fee lines charged on quotes and withdrawals, and the chain a USDT quote is for
+    "ALTER TABLE quotes ADD COLUMN fees TEXT NOT NULL DEFAULT '[]';
+    ALTER TABLE quotes ADD COLUMN chain_id INTEGER;
+    ALTER TABLE withdrawals ADD COLUMN fees TEXT NOT NULL DEFAULT '[]';",
+    // 17: withdrawal limits per user tier, and the global outflow limit
+    "ALTER TABLE users ADD COLUMN tier TEXT NOT NULL DEFAULT 'standard';
+    CREATE TABLE tier_limits (
+        tier TEXT PRIMARY KEY,
+        per_transaction INTEGER,
+        daily INTEGER,
+        monthly INTEGER,
+        updated_at TEXT
+    );
+    CREATE TABLE global_limits (
+        id INTEGER PRIMARY KEY CHECK (id = 1),
+        hourly_outflow INTEGER,
+        updated_at TEXT
+    );",
+    // 18: what each quote may be spent on. Only USDT quotes for a withdrawal had a chain.
+    "ALTER TABLE quotes ADD COLUMN purpose TEXT NOT NULL DEFAULT 'Transfer';
+    UPDATE quotes SET purpose = 'Withdrawal' WHERE chain_id IS NOT NULL;",
+];
+
+// Embedded SQLite storage. rusqlite connections are not `Sync`, so a single
+// connection is shared behind a mutex; SQLite serializes writers anyway.
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &str) -> Result<Self, OpenBankError> {
+        let conn = Connection::open(path).map_err(|e| OpenBankError::StorageError {
+            message: format!("Failed to open database {}: {}", path, e),
+        })?;
+        Self::with_connection(conn)
+    }
+
+    pub fn with_connection(mut conn: Connection) -> Result<Self, OpenBankError> {
+        conn.pragma_update(None, "foreign_keys", true).map_err(db_error)?;
+        run_migrations(&mut conn)?;
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+}
+
+fn run_migrations(conn: &mut Connection) -> Result<(), OpenBankError> {
+    let current: usize = conn
+        .pragma_query_value(None, "user_version", |row| row.get(0))
+        .map_err(db_error)?;
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+        let version = index + 1;
+        let tx = conn.transaction().map_err(db_error)?;
+        tx.execute_batch(migration).map_err(|e| OpenBankError::StorageError {
+            message: format!("Migration {} failed: {}", version, e),
+        })?;
+        tx.pragma_update(None, "user_version", version).map_err(db_error)?;
+        tx.commit().map_err(db_error)?;
+        println!("Applied database migration {}", version);
+    }
+
+    Ok(())
+}
+
+fn db_error(e: rusqlite::Error) -> OpenBankError {
+    OpenBankError::StorageError { message: e.to_string() }
+}
+
+impl ToSql for AccountType {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let value = match self {
+            AccountType::Deposit => "Deposit",
+        };
+        Ok(value.into())
+    }
+}
+
+impl FromSql for AccountType {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "Deposit" => Ok(AccountType::Deposit),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for TransactionType {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let value = match self {
+            TransactionType::Deposit => "Deposit",
+            TransactionType::Transfer => "Transfer",
+            TransactionType::Withdrawal => "Withdrawal",
+        };
+        Ok(value.into())
+    }
+}
+
+impl FromSql for TransactionType {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "Deposit" => Ok(TransactionType::Deposit),
+            "Transfer" => Ok(TransactionType::Transfer),
+            "Withdrawal" => Ok(TransactionType::Withdrawal),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for PostingSide {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let value = match self {
+            PostingSide::Debit => "Debit",
+            PostingSide::Credit => "Credit",
+        };
+        Ok(value.into())
+    }
+}
+
+impl FromSql for PostingSide {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "Debit" => Ok(PostingSide::Debit),
+            "Credit" => Ok(PostingSide::Credit),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for QuotePurpose {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let value = match self {
+            QuotePurpose::Withdrawal => "Withdrawal",
+            QuotePurpose::Transfer => "Transfer",
+        };
+        Ok(value.into())
+    }
+}
+
+impl FromSql for QuotePurpose {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "Withdrawal" => Ok(QuotePurpose::Withdrawal),
+            "Transfer" => Ok(QuotePurpose::Transfer),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+// Rates are kept as exact decimal strings
+impl ToSql for Rate {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(self.to_string().into())
+    }
+}
+
+impl FromSql for Rate {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        Rate::parse(value.as_str()?).map_err(|e| FromSqlError::Other(Box::new(e)))
+    }
+}
+
+impl ToSql for HoldStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let value = match self {
+            HoldStatus::Active => "Active",
+            HoldStatus::Released => "Released",
+            HoldStatus::Captured => "Captured",
+        };
+        Ok(value.into())
+    }
+}
+
+impl FromSql for HoldStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "Active" => Ok(HoldStatus::Active),
+            "Released" => Ok(HoldStatus::Released),
+            "Captured" => Ok(HoldStatus::Captured),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for WithdrawalStatus {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let value = match self {
+            WithdrawalStatus::Requested => "Requested",
+            WithdrawalStatus::Submitted => "Submitted",
+            WithdrawalStatus::Confirmed => "Confirmed",
+            WithdrawalStatus::Failed => "Failed",
+            WithdrawalStatus::Replaced => "Replaced",
+        };
+        Ok(value.into())
+    }
+}
+
+impl FromSql for WithdrawalStatus {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "Requested" => Ok(WithdrawalStatus::Requested),
+            "Submitted" => Ok(WithdrawalStatus::Submitted),
+            "Confirmed" => Ok(WithdrawalStatus::Confirmed),
+            "Failed" => Ok(WithdrawalStatus::Failed),
+            "Replaced" => Ok(WithdrawalStatus::Replaced),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for ChainEventKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let value = match self {
+            ChainEventKind::DepositMade => "DepositMade",
+            ChainEventKind::WithdrawalMade => "WithdrawalMade",
+            ChainEventKind::EmergencyWithdraw => "EmergencyWithdraw",
+            ChainEventKind::UsdtTokenSet => "UsdtTokenSet",
+        };
+        Ok(value.into())
+    }
+}
+
+impl FromSql for ChainEventKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "DepositMade" => Ok(ChainEventKind::DepositMade),
+            "WithdrawalMade" => Ok(ChainEventKind::WithdrawalMade),
+            "EmergencyWithdraw" => Ok(ChainEventKind::EmergencyWithdraw),
+            "UsdtTokenSet" => Ok(ChainEventKind::UsdtTokenSet),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+const USER_COLUMNS: &str = "id, email, name, wallet_address, created_at, wallet_chain_id, tier";
+// The balance is not stored: user accounts are liabilities, so it is the
+// account's credits minus its debits
+const ACCOUNT_COLUMNS: &str = "id, user_id, account_type, \
+    (SELECT COALESCE(SUM(CASE side WHEN 'Credit' THEN amount ELSE -amount END), 0) \
+     FROM postings WHERE ledger_account = 'user:' || accounts.id), \
+    held_balance, currency, created_at, is_active";
+const HOLD_COLUMNS: &str = "id, account_id, amount, currency, status, created_at, updated_at";
+const TRANSACTION_COLUMNS: &str =
+    "id, user_id, account_id, transaction_type, amount, currency, description, timestamp, balance_after, tx_hash, \
+    transfer_id";
+const CHAIN_EVENT_COLUMNS: &str =
+    "id, kind, address, amount, description, timestamp, block_number, block_hash, tx_hash, log_index, chain_id";
+const WITHDRAWAL_COLUMNS: &str = "id, user_id, account_id, wallet_address, amount, currency, description, hold_id, \
+    status, tx_hash, nonce, block_number, gas_used, transaction_id, failure_reason, created_at, updated_at, \
+    replaced_tx_hashes, chain_id, usdt_amount, quote_id, fees";
+const QUOTE_COLUMNS: &str = "id, user_id, account_id, amount, currency, converted_amount, converted_currency, \
+    mid_rate, spread_bps, rate, provider, created_at, expires_at, used_by, fees, chain_id, purpose";
+
+// Money columns hold minor units; the currency lives in a sibling column
+fn money_from_sql(minor_units: i64, currency: &str) -> rusqlite::Result<Money> {
+    Money::from_minor_units(minor_units, currency)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e)))
+}
+
+fn account_from_row(row: &Row<'_>) -> rusqlite::Result<Account> {
+    let currency: String = row.get(5)?;
+    Ok(Account {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        account_type: row.get(2)?,
+        balance: money_from_sql(row.get(3)?, &currency)?,
+        held_balance: money_from_sql(row.get(4)?, &currency)?,
+        currency,
+        created_at: row.get(6)?,
+        is_active: row.get(7)?,
+    })
+}
+
+fn hold_from_row(row: &Row<'_>) -> rusqlite::Result<Hold> {
+    let currency: String = row.get(3)?;
+    Ok(Hold {
+        id: row.get(0)?,
+        account_id: row.get(1)?,
+        amount: money_from_sql(row.get(2)?, &currency)?,
+        status: row.get(4)?,
+        created_at: row.get(5)?,
+        updated_at: row.get(6)?,
+    })
+}
+
+fn load_account(conn: &Connection, account_id: &str) -> Result<Account, OpenBankError> {
+    conn.query_row(
+        &format!("SELECT {} FROM accounts WHERE id = ?1", ACCOUNT_COLUMNS),
+        params![account_id],
+        account_from_row,
+    )
+    .optional()
+    .map_err(db_error)?
+    .ok_or_else(|| OpenBankError::AccountNotFound { account_id: account_id.to_string() })
+}
+
+fn load_active_hold(conn: &Connection, hold_id: &str) -> Result<Hold, OpenBankError> {
+    conn.query_row(
+        &format!("SELECT {} FROM holds WHERE id = ?1", HOLD_COLUMNS),
+        params![hold_id],
+        hold_from_row,
+    )
+    .optional()
+    .map_err(db_error)?
+    .filter(|hold| hold.status == HoldStatus::Active)
+    .ok_or_else(|| OpenBankError::HoldNotFound { hold_id: hold_id.to_string() })
+}
+
+// Records a journal entry inside an open database transaction. User accounts
+// it posts to must exist and be active.
+fn post_entry_in_tx(conn: &Connection, entry: &JournalEntry) -> Result<(), OpenBankError> {
+    ledger::ensure_balanced(entry)?;
+    for posting in &entry.postings {
+        if let Some(account_id) = ledger::user_account_id(&posting.ledger_account) {
+            let account = load_account(conn, account_id)?;
+            if !account.is_active {
+                return Err(OpenBankError::AccountInactive { account_id: account.id });
+            }
+        }
+    }
+
+    conn.execute(
+        "INSERT INTO journal_entries (id, description, reference, created_at) VALUES (?1, ?2, ?3, ?4)",
+        params![entry.id, entry.description, entry.reference, entry.created_at],
+    )
+    .map_err(db_error)?;
+    for (position, posting) in entry.postings.iter().enumerate() {
+        conn.execute(
+            "INSERT INTO postings (entry_id, position, ledger_account, side, amount, currency)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                entry.id,
+                position,
+                posting.ledger_account,
+                posting.side,
+                posting.amount.minor_units(),
+                posting.amount.currency(),
+            ],
+        )
+        .map_err(db_error)?;
+    }
+    Ok(())
+}
+
+// Records a transaction whose entry is already posted, inside an open database transaction
+fn insert_transaction_in_tx(conn: &Connection, mut transaction: Transaction) -> Result<Transaction, OpenBankError> {
+    transaction.balance_after = load_account(conn, &transaction.account_id)?.balance;
+
+    conn.execute(
+        &format!("INSERT INTO transactions ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)", TRANSACTION_COLUMNS),
+        params![
+            transaction.id,
+            transaction.user_id,
+            transaction.account_id,
+            transaction.transaction_type,
+            transaction.amount.minor_units(),
+            transaction.amount.currency(),
+            transaction.description,
+            transaction.timestamp,
+            transaction.balance_after.minor_units(),
+            transaction.tx_hash,
+            transaction.transfer_id,
+        ],
+    )
+    .map_err(db_error)?;
+
+    Ok(transaction)
+}
+
+// Posts the entry behind a transaction and records the transaction inside an open database transaction
+fn apply_in_tx(conn: &Connection, transaction: Transaction, entry: &JournalEntry) -> Result<Transaction, OpenBankError> {
+    load_account(conn, &transaction.account_id)?;
+    post_entry_in_tx(conn, entry)?;
+    insert_transaction_in_tx(conn, transaction)
+}
+
+// Stores `body` as the response of the completion's reservation, failing
+// unless it is still in progress, so a request that lost its key changes nothing
+fn complete_key_in_tx(conn: &Connection, completion: &IdempotencyCompletion, body: &str) -> Result<(), OpenBankError> {
+    let updated = conn
+        .execute(
+            "UPDATE idempotency_keys SET response_status = ?1, response_body = ?2
+             WHERE key = ?3 AND response_status IS NULL AND created_at = ?4",
+            params![completion.status, body, completion.key, completion.reserved_at],
+        )
+        .map_err(db_error)?;
+    if updated == 0 {
+        return Err(OpenBankError::IdempotencyRequestInProgress { key: completion.key.clone() });
+    }
+    Ok(())
+}
+
+// Same with `data` as the response, for writes made with an optional completion
+fn complete_in_tx<T: serde::Serialize>(
+    conn: &Connection,
+    completion: Option<&IdempotencyCompletion>,
+    data: &T,
+) -> Result<(), OpenBankError> {
+    match completion {
+        Some(completion) => complete_key_in_tx(conn, completion, &response_body(data)?),
+        None => Ok(()),
+    }
+}
+
+// Takes an active hold out of the account's held balance and marks it with `status`
+fn close_hold_in_tx(conn: &Connection, hold_id: &str, status: HoldStatus) -> Result<Hold, OpenBankError> {
+    let mut hold = load_active_hold(conn, hold_id)?;
+    let account = load_account(conn, &hold.account_id)?;
+    let held_balance = account.held_balance.checked_sub(&hold.amount)?;
+
+    hold.status = status;
+    hold.updated_at = Utc::now();
+    conn.execute(
+        "UPDATE accounts SET held_balance = ?1 WHERE id = ?2",
+        params![held_balance.minor_units(), hold.account_id],
+    )
+    .map_err(db_error)?;
+    conn.execute(
+        "UPDATE holds SET status = ?1, updated_at = ?2 WHERE id = ?3",
+        params![hold.status, hold.updated_at, hold.id],
+    )
+    .map_err(db_error)?;
+
+    Ok(hold)
+}
+
+// Debits an active hold by recording `transaction` against its account
+fn capture_in_tx(
+    conn: &Connection,
+    hold_id: &str,
+    mut transaction: Transaction,
+    entry: &JournalEntry,
+) -> Result<Transaction, OpenBankError> {
+    let hold = close_hold_in_tx(conn, hold_id, HoldStatus::Captured)?;
+    transaction.account_id = hold.account_id;
+    transaction.amount = hold.amount.checked_neg()?;
+    apply_in_tx(conn, transaction, entry)
+}
+
+fn withdrawal_from_row(row: &Row<'_>) -> rusqlite::Result<Withdrawal> {
+    let currency: String = row.get(5)?;
+    Ok(Withdrawal {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        account_id: row.get(2)?,
+        wallet_address: row.get(3)?,
+        amount: money_from_sql(row.get(4)?, &currency)?,
+        description: row.get(6)?,
+        hold_id: row.get(7)?,
+        status: row.get(8)?,
+        tx_hash: row.get(9)?,
+        nonce: row.get(10)?,
+        block_number: row.get(11)?,
+        gas_used: row.get(12)?,
+        transaction_id: row.get(13)?,
+        failure_reason: row.get(14)?,
+        created_at: row.get(15)?,
+        updated_at: row.get(16)?,
+        replaced_tx_hashes: json_from_sql(17, row.get(17)?)?,
+        chain_id: row.get(18)?,
+        usdt_amount: money_from_sql(row.get(19)?, USDT)?,
+        quote_id: row.get(20)?,
+        fees: json_from_sql(21, row.get(21)?)?,
+    })
+}
+
+fn quote_from_row(row: &Row<'_>) -> rusqlite::Result<Quote> {
+    let currency: String = row.get(4)?;
+    let converted_currency: String = row.get(6)?;
+    Ok(Quote {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        account_id: row.get(2)?,
+        amount: money_from_sql(row.get(3)?, &currency)?,
+        converted_amount: money_from_sql(row.get(5)?, &converted_currency)?,
+        mid_rate: row.get(7)?,
+        spread_bps: row.get(8)?,
+        rate: row.get(9)?,
+        provider: row.get(10)?,
+        created_at: row.get(11)?,
+        expires_at: row.get(12)?,
+        used_by: row.get(13)?,
+        fees: json_from_sql(14, row.get(14)?)?,
+        chain_id: row.get(15)?,
+        purpose: row.get(16)?,
+    })
+}
+
+// Limit columns hold USDT base units, NULL for no limit
+fn limit_from_sql(minor_units: Option<i64>) -> rusqlite::Result<Option<Money>> {
+    minor_units.map(|minor_units| money_from_sql(minor_units, USDT)).transpose()
+}
+
+fn tier_limits_from_row(row: &Row<'_>) -> rusqlite::Result<TierLimits> {
+    Ok(TierLimits {
+        tier: row.get(0)?,
+        per_transaction: limit_from_sql(row.get(1)?)?,
+        daily: limit_from_sql(row.get(2)?)?,
+        monthly: limit_from_sql(row.get(3)?)?,
+        updated_at: row.get(4)?,
+    })
+}
+
+fn load_quote(conn: &Connection, quote_id: &str) -> Result<Option<Quote>, OpenBankError> {
+    conn.query_row(
+        &format!("SELECT {} FROM quotes WHERE id = ?1", QUOTE_COLUMNS),
+        params![quote_id],
+        quote_from_row,
+    )
+    .optional()
+    .map_err(db_error)
+}
+
+fn json_from_sql<T: serde::de::DeserializeOwned>(index: usize, value: String) -> rusqlite::Result<T> {
+    serde_json::from_str(&value)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(index, rusqlite::types::Type::Text, Box::new(e)))
+}
+
+fn json_to_sql<T: serde::Serialize>(value: &T) -> Result<String, OpenBankError> {
+    serde_json::to_string(value).map_err(|e| OpenBankError::StorageError {
+        message: format!("Failed to encode column value: {}", e),
+    })
+}
+
+// Overwrites the mutable part of a withdrawal; fails if it does not exist
+fn update_withdrawal_in_tx(conn: &Connection, withdrawal: &Withdrawal) -> Result<(), OpenBankError> {
+    let updated = conn
+        .execute(
+            "UPDATE withdrawals SET status = ?1, tx_hash = ?2, nonce = ?3, block_number = ?4, gas_used = ?5,
+                transaction_id = ?6, failure_reason = ?7, updated_at = ?8, replaced_tx_hashes = ?9
+             WHERE id = ?10",
+            params![
+                withdrawal.status,
+                withdrawal.tx_hash,
+                withdrawal.nonce,
+                withdrawal.block_number,
+                withdrawal.gas_used,
+                withdrawal.transaction_id,
+                withdrawal.failure_reason,
+                withdrawal.updated_at,
+                json_to_sql(&withdrawal.replaced_tx_hashes)?,
+                withdrawal.id,
+            ],
+        )
+        .map_err(db_error)?;
+    if updated == 0 {
+        return Err(OpenBankError::WithdrawalNotFound { withdrawal_id: withdrawal.id.clone() });
+    }
+    Ok(())
+}
+
+fn query_withdrawals<P: rusqlite::Params>(conn: &Connection, filter: &str, params: P) -> Result<Vec<Withdrawal>, OpenBankError> {
+    let sql = format!(
+        "SELECT {} FROM withdrawals WHERE {} ORDER BY created_at, rowid",
+        WITHDRAWAL_COLUMNS, filter
+    );
+    let mut stmt = conn.prepare(&sql).map_err(db_error)?;
+    stmt.query_map(params, withdrawal_from_row)
+        .map_err(db_error)?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(db_error)
+}
+
+// On-chain amounts can exceed i64, so they are stored as decimal text
+fn chain_event_from_row(row: &Row<'_>) -> rusqlite::Result<ChainEvent> {
+    let amount: String = row.get(3)?;
+    Ok(ChainEvent {
+        id: row.get(0)?,
+        kind: row.get(1)?,
+        address: row.get(2)?,
+        amount: U256::from_dec_str(&amount)
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(3, rusqlite::types::Type::Text, Box::new(e)))?,
+        description: row.get(4)?,
+        timestamp: row.get(5)?,
+        block_number: row.get(6)?,
+        block_hash: row.get(7)?,
+        tx_hash: row.get(8)?,
+        log_index: row.get(9)?,
+        chain_id: row.get(10)?,
+    })
+}
+
+fn set_cursor_in_tx(conn: &Connection, cursor: &IndexerCursor) -> Result<(), OpenBankError> {
+    conn.execute(
+        "INSERT INTO indexer_cursors (chain_id, block_number, block_hash) VALUES (?1, ?2, ?3)
+         ON CONFLICT(chain_id) DO UPDATE SET block_number = excluded.block_number, block_hash = excluded.block_hash",
+        params![cursor.chain_id, cursor.block_number, cursor.block_hash],
+    )
+    .map_err(db_error)?;
+    Ok(())
+}
+
+fn transaction_from_row(row: &Row<'_>) -> rusqlite::Result<Transaction> {
+    let currency: String = row.get(5)?;
+    Ok(Transaction {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        account_id: row.get(2)?,
+        transaction_type: row.get(3)?,
+        amount: money_from_sql(row.get(4)?, &currency)?,
+        description: row.get(6)?,
+        timestamp: row.get(7)?,
+        balance_after: money_from_sql(row.get(8)?, &currency)?,
+        tx_hash: row.get(9)?,
+        transfer_id: row.get(10)?,
+    })
+}
+
+// Loads a user row together with the ids of its accounts.
+fn query_user(conn: &Connection, filter: &str, value: &str) -> Result<Option<User>, OpenBankError> {
+    let sql = format!("SELECT {} FROM users WHERE {} = ?1", USER_COLUMNS, filter);
+    let user = conn
+        .query_row(&sql, params![value], |row| {
+            Ok(User {
+                id: row.get(0)?,
+                email: row.get(1)?,
+                name: row.get(2)?,
+                wallet_address: row.get(3)?,
+                wallet_chain_id: row.get(5)?,
+                tier: row.get(6)?,
+                created_at: row.get(4)?,
+                accounts: Vec::new(),
+            })
+        })
+        .optional()
+        .map_err(db_error)?;
+
+    let Some(mut user) = user else {
+        return Ok(None);
+    };
+
+    let mut stmt = conn
+        .prepare("SELECT id FROM accounts WHERE user_id = ?1 ORDER BY rowid")
+        .map_err(db_error)?;
+    user.accounts = stmt
+        .query_map(params![user.id], |row| row.get(0))
+        .map_err(db_error)?
+        .collect::<rusqlite::Result<Vec<String>>>()
+        .map_err(db_error)?;
+
+    Ok(Some(user))
+}
+
+impl UserRepository for SqliteStorage {
+    fn insert_user(&self, user: &User) -> Result<(), OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            &format!("INSERT INTO users ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)", USER_COLUMNS),
+            params![user.id, user.email, user.name, user.wallet_address, user.created_at, user.wallet_chain_id, user.tier],
+        )
+        .map_err(db_error)?;
+        Ok(())
+    }
+
+    fn get_user(&self, user_id: &str) -> Result<Option<User>, OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        query_user(&conn, "id", user_id)
+    }
+
+    fn find_user_by_email(&self, email: &str) -> Result<Option<User>, OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        query_user(&conn, "email", email)
+    }
+
+    fn find_user_by_wallet(&self, wallet_address: &str) -> Result<Option<User>, OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        query_user(&conn, "lower(wallet_address)", &wallet_address.to_lowercase())
+    }
+
+    fn update_user(&self, user: &User) -> Result<(), OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        let updated = conn
+            .execute(
+                "UPDATE users SET email = ?1, name = ?2, wallet_address = ?3, wallet_chain_id = ?4 WHERE id = ?5",
+                params![user.email, user.name, user.wallet_address, user.wallet_chain_id, user.id],
+            )
+            .map_err(db_error)?;
+        if updated == 0 {
+            return Err(OpenBankError::UserNotFound { user_id: user.id.clone() });
+        }
+        Ok(())
+    }
+
+    fn set_user_tier(&self, user_id: &str, tier: &str) -> Result<(), OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        let updated = conn
+            .execute("UPDATE users SET tier = ?1 WHERE id = ?2", params![tier, user_id])
+            .map_err(db_error)?;
+        if updated == 0 {
+            return Err(OpenBankError::UserNotFound { user_id: user_id.to_string() });
+        }
+        Ok(())
+    }
+
+    fn list_users(&self) -> Result<Vec<User>, OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT id FROM users ORDER BY created_at, rowid")
+            .map_err(db_error)?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))
+            .map_err(db_error)?
+            .collect::<rusqlite::Result<Vec<String>>>()
+            .map_err(db_error)?;
+
+        let mut users = Vec::with_capacity(ids.len());
+        for id in ids {
+            users.extend(query_user(&conn, "id", &id)?);
+        }
+        Ok(users)
+    }
+}
+
+impl AccountRepository for SqliteStorage {
+    fn insert_account(&self, account: &Account) -> Result<(), OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        let user_exists: bool = conn
+            .query_row("SELECT EXISTS(SELECT 1 FROM users WHERE id = ?1)", params![account.user_id], |row| row.get(0))
+            .map_err(db_error)?;
+        if !user_exists {
+            return Err(OpenBankError::UserNotFound { user_id: account.user_id.clone() });
+        }
+
+        conn.execute(
+            "INSERT INTO accounts (id, user_id, account_type, held_balance, currency, created_at, is_active)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                account.id,
+                account.user_id,
+                account.account_type,
+                account.held_balance.minor_units(),
+                account.currency,
+                account.created_at,
+                account.is_active,
+            ],
+        )
+        .map_err(db_error)?;
+        Ok(())
+    }
+
+    fn get_account(&self, account_id: &str) -> Result<Option<Account>, OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            &format!("SELECT {} FROM accounts WHERE id = ?1", ACCOUNT_COLUMNS),
+            params![account_id],
+            account_from_row,
+        )
+        .optional()
+        .map_err(db_error)
+    }
+
+    fn list_user_accounts(&self, user_id: &str) -> Result<Vec<Account>, OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare(&format!("SELECT {} FROM accounts WHERE user_id = ?1 ORDER BY rowid", ACCOUNT_COLUMNS))
+            .map_err(db_error)?;
+        stmt.query_map(params![user_id], account_from_row)
+            .map_err(db_error)?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(db_error)
+    }
+
+    fn close_account(&self, account_id: &str) -> Result<Account, OpenBankError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+
+        let mut account = load_account(&tx, account_id)?;
+        if !account.is_active {
+            return Err(OpenBankError::AccountInactive { account_id: account_id.to_string() });
+        }
+        if account.balance.minor_units() != 0 || account.held_balance.minor_units() != 0 {
+            return Err(OpenBankError::AccountNotEmpty {
+                account_id: account_id.to_string(),
+                balance: account.balance.to_string(),
+            });
+        }
+        account.is_active = false;
+        tx.execute("UPDATE accounts SET is_active = 0 WHERE id = ?1", params![account_id])
+            .map_err(db_error)?;
+
+        tx.commit().map_err(db_error)?;
+        Ok(account)
+    }
+}
+
+impl TransactionRepository for SqliteStorage {
+    fn apply_transaction(
+        &self,
+        transaction: Transaction,
+        entry: &JournalEntry,
+        completion: Option<&IdempotencyCompletion>,
+    ) -> Result<Transaction, OpenBankError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+        let transaction = apply_in_tx(&tx, transaction, entry)?;
+        complete_in_tx(&tx, completion, &transaction)?;
+        tx.commit().map_err(db_error)?;
+        Ok(transaction)
+    }
+
+    fn apply_transfer(
+        &self,
+        mut transfer: Transfer,
+        entry: &JournalEntry,
+        completion: Option<&IdempotencyCompletion>,
+    ) -> Result<Transfer, OpenBankError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+
+        let (debit, credit) = (&transfer.debit, &transfer.credit);
+        let from = load_account(&tx, &debit.account_id)?;
+        let available = from.available_balance()?;
+        if available.checked_add(&debit.amount)?.minor_units() < 0 {
+            return Err(OpenBankError::InsufficientFunds {
+                account_id: from.id,
+                available: available.to_string(),
+                requested: debit.amount.checked_neg()?.to_string(),
+            });
+        }
+        load_account(&tx, &credit.account_id)?;
+
+        post_entry_in_tx(&tx, entry)?;
+        transfer.debit = insert_transaction_in_tx(&tx, transfer.debit)?;
+        transfer.credit = insert_transaction_in_tx(&tx, transfer.credit)?;
+        complete_in_tx(&tx, completion, &transfer)?;
+        tx.commit().map_err(db_error)?;
+        Ok(transfer)
+    }
+
+    fn list_transactions(&self, account_id: &str) -> Result<Vec<Transaction>, OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare(&format!("SELECT {} FROM transactions WHERE account_id = ?1 ORDER BY rowid", TRANSACTION_COLUMNS))
+            .map_err(db_error)?;
+        stmt.query_map(params![account_id], transaction_from_row)
+            .map_err(db_error)?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(db_error)
+    }
+}
+
+impl LedgerRepository for SqliteStorage {
+    fn list_journal_entries(&self) -> Result<Vec<JournalEntry>, OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT id, description, reference, created_at FROM journal_entries ORDER BY created_at, rowid")
+            .map_err(db_error)?;
+        let mut entries = stmt
+            .query_map([], |row| {
+                Ok(JournalEntry {
+                    id: row.get(0)?,
+                    description: row.get(1)?,
+                    reference: row.get(2)?,
+                    created_at: row.get(3)?,
+                    postings: Vec::new(),
+                })
+            })
+            .map_err(db_error)?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(db_error)?;
+
+        let mut stmt = conn
+            .prepare("SELECT ledger_account, side, amount, currency FROM postings WHERE entry_id = ?1 ORDER BY position")
+            .map_err(db_error)?;
+        for entry in &mut entries {
+            entry.postings = stmt
+                .query_map(params![entry.id], |row| {
+                    let currency: String = row.get(3)?;
+                    Ok(Posting {
+                        ledger_account: row.get(0)?,
+                        side: row.get(1)?,
+                        amount: money_from_sql(row.get(2)?, &currency)?,
+                    })
+                })
+                .map_err(db_error)?
+                .collect::<rusqlite::Result<Vec<_>>>()
+                .map_err(db_error)?;
+        }
+        Ok(entries)
+    }
+}
+
+impl HoldRepository for SqliteStorage {
+    fn place_hold(&self, account_id: &str, amount: &Money) -> Result<Hold, OpenBankError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+
+        let account = load_account(&tx, account_id)?;
+        if !account.is_active {
+            return Err(OpenBankError::AccountInactive { account_id: account_id.to_string() });
+        }
+        let available = account.available_balance()?;
+        if available.checked_sub(amount)?.minor_units() < 0 {
+            return Err(OpenBankError::InsufficientFunds {
+                account_id: account_id.to_string(),
+                available: available.to_string(),
+                requested: amount.to_string(),
+            });
+        }
+        let held_balance = account.held_balance.checked_add(amount)?;
+
+        let now = Utc::now();
+        let hold = Hold {
+            id: Uuid::new_v4().to_string(),
+            account_id: account_id.to_string(),
+            amount: amount.clone(),
+            status: HoldStatus::Active,
+            created_at: now,
+            updated_at: now,
+        };
+        tx.execute(
+            "UPDATE accounts SET held_balance = ?1 WHERE id = ?2",
+            params![held_balance.minor_units(), account_id],
+        )
+        .map_err(db_error)?;
+        tx.execute(
+            &format!("INSERT INTO holds ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)", HOLD_COLUMNS),
+            params![
+                hold.id,
+                hold.account_id,
+                hold.amount.minor_units(),
+                hold.amount.currency(),
+                hold.status,
+                hold.created_at,
+                hold.updated_at,
+            ],
+        )
+        .map_err(db_error)?;
+
+        tx.commit().map_err(db_error)?;
+        Ok(hold)
+    }
+
+    fn release_hold(&self, hold_id: &str) -> Result<Hold, OpenBankError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+        let hold = close_hold_in_tx(&tx, hold_id, HoldStatus::Released)?;
+        tx.commit().map_err(db_error)?;
+        Ok(hold)
+    }
+
+    fn capture_hold(&self, hold_id: &str, transaction: Transaction, entry: &JournalEntry) -> Result<Transaction, OpenBankError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+        let transaction = capture_in_tx(&tx, hold_id, transaction, entry)?;
+        tx.commit().map_err(db_error)?;
+        Ok(transaction)
+    }
+}
+
+impl IdempotencyRepository for SqliteStorage {
+    fn reserve_idempotency_key(
+        &self,
+        record: &IdempotencyRecord,
+        stale_before: DateTime<Utc>,
+    ) -> Result<Option<IdempotencyRecord>, OpenBankError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+        let existing = tx
+            .query_row(
+                "SELECT key, fingerprint, response_status, response_body, created_at FROM idempotency_keys WHERE key = ?1",
+                params![record.key],
+                |row| {
+                    Ok(IdempotencyRecord {
+                        key: row.get(0)?,
+                        fingerprint: row.get(1)?,
+                        response_status: row.get(2)?,
+                        response_body: row.get(3)?,
+                        created_at: row.get(4)?,
+                    })
+                },
+            )
+            .optional()
+            .map_err(db_error)?;
+        if let Some(existing) = existing
+            && !is_stale_reservation(&existing, record, stale_before)
+        {
+            return Ok(Some(existing));
+        }
+
+        tx.execute(
+            "INSERT OR REPLACE INTO idempotency_keys (key, fingerprint, created_at) VALUES (?1, ?2, ?3)",
+            params![record.key, record.fingerprint, record.created_at],
+        )
+        .map_err(db_error)?;
+        tx.commit().map_err(db_error)?;
+        Ok(None)
+    }
+
+    fn complete_idempotency_key(&self, completion: &IdempotencyCompletion, body: &str) -> Result<(), OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        complete_key_in_tx(&conn, completion, body)
+    }
+
+    fn remove_idempotency_key(&self, key: &str, reserved_at: DateTime<Utc>) -> Result<(), OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM idempotency_keys WHERE key = ?1 AND response_status IS NULL AND created_at = ?2",
+            params![key, reserved_at],
+        )
+        .map_err(db_error)?;
+        Ok(())
+    }
+}
+
+impl WithdrawalRepository for SqliteStorage {
+    fn insert_withdrawal(&self, withdrawal: &Withdrawal, completion: Option<&IdempotencyCompletion>) -> Result<(), OpenBankError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+        tx.execute(
+            &format!(
+                "INSERT INTO withdrawals ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22)",
+                WITHDRAWAL_COLUMNS
+            ),
+            params![
+                withdrawal.id,
+                withdrawal.user_id,
+                withdrawal.account_id,
+                withdrawal.wallet_address,
+                withdrawal.amount.minor_units(),
+                withdrawal.amount.currency(),
+                withdrawal.description,
+                withdrawal.hold_id,
+                withdrawal.status,
+                withdrawal.tx_hash,
+                withdrawal.nonce,
+                withdrawal.block_number,
+                withdrawal.gas_used,
+                withdrawal.transaction_id,
+                withdrawal.failure_reason,
+                withdrawal.created_at,
+                withdrawal.updated_at,
+                json_to_sql(&withdrawal.replaced_tx_hashes)?,
+                withdrawal.chain_id,
+                withdrawal.usdt_amount.minor_units(),
+                withdrawal.quote_id,
+                json_to_sql(&withdrawal.fees)?,
+            ],
+        )
+        .map_err(db_error)?;
+        complete_in_tx(&tx, completion, withdrawal)?;
+        tx.commit().map_err(db_error)
+    }
+
+    fn get_withdrawal(&self, withdrawal_id: &str) -> Result<Option<Withdrawal>, OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        Ok(query_withdrawals(&conn, "id = ?1", params![withdrawal_id])?.pop())
+    }
+
+    fn list_user_withdrawals(&self, user_id: &str) -> Result<Vec<Withdrawal>, OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        query_withdrawals(&conn, "user_id = ?1", params![user_id])
+    }
+
+    fn list_pending_withdrawals(&self) -> Result<Vec<Withdrawal>, OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        query_withdrawals(&conn, "status IN ('Requested', 'Submitted')", [])
+    }
+
+    fn update_withdrawal(&self, withdrawal: &Withdrawal) -> Result<(), OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        update_withdrawal_in_tx(&conn, withdrawal)
+    }
+
+    fn settle_withdrawal(
+        &self,
+        withdrawal: &Withdrawal,
+        transaction: Transaction,
+        entry: &JournalEntry,
+    ) -> Result<Transaction, OpenBankError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+        let transaction = capture_in_tx(&tx, &withdrawal.hold_id, transaction, entry)?;
+        update_withdrawal_in_tx(&tx, withdrawal)?;
+        tx.commit().map_err(db_error)?;
+        Ok(transaction)
+    }
+
+    fn cancel_withdrawal(&self, withdrawal: &Withdrawal) -> Result<(), OpenBankError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+        close_hold_in_tx(&tx, &withdrawal.hold_id, HoldStatus::Released)?;
+        update_withdrawal_in_tx(&tx, withdrawal)?;
+        tx.commit().map_err(db_error)?;
+        Ok(())
+    }
+}
+
+impl ApiKeyRepository for SqliteStorage {
+    fn insert_api_key(&self, api_key: &ApiKey) -> Result<(), OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO api_keys (key_hash, user_id, created_at) VALUES (?1, ?2, ?3)",
+            params![api_key.key_hash, api_key.user_id, api_key.created_at],
+        )
+        .map_err(db_error)?;
+        Ok(())
+    }
+
+    fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT key_hash, user_id, created_at FROM api_keys WHERE key_hash = ?1",
+            params![key_hash],
+            |row| {
+                Ok(ApiKey {
+                    key_hash: row.get(0)?,
+                    user_id: row.get(1)?,
+                    created_at: row.get(2)?,
+                })
+            },
+        )
+        .optional()
+        .map_err(db_error)
+    }
+}
+
+impl WalletChallengeRepository for SqliteStorage {
+    fn insert_wallet_challenge(&self, challenge: &WalletChallenge) -> Result<(), OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO wallet_challenges (nonce, user_id, wallet_address, wallet_chain_id, created_at, expires_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                challenge.nonce,
+                challenge.user_id,
+                challenge.wallet_address,
+                challenge.wallet_chain_id,
+                challenge.created_at,
+                challenge.expires_at,
+            ],
+        )
+        .map_err(db_error)?;
+        Ok(())
+    }
+
+    fn take_wallet_challenge(&self, nonce: &str) -> Result<Option<WalletChallenge>, OpenBankError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+        let challenge = tx
+            .query_row(
+                "SELECT nonce, user_id, wallet_address, wallet_chain_id, created_at, expires_at
+                 FROM wallet_challenges WHERE nonce = ?1",
+                params![nonce],
+                |row| {
+                    Ok(WalletChallenge {
+                        nonce: row.get(0)?,
+                        user_id: row.get(1)?,
+                        wallet_address: row.get(2)?,
+                        wallet_chain_id: row.get(3)?,
+                        created_at: row.get(4)?,
+                        expires_at: row.get(5)?,
+                    })
+                },
+            )
+            .optional()
+            .map_err(db_error)?;
+        tx.execute("DELETE FROM wallet_challenges WHERE nonce = ?1", params![nonce])
+            .map_err(db_error)?;
+        tx.commit().map_err(db_error)?;
+        Ok(challenge)
+    }
+}
+
+impl QuoteRepository for SqliteStorage {
+    fn insert_quote(&self, quote: &Quote) -> Result<(), OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            &format!(
+                "INSERT INTO quotes ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+                QUOTE_COLUMNS
+            ),
+            params![
+                quote.id,
+                quote.user_id,
+                quote.account_id,
+                quote.amount.minor_units(),
+                quote.amount.currency(),
+                quote.converted_amount.minor_units(),
+                quote.converted_amount.currency(),
+                quote.mid_rate,
+                quote.spread_bps,
+                quote.rate,
+                quote.provider,
+                quote.created_at,
+                quote.expires_at,
+                quote.used_by,
+                json_to_sql(&quote.fees)?,
+                quote.chain_id,
+                quote.purpose,
+            ],
+        )
+        .map_err(db_error)?;
+        Ok(())
+    }
+
+    fn get_quote(&self, quote_id: &str) -> Result<Option<Quote>, OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        load_quote(&conn, quote_id)
+    }
+
+    fn use_quote(&self, quote_id: &str, used_by: &str) -> Result<Quote, OpenBankError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+        let quote = load_quote(&tx, quote_id)?
+            .ok_or_else(|| OpenBankError::QuoteNotFound { quote_id: quote_id.to_string() })?;
+        if quote.used_by.is_some() {
+            return Err(OpenBankError::QuoteAlreadyUsed { quote_id: quote_id.to_string() });
+        }
+        tx.execute("UPDATE quotes SET used_by = ?1 WHERE id = ?2", params![used_by, quote_id])
+            .map_err(db_error)?;
+        tx.commit().map_err(db_error)?;
+        Ok(Quote { used_by: Some(used_by.to_string()), ..quote })
+    }
+}
+
+impl LimitRepository for SqliteStorage {
+    fn list_tier_limits(&self) -> Result<Vec<TierLimits>, OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare("SELECT tier, per_transaction, daily, monthly, updated_at FROM tier_limits ORDER BY tier")
+            .map_err(db_error)?;
+        stmt.query_map([], tier_limits_from_row)
+            .map_err(db_error)?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(db_error)
+    }
+
+    fn get_tier_limits(&self, tier: &str) -> Result<Option<TierLimits>, OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT tier, per_transaction, daily, monthly, updated_at FROM tier_limits WHERE tier = ?1",
+            params![tier],
+            tier_limits_from_row,
+        )
+        .optional()
+        .map_err(db_error)
+    }
+
+    fn set_tier_limits(&self, limits: &TierLimits) -> Result<(), OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO tier_limits (tier, per_transaction, daily, monthly, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                limits.tier,
+                limits.per_transaction.as_ref().map(Money::minor_units),
+                limits.daily.as_ref().map(Money::minor_units),
+                limits.monthly.as_ref().map(Money::minor_units),
+                limits.updated_at,
+            ],
+        )
+        .map_err(db_error)?;
+        Ok(())
+    }
+
+    fn get_global_limits(&self) -> Result<Option<GlobalLimits>, OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT hourly_outflow, updated_at FROM global_limits WHERE id = 1",
+            [],
+            |row| {
+                Ok(GlobalLimits {
+                    hourly_outflow: limit_from_sql(row.get(0)?)?,
+                    updated_at: row.get(1)?,
+                })
+            },
+        )
+        .optional()
+        .map_err(db_error)
+    }
+
+    fn set_global_limits(&self, limits: &GlobalLimits) -> Result<(), OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO global_limits (id, hourly_outflow, updated_at) VALUES (1, ?1, ?2)",
+            params![limits.hourly_outflow.as_ref().map(Money::minor_units), limits.updated_at],
+        )
+        .map_err(db_error)?;
+        Ok(())
+    }
+}
+
+impl ChainEventRepository for SqliteStorage {
+    fn get_indexer_cursor(&self, chain_id: u64) -> Result<Option<IndexerCursor>, OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT block_number, block_hash FROM indexer_cursors WHERE chain_id = ?1",
+            params![chain_id],
+            |row| {
+                Ok(IndexerCursor {
+                    chain_id,
+                    block_number: row.get(0)?,
+                    block_hash: row.get(1)?,
+                })
+            },
+        )
+        .optional()
+        .map_err(db_error)
+    }
+
+    fn record_chain_events(&self, events: &[ChainEvent], cursor: &IndexerCursor) -> Result<(), OpenBankError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+        for event in events {
+            tx.execute(
+                &format!("INSERT OR IGNORE INTO chain_events ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)", CHAIN_EVENT_COLUMNS),
+                params![
+                    event.id,
+                    event.kind,
+                    event.address,
+                    event.amount.to_string(),
+                    event.description,
+                    event.timestamp,
+                    event.block_number,
+                    event.block_hash,
+                    event.tx_hash,
+                    event.log_index,
+                    event.chain_id,
+                ],
+            )
+            .map_err(db_error)?;
+        }
+        set_cursor_in_tx(&tx, cursor)?;
+        tx.commit().map_err(db_error)?;
+        Ok(())
+    }
+
+    fn rewind_chain_events(&self, chain_id: u64, cursor: Option<&IndexerCursor>) -> Result<(), OpenBankError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(db_error)?;
+        tx.execute(
+            "DELETE FROM chain_events WHERE chain_id = ?1 AND (?2 IS NULL OR block_number > ?2)",
+            params![chain_id, cursor.map(|cursor| cursor.block_number)],
+        )
+        .map_err(db_error)?;
+        match cursor {
+            Some(cursor) => set_cursor_in_tx(&tx, cursor)?,
+            None => {
+                tx.execute("DELETE FROM indexer_cursors WHERE chain_id = ?1", params![chain_id])
+                    .map_err(db_error)?;
+            }
+        }
+        tx.commit().map_err(db_error)?;
+        Ok(())
+    }
+
+    fn list_chain_events(
+        &self,
+        chain_id: Option<u64>,
+        kind: Option<ChainEventKind>,
+        address: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<ChainEvent>, OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {} FROM chain_events
+                 WHERE (?1 IS NULL OR chain_id = ?1) AND (?2 IS NULL OR kind = ?2) AND (?3 IS NULL OR address = lower(?3))
+                 ORDER BY block_number DESC, log_index DESC
+                 LIMIT ?4",
+                CHAIN_EVENT_COLUMNS
+            ))
+            .map_err(db_error)?;
+        stmt.query_map(params![chain_id, kind, address, limit], chain_event_from_row)
+            .map_err(db_error)?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(db_error)
+    }
+}
+
+impl ReconciliationRepository for SqliteStorage {
+    fn insert_reconciliation_report(&self, report: &ReconciliationReport) -> Result<(), OpenBankError> {
+        let body = serde_json::to_string(report).map_err(|e| OpenBankError::StorageError {
+            message: format!("Failed to encode reconciliation report: {}", e),
+        })?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO reconciliation_reports (id, generated_at, report) VALUES (?1, ?2, ?3)",
+            params![report.id, report.generated_at, body],
+        )
+        .map_err(db_error)?;
+        Ok(())
+    }
+
+    fn latest_reconciliation_report(&self) -> Result<Option<ReconciliationReport>, OpenBankError> {
+        let conn = self.conn.lock().unwrap();
+        let body: Option<String> = conn
+            .query_row(
+                "SELECT report FROM reconciliation_reports ORDER BY generated_at DESC, rowid DESC LIMIT 1",
+                [],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(db_error)?;
+
+        body.map(|body| {
+            serde_json::from_str(&body).map_err(|e| OpenBankError::StorageError {
+                message: format!("Failed to decode reconciliation report: {}", e),
+            })
+        })
+        .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrations_bring_a_fresh_database_to_the_latest_version() {
+        let storage = SqliteStorage::with_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let conn = storage.conn.lock().unwrap();
+        let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap();
+        assert_eq!(version, 18);
+        assert_eq!(version, MIGRATIONS.len());
+        drop(conn);
+
+        // Reopening applies nothing twice
+        let conn = storage.conn.into_inner().unwrap();
+        let storage = SqliteStorage::with_connection(conn).unwrap();
+        let version: usize = storage.conn.lock().unwrap().pragma_query_value(None, "user_version", |row| row.get(0)).unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+    }
+}
@@ -1,6 +1,11 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use crate::error::OpenBankError;
+use ethers::types::U256;
+use crate::error::{ApiError, OpenBankError};
+use crate::gas::GasPolicy;
+use crate::rpc::RpcPolicy;
+use crate::signer::SignerConfig;
+use crate::money::{u256_decimal, Money, Rate};
 
 // Data structures
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -9,21 +14,39 @@ pub struct User {
     pub email: String,
     pub name: String,
     pub wallet_address: Option<String>, // Ethereum wallet address
+    #[serde(default)]
+    pub wallet_chain_id: Option<u64>, // Chain the wallet is used on, None for any configured chain
+    #[serde(default = "default_tier")]
+    pub tier: String, // Picks the user's withdrawal limits
     pub created_at: DateTime<Utc>,
     pub accounts: Vec<String>, // Account IDs
 }
 
+pub const DEFAULT_TIER: &str = "standard";
+
+fn default_tier() -> String {
+    DEFAULT_TIER.to_string()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Account {
     pub id: String,
     pub user_id: String,
     pub account_type: AccountType,
-    pub balance: f64,
+    pub balance: Money,
+    pub held_balance: Money, // Reserved by in-flight withdrawals
     pub currency: String,
     pub created_at: DateTime<Utc>,
     pub is_active: bool,
 }
 
+impl Account {
+    // Funds that can still be withdrawn once active holds are taken out
+    pub fn available_balance(&self) -> Result<Money, OpenBankError> {
+        self.balance.checked_sub(&self.held_balance)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum AccountType {
     Deposit, // Account for tracking deposits
@@ -35,42 +58,365 @@ pub struct Transaction {
     pub user_id: String,
     pub account_id: String,
     pub transaction_type: TransactionType,
-    pub amount: f64,
+    pub amount: Money,
     pub description: String,
     pub timestamp: DateTime<Utc>,
-    pub balance_after: f64,
+    pub balance_after: Money,
+    #[serde(default)]
+    pub tx_hash: Option<String>, // On-chain transaction that settled it, for withdrawals
+    #[serde(default)]
+    pub transfer_id: Option<String>, // Shared by both sides of an internal transfer
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TransactionType {
     Deposit,
     Transfer,
+    Withdrawal,
+}
+
+// Balanced set of postings recorded together. Every change to an account
+// balance goes through one; see ledger.rs for the ledger accounts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub id: String,
+    pub description: String,
+    pub reference: Option<String>, // Transaction or withdrawal the entry belongs to
+    pub created_at: DateTime<Utc>,
+    pub postings: Vec<Posting>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Posting {
+    pub ledger_account: String, // e.g. "user:<account id>", "clearing:USD", "usdt_inventory"
+    pub side: PostingSide,
+    pub amount: Money, // Always positive; the side gives the direction
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PostingSide {
+    Debit,
+    Credit,
+}
+
+// Result of checking the ledger invariants
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerCheckReport {
+    pub checked_at: DateTime<Utc>,
+    pub entries_checked: usize,
+    pub accounts_checked: usize,
+    pub balances: Vec<LedgerBalance>, // Trial balance, one line per ledger account
+    pub violations: Vec<LedgerViolation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerBalance {
+    pub ledger_account: String,
+    pub debits: Money,
+    pub credits: Money,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerViolation {
+    pub kind: LedgerViolationKind,
+    pub reference: String, // Journal entry id, ledger account or currency, depending on the kind
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LedgerViolationKind {
+    UnbalancedEntry,       // An entry's debits and credits differ in some currency
+    TrialBalanceMismatch,  // Total debits and credits differ in some currency
+    AccountBalanceMismatch, // An account balance differs from its postings or its last transaction
+    NegativeBalance,       // A user account owes money, or holds more than it has
+}
+
+// Funds reserved on an account while a withdrawal is being sent on-chain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hold {
+    pub id: String,
+    pub account_id: String,
+    pub amount: Money,
+    pub status: HoldStatus,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HoldStatus {
+    Active,
+    Released, // Withdrawal failed, funds returned to the available balance
+    Captured, // Withdrawal succeeded, funds debited from the balance
+}
+
+// First response stored for an Idempotency-Key, replayed on retries
+#[derive(Debug, Clone)]
+pub struct IdempotencyRecord {
+    pub key: String,
+    pub fingerprint: String, // Hash of the endpoint, path and body of the first request
+    pub response_status: Option<u16>, // None while the first request is still running
+    pub response_body: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+// Response a write completes its idempotency key with, stored in the same step
+// as the write so the key can't stay in progress once the write happened
+#[derive(Debug, Clone)]
+pub struct IdempotencyCompletion {
+    pub key: String,
+    pub reserved_at: DateTime<Utc>, // created_at of the reservation, which must still be in progress
+    pub status: u16,
+}
+
+// API key issued to an end user; only a hash of the key is stored
+#[derive(Debug, Clone)]
+pub struct ApiKey {
+    pub key_hash: String,
+    pub user_id: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// Nonce a wallet has to sign before it is bound to a user. Single use.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalletChallenge {
+    pub nonce: String,
+    pub user_id: String,
+    pub wallet_address: String, // EIP-55 checksummed
+    pub wallet_chain_id: Option<u64>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignatureType {
+    #[default]
+    PersonalSign, // EIP-191, over WalletChallengeResponse.message
+    Eip712,       // eth_signTypedData_v4, over WalletChallengeResponse.typed_data
+}
+
+// USDT payout to a user's wallet, funded by a hold on one of their fiat accounts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Withdrawal {
+    pub id: String,
+    pub user_id: String,
+    pub account_id: String,
+    pub wallet_address: String,
+    #[serde(default)]
+    pub chain_id: Option<u64>, // None for withdrawals made before multi-chain support, sent on the default chain
+    pub amount: Money, // Held and debited in the account currency
+    pub usdt_amount: Money, // Sent on-chain; the quote's converted amount, or priced the same way at the current rate without one
+    #[serde(default)]
+    pub quote_id: Option<String>,
+    #[serde(default)]
+    pub fees: Vec<FeeLine>, // Included in `amount`, credited to the fee account once confirmed
+    pub description: String,
+    pub hold_id: String,
+    pub status: WithdrawalStatus,
+    pub tx_hash: Option<String>,
+    pub nonce: Option<u64>,
+    #[serde(default)]
+    pub replaced_tx_hashes: Vec<String>, // Earlier attempts with the same nonce, any of which may still be mined
+    pub block_number: Option<u64>,
+    pub gas_used: Option<u64>,
+    pub transaction_id: Option<String>, // Fiat debit recorded once confirmed
+    pub failure_reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WithdrawalStatus {
+    Requested, // Accepted and funds held, waiting for the worker
+    Submitted, // Signed and broadcast, waiting for confirmations
+    Confirmed, // Mined successfully, hold captured
+    Failed,    // Rejected or reverted, hold released
+    Replaced,  // Nonce consumed by another transaction, hold released
 }
 
 // Smart Contract related types
 #[derive(Debug, Clone)]
 pub struct SmartContractConfig {
     pub contract_address: String,
-    pub owner_private_key: String,
-    pub rpc_url: String,
+    pub signer: SignerConfig,
+    pub rpc_urls: Vec<String>, // In order of preference
+    pub ws_url: Option<String>, // For subscriptions
+    pub rpc_policy: RpcPolicy,
     pub chain_id: u64,
+    pub confirmations: usize,
+    pub gas_policy: GasPolicy,
 }
 
+// Outcome of a mined contract write
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TxReceipt {
+    pub tx_hash: String,
+    pub block_number: Option<u64>,
+    pub gas_used: Option<u64>,
+    pub status: TxStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TxStatus {
+    Success,
+    Reverted,
+}
+
+// Amounts are USDT base units (6 decimals), kept as U256 so nothing is truncated
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ContractUserBalance {
-    pub deposited: u64,
-    pub withdrawn: u64,
-    pub last_deposit: u64,
+    #[serde(with = "u256_decimal")]
+    pub deposited: U256,
+    #[serde(with = "u256_decimal")]
+    pub withdrawn: U256,
+    pub last_deposit: u64, // Unix timestamps
     pub last_withdrawal: u64,
     pub has_deposited: bool,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContractStats {
+    #[serde(with = "u256_decimal")]
+    pub total_users: U256,
+    #[serde(with = "u256_decimal")]
+    pub total_deposits: U256,
+    #[serde(with = "u256_decimal")]
+    pub total_withdrawals: U256,
+    #[serde(with = "u256_decimal")]
+    pub contract_balance: U256, // USDT currently held by the contract
+    #[serde(with = "u256_decimal")]
+    pub total_transactions: U256,
+}
+
+// Entry of the contract's own transaction log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContractTransaction {
+    pub id: u64,
+    pub user: String,
+    #[serde(with = "u256_decimal")]
+    pub amount: U256, // USDT base units (6 decimals)
+    pub timestamp: u64,
+    pub is_deposit: bool,
+    pub description: String,
+}
+
+// Contract event picked up by the indexer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainEvent {
+    pub id: String, // "<chain_id>:<tx_hash>:<log_index>", unique per log
+    pub chain_id: u64,
+    pub kind: ChainEventKind,
+    pub address: String, // User for deposits/withdrawals, owner for emergency withdrawals, token for USDTTokenSet
+    #[serde(with = "u256_decimal")]
+    pub amount: U256, // USDT base units, zero for USDTTokenSet
+    pub description: Option<String>,
+    pub timestamp: Option<u64>, // Contract-provided timestamp, when the event has one
+    pub block_number: u64,
+    pub block_hash: String,
+    pub tx_hash: String,
+    pub log_index: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChainEventKind {
+    DepositMade,
+    WithdrawalMade,
+    EmergencyWithdraw,
+    UsdtTokenSet,
+}
+
+// Last block the indexer has fully processed on a chain
+#[derive(Debug, Clone)]
+pub struct IndexerCursor {
+    pub chain_id: u64,
+    pub block_number: u64,
+    pub block_hash: String,
+}
+
+// Outcome of one comparison between the fiat ledger and the contract.
+// Amounts are USDT base units, one account unit per USDT.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReconciliationReport {
+    pub id: String,
+    pub generated_at: DateTime<Utc>,
+    #[serde(default)]
+    pub chain_ids: Vec<u64>, // Chains whose totals were added up for the comparison
+    pub users_checked: usize, // Users with a wallet whose on-chain totals were compared
+    #[serde(with = "u256_decimal")]
+    pub contract_usdt_balance: U256,
+    #[serde(with = "u256_decimal")]
+    pub outstanding_liabilities: U256, // Sum of all fiat account balances
+    #[serde(with = "u256_decimal")]
+    pub in_flight: U256, // Submitted withdrawals that may already have left the contract
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Discrepancy {
+    pub kind: DiscrepancyKind,
+    pub user_id: Option<String>,
+    pub wallet_address: Option<String>,
+    #[serde(with = "u256_decimal")]
+    pub expected: U256, // What the fiat ledger implies
+    #[serde(with = "u256_decimal")]
+    pub actual: U256, // What the contract reports
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiscrepancyKind {
+    WithdrawnMismatch, // Fiat withdrawal debits differ from the WithdrawalMade events to the wallet
+    ReserveShortfall,  // Contract USDT does not cover outstanding fiat balances
+}
+
 // Request/Response structures
 #[derive(Debug, Deserialize)]
 pub struct CreateUserRequest {
     pub email: String,
     pub name: String,
     pub wallet_address: Option<String>,
+    pub wallet_chain_id: Option<u64>,
+}
+
+// The API key is only ever shown in this response. A wallet given at
+// registration is not bound yet; it comes back as a challenge to sign.
+#[derive(Debug, Serialize)]
+pub struct CreateUserResponse {
+    #[serde(flatten)]
+    pub user: User,
+    pub api_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wallet_challenge: Option<WalletChallengeResponse>,
+}
+
+// Fields left out are kept as they are
+#[derive(Debug, Deserialize)]
+pub struct UpdateUserRequest {
+    pub email: Option<String>,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WalletChallengeRequest {
+    pub wallet_address: String,
+    pub wallet_chain_id: Option<u64>,
+}
+
+// A challenge together with both forms the wallet may sign
+#[derive(Debug, Serialize)]
+pub struct WalletChallengeResponse {
+    #[serde(flatten)]
+    pub challenge: WalletChallenge,
+    pub message: String,
+    pub typed_data: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyWalletRequest {
+    pub nonce: String,
+    pub signature: String, // 65-byte hex
+    #[serde(default)]
+    pub signature_type: SignatureType,
 }
 
 #[derive(Debug, Deserialize)]
@@ -78,22 +424,188 @@ pub struct CreateAccountRequest {
     pub currency: String, // e.g., "USD", "EUR", "GBP"
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct DepositRequest {
-    pub amount: f64,
+    pub amount: String, // decimal string in the account currency, e.g. "10.50"
     pub description: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct WithdrawRequest {
     pub user_id: String,
-    pub amount: f64,
+    pub account_id: String, // Fiat account debited for the withdrawal
+    pub amount: String, // decimal string in the account currency, e.g. "0.29"
     pub description: Option<String>,
+    // Left out of the idempotency fingerprint when unset, so older keys still match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chain_id: Option<u64>, // Defaults to the wallet's chain, then the default chain
+    // Required unless the account is in USD; `amount` must match the quoted amount
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransferRequest {
+    pub from_account_id: String,
+    pub to_account_id: String,
+    pub amount: String, // decimal string in the source account currency
+    pub description: Option<String>,
+    // Between currencies either a quote for this amount into the target currency,
+    // or (operators only) target currency units per source unit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fx_rate: Option<Rate>,
+}
+
+// Both sides of an internal transfer, recorded together
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Transfer {
+    pub id: String,
+    pub from_account_id: String,
+    pub to_account_id: String,
+    pub amount: Money, // Debited from the source account, fees included
+    pub converted_amount: Money, // Credited to the target account
+    pub fx_rate: Option<Rate>,
+    pub quote_id: Option<String>,
+    pub fees: Vec<FeeLine>,
+    pub debit: Transaction,
+    pub credit: Transaction,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QuoteRequest {
+    pub account_id: String, // Account the amount comes out of
+    pub amount: String, // decimal string in the account currency
+    pub currency: Option<String>, // Currency to convert into, USDT by default
+    pub purpose: Option<QuotePurpose>, // Withdrawal for USDT, transfer otherwise
+    // For USDT quotes; defaults to the wallet's chain, then the default chain
+    pub chain_id: Option<u64>,
+}
+
+// Locked conversion of an amount out of a fiat account, spent by one
+// withdrawal or transfer before it expires
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Quote {
+    pub id: String,
+    pub user_id: String,
+    pub account_id: String,
+    pub amount: Money, // In the account currency, fees included
+    pub fees: Vec<FeeLine>, // Conversion fee, plus the withdrawal fee for withdrawal quotes
+    pub converted_amount: Money, // amount less fees at `rate`, rounded down
+    pub mid_rate: Rate, // From the rate provider
+    pub spread_bps: u32,
+    pub rate: Rate, // mid_rate less the spread
+    pub provider: String,
+    pub purpose: QuotePurpose,
+    pub chain_id: Option<u64>, // Chain a withdrawal quote's fee was priced for
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub used_by: Option<String>, // Withdrawal or transfer that spent it
+}
+
+// Withdrawal caps of a user tier, in USDT; None is unlimited. Daily and monthly
+// caps cover the last 24 hours and 30 days of withdrawals that haven't failed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TierLimits {
+    pub tier: String,
+    pub per_transaction: Option<Money>,
+    pub daily: Option<Money>,
+    pub monthly: Option<Money>,
+    pub updated_at: Option<DateTime<Utc>>, // None for the defaults from the environment
+}
+
+// Cap on the USDT leaving each contract per rolling hour, across all users
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GlobalLimits {
+    pub hourly_outflow: Option<Money>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LimitSettings {
+    pub defaults: TierLimits, // For tiers without limits of their own
+    pub tiers: Vec<TierLimits>,
+    pub global: GlobalLimits,
+}
+
+// A user's limits and how much of them recent withdrawals used
+#[derive(Debug, Serialize)]
+pub struct UserLimits {
+    pub user_id: String,
+    pub limits: TierLimits,
+    pub daily_used: Money,
+    pub monthly_used: Money,
+}
+
+// USDT amounts as decimal strings; left out or null for no limit
+#[derive(Debug, Deserialize)]
+pub struct SetTierLimitsRequest {
+    pub per_transaction: Option<String>,
+    pub daily: Option<String>,
+    pub monthly: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetGlobalLimitsRequest {
+    pub hourly_outflow: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetUserTierRequest {
+    pub tier: String,
+}
+
+// What a quote may be spent on; only withdrawal quotes carry a withdrawal fee
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotePurpose {
+    Withdrawal, // Paying USDT out on-chain
+    Transfer,   // Crediting another account
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeeKind {
+    Conversion, // Exchanging one currency for another
+    Withdrawal, // Paying USDT out on-chain
+}
+
+// A fee charged on an operation, in the currency of the account paying it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeeLine {
+    pub kind: FeeKind,
+    pub amount: Money,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetUsdtTokenRequest {
+    pub token_address: String,
+}
+
+// Picks the chain for contract routes; the default chain when left out
+#[derive(Debug, Deserialize)]
+pub struct ChainQuery {
+    pub chain_id: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContractTransactionsQuery {
+    pub chain_id: Option<u64>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChainEventsQuery {
+    pub chain_id: Option<u64>,
+    pub kind: Option<ChainEventKind>,
+    pub address: Option<String>,
+    pub limit: Option<usize>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct ApiResponse<T> {
     pub success: bool,
     pub data: Option<T>,
-    pub error: Option<OpenBankError>,
+    pub error: Option<ApiError>,
 }
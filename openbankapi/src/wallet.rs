@@ -0,0 +1,221 @@
+use chrono::{Duration, SubsecRound, Utc};
+use ethers::core::rand::{thread_rng, RngCore};
+use ethers::core::types::{
+    transaction::eip712::{Eip712, TypedData},
+    Address, Signature, H256,
+};
+use ethers::utils::to_checksum;
+use serde_json::json;
+use crate::error::OpenBankError;
+use crate::types::{SignatureType, WalletChallenge};
+
+const DEFAULT_CHALLENGE_TTL_SECS: i64 = 600;
+const EIP712_DOMAIN_NAME: &str = "OnrampTee";
+const EIP712_DOMAIN_VERSION: &str = "1";
+
+/// Parses a `0x`-prefixed 20-byte hex address. All-lowercase and all-uppercase
+/// addresses are taken as is; mixed case must be a valid EIP-55 checksum.
+pub fn parse_address(address: &str) -> Result<Address, OpenBankError> {
+    let invalid = || OpenBankError::InvalidWalletAddress { address: address.to_string() };
+    let digits = address.strip_prefix("0x").ok_or_else(invalid)?;
+    if digits.len() != 40 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let parsed: Address = digits.parse().map_err(|_e| invalid())?;
+
+    let mixed_case = digits.bytes().any(|b| b.is_ascii_lowercase()) && digits.bytes().any(|b| b.is_ascii_uppercase());
+    if mixed_case && to_checksum(&parsed, None) != address {
+        return Err(invalid());
+    }
+    Ok(parsed)
+}
+
+// Form addresses are stored in, so lookups don't depend on the client's casing
+pub fn checksum(address: &Address) -> String {
+    to_checksum(address, None)
+}
+
+// New single-use challenge for binding `address` to the user. Its lifetime
+// comes from WALLET_CHALLENGE_TTL_SECS.
+pub fn new_challenge(user_id: &str, address: &Address, wallet_chain_id: Option<u64>) -> WalletChallenge {
+    let ttl = std::env::var("WALLET_CHALLENGE_TTL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_CHALLENGE_TTL_SECS);
+    let mut nonce = [0u8; 16];
+    thread_rng().fill_bytes(&mut nonce);
+
+    // Whole seconds, so the signed text survives a round trip through storage
+    let now = Utc::now().trunc_subsecs(0);
+    WalletChallenge {
+        nonce: hex::encode(nonce),
+        user_id: user_id.to_string(),
+        wallet_address: checksum(address),
+        wallet_chain_id,
+        created_at: now,
+        expires_at: now + Duration::seconds(ttl),
+    }
+}
+
+// Text signed with personal_sign (EIP-191)
+pub fn challenge_message(challenge: &WalletChallenge) -> String {
+    let chain = challenge.wallet_chain_id.map(|id| id.to_string()).unwrap_or_else(|| "any".to_string());
+    format!(
+        "OnrampTee wants you to link this wallet to your account.\n\nUser: {}\nWallet: {}\nChain: {}\nNonce: {}\nExpires: {}",
+        challenge.user_id,
+        challenge.wallet_address,
+        chain,
+        challenge.nonce,
+        challenge.expires_at.to_rfc3339(),
+    )
+}
+
+// The same challenge as EIP-712 typed data, for eth_signTypedData_v4. The
+// domain only names a chain when the wallet is bound to one.
+pub fn challenge_typed_data(challenge: &WalletChallenge) -> serde_json::Value {
+    let mut domain_type = vec![
+        json!({ "name": "name", "type": "string" }),
+        json!({ "name": "version", "type": "string" }),
+    ];
+    let mut domain = json!({ "name": EIP712_DOMAIN_NAME, "version": EIP712_DOMAIN_VERSION });
+    if let Some(chain_id) = challenge.wallet_chain_id {
+        domain_type.push(json!({ "name": "chainId", "type": "uint256" }));
+        domain["chainId"] = json!(chain_id);
+    }
+
+    json!({
+        "types": {
+            "EIP712Domain": domain_type,
+            "WalletLink": [
+                { "name": "user", "type": "string" },
+                { "name": "wallet", "type": "address" },
+                { "name": "nonce", "type": "string" },
+                { "name": "expiresAt", "type": "uint256" },
+            ],
+        },
+        "primaryType": "WalletLink",
+        "domain": domain,
+        "message": {
+            "user": challenge.user_id,
+            "wallet": challenge.wallet_address,
+            "nonce": challenge.nonce,
+            "expiresAt": challenge.expires_at.timestamp(),
+        },
+    })
+}
+
+/// Checks that `signature` over the challenge was made by the challenged wallet.
+pub fn verify_challenge(
+    challenge: &WalletChallenge,
+    signature: &str,
+    signature_type: SignatureType,
+) -> Result<Address, OpenBankError> {
+    let address = parse_address(&challenge.wallet_address)?;
+    let invalid = || OpenBankError::InvalidWalletSignature { address: challenge.wallet_address.clone() };
+    let signature: Signature = signature.trim_start_matches("0x").parse().map_err(|_e| invalid())?;
+
+    let recovered = match signature_type {
+        SignatureType::PersonalSign => signature.recover(challenge_message(challenge)),
+        SignatureType::Eip712 => {
+            // Built from our own fields, so this only fails on a bug
+            let digest = serde_json::from_value::<TypedData>(challenge_typed_data(challenge))
+                .map_err(|e| e.to_string())
+                .and_then(|typed_data| typed_data.encode_eip712().map_err(|e| e.to_string()))
+                .map_err(|message| OpenBankError::InvalidRequest { message })?;
+            signature.recover(H256::from(digest))
+        }
+    };
+
+    match recovered {
+        Ok(signer) if signer == address => Ok(address),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::signers::{LocalWallet, Signer};
+
+    // Well known development keys, so the addresses below are fixed
+    const KEY: &str = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+    const ADDRESS: &str = "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266";
+    const OTHER_KEY: &str = "59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d";
+
+    fn signer(key: &str) -> LocalWallet {
+        key.parse().unwrap()
+    }
+
+    fn challenge(wallet_chain_id: Option<u64>) -> WalletChallenge {
+        new_challenge("alice", &parse_address(ADDRESS).unwrap(), wallet_chain_id)
+    }
+
+    #[test]
+    fn addresses_must_be_hex_with_a_valid_checksum_when_mixed_case() {
+        let address = parse_address(ADDRESS).unwrap();
+        assert_eq!(checksum(&address), ADDRESS);
+        assert_eq!(parse_address(&ADDRESS.to_lowercase()).unwrap(), address);
+        assert_eq!(parse_address(&format!("0x{}", ADDRESS[2..].to_uppercase())).unwrap(), address);
+
+        // One letter with the wrong case breaks the checksum
+        let miscased = ADDRESS.replacen("Fd6", "fd6", 1);
+        let invalid = [
+            miscased.as_str(),
+            &ADDRESS[2..],
+            &ADDRESS[..41],
+            "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb9226g",
+            "0x",
+        ];
+        for address in invalid {
+            assert!(matches!(parse_address(address), Err(OpenBankError::InvalidWalletAddress { .. })), "{}", address);
+        }
+    }
+
+    #[tokio::test]
+    async fn personal_sign_recovers_the_challenged_wallet() {
+        let challenge = challenge(None);
+        let signature = signer(KEY).sign_message(challenge_message(&challenge)).await.unwrap();
+        let address = verify_challenge(&challenge, &format!("0x{}", signature), SignatureType::PersonalSign).unwrap();
+        assert_eq!(checksum(&address), ADDRESS);
+
+        // The same signature doesn't stand in for the typed data
+        let result = verify_challenge(&challenge, &signature.to_string(), SignatureType::Eip712);
+        assert!(matches!(result, Err(OpenBankError::InvalidWalletSignature { .. })));
+    }
+
+    #[tokio::test]
+    async fn eip712_recovers_the_challenged_wallet() {
+        for wallet_chain_id in [None, Some(137)] {
+            let challenge = challenge(wallet_chain_id);
+            let typed_data: TypedData = serde_json::from_value(challenge_typed_data(&challenge)).unwrap();
+            let signature = signer(KEY).sign_typed_data(&typed_data).await.unwrap();
+            let address = verify_challenge(&challenge, &signature.to_string(), SignatureType::Eip712).unwrap();
+            assert_eq!(checksum(&address), ADDRESS, "{:?}", wallet_chain_id);
+        }
+    }
+
+    #[tokio::test]
+    async fn other_signers_and_malformed_signatures_are_rejected() {
+        let challenge = challenge(Some(1));
+        let other = signer(OTHER_KEY);
+        let signature = other.sign_message(challenge_message(&challenge)).await.unwrap();
+        let result = verify_challenge(&challenge, &signature.to_string(), SignatureType::PersonalSign);
+        assert!(matches!(result, Err(OpenBankError::InvalidWalletSignature { address }) if address == ADDRESS));
+
+        let typed_data: TypedData = serde_json::from_value(challenge_typed_data(&challenge)).unwrap();
+        let signature = other.sign_typed_data(&typed_data).await.unwrap();
+        let result = verify_challenge(&challenge, &signature.to_string(), SignatureType::Eip712);
+        assert!(matches!(result, Err(OpenBankError::InvalidWalletSignature { .. })));
+
+        // A signature over another challenge of the same wallet doesn't carry over
+        let earlier = self::challenge(Some(1));
+        let signature = signer(KEY).sign_message(challenge_message(&earlier)).await.unwrap();
+        let result = verify_challenge(&challenge, &signature.to_string(), SignatureType::PersonalSign);
+        assert!(matches!(result, Err(OpenBankError::InvalidWalletSignature { .. })));
+
+        for signature in ["", "0x1234", "not hex"] {
+            let result = verify_challenge(&challenge, signature, SignatureType::PersonalSign);
+            assert!(matches!(result, Err(OpenBankError::InvalidWalletSignature { .. })), "{}", signature);
+        }
+    }
+}
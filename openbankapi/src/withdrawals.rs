@@ -0,0 +1,267 @@
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::Utc;
+use uuid::Uuid;
+use crate::contract::{ContractClient, ContractClients, TxProgress};
+use crate::error::OpenBankError;
+use crate::ledger;
+use crate::money::Money;
+use crate::storage::Storage;
+use crate::types::{Transaction, TransactionType, TxStatus, Withdrawal, WithdrawalStatus};
+
+const DEFAULT_POLL_INTERVAL_SECS: u64 = 5;
+// An unmined transaction is re-signed with higher fees after this long
+const DEFAULT_STUCK_AFTER_SECS: i64 = 180;
+// After this many replacements a transaction the node has lost is given up on
+const MAX_REPLACEMENTS: usize = 5;
+
+// Starts the background task that moves withdrawals through
+// Requested -> Submitted -> Confirmed / Failed / Replaced.
+// Poll interval comes from WITHDRAWAL_POLL_INTERVAL_SECS, and how long a
+// transaction may stay unmined before it is replaced from WITHDRAWAL_STUCK_AFTER_SECS.
+pub fn spawn_worker(storage: Arc<dyn Storage>, contracts: Arc<ContractClients>) {
+    let interval = std::env::var("WITHDRAWAL_POLL_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_POLL_INTERVAL_SECS);
+    let stuck_after = std::env::var("WITHDRAWAL_STUCK_AFTER_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_STUCK_AFTER_SECS);
+
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+        loop {
+            ticker.tick().await;
+            if let Err(e) = process_pending(storage.as_ref(), &contracts, stuck_after).await {
+                println!("Warning: Withdrawal worker could not load pending withdrawals: {:?}", e);
+            }
+        }
+    });
+}
+
+// Withdrawals are handled one at a time so nonces are assigned in order,
+// each on the chain it was requested for
+async fn process_pending(
+    storage: &dyn Storage,
+    contracts: &ContractClients,
+    stuck_after: i64,
+) -> Result<(), OpenBankError> {
+    for withdrawal in storage.list_pending_withdrawals()? {
+        let withdrawal_id = withdrawal.id.clone();
+        let contract_client = match contracts.get(withdrawal.chain_id) {
+            Ok(contract_client) => contract_client,
+            Err(e) => {
+                println!("Warning: Could not process withdrawal {}: {:?}", withdrawal_id, e);
+                continue;
+            }
+        };
+        let result = match withdrawal.status {
+            WithdrawalStatus::Requested => submit(storage, &contract_client, withdrawal).await,
+            WithdrawalStatus::Submitted => track(storage, &contract_client, withdrawal, stuck_after).await,
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            println!("Warning: Could not process withdrawal {}: {:?}", withdrawal_id, e);
+        }
+    }
+    Ok(())
+}
+
+async fn submit(
+    storage: &dyn Storage,
+    contract_client: &ContractClient,
+    mut withdrawal: Withdrawal,
+) -> Result<(), OpenBankError> {
+    let signed = match withdrawal.usdt_amount.to_usdt_base_units() {
+        Ok(amount_usdt) => {
+            contract_client
+                .sign_usdt_transfer(withdrawal.wallet_address.clone(), amount_usdt, withdrawal.description.clone())
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    // Nothing was broadcast, so the funds can go straight back
+    let signed = match signed {
+        Ok(signed) => signed,
+        Err(e) => return fail(storage, withdrawal, WithdrawalStatus::Failed, e.to_string()),
+    };
+
+    // Record the hash before broadcasting so a crash can't lead to sending twice
+    withdrawal.status = WithdrawalStatus::Submitted;
+    withdrawal.tx_hash = Some(signed.tx_hash.clone());
+    withdrawal.nonce = Some(signed.nonce);
+    withdrawal.updated_at = Utc::now();
+    if let Err(e) = storage.update_withdrawal(&withdrawal) {
+        // The signed transaction is dropped, so its nonce must not be skipped
+        contract_client.resync_nonce().await;
+        return Err(e);
+    }
+
+    // A broadcast error is ambiguous (the node may still have it), so tracking decides
+    if let Err(e) = contract_client.broadcast(&signed).await {
+        println!("Warning: Broadcast of withdrawal {} failed: {:?}", withdrawal.id, e);
+    } else {
+        println!("Withdrawal {} submitted in {}", withdrawal.id, signed.tx_hash);
+    }
+    Ok(())
+}
+
+async fn track(
+    storage: &dyn Storage,
+    contract_client: &ContractClient,
+    mut withdrawal: Withdrawal,
+    stuck_after: i64,
+) -> Result<(), OpenBankError> {
+    let (Some(tx_hash), Some(nonce)) = (withdrawal.tx_hash.clone(), withdrawal.nonce) else {
+        return Err(OpenBankError::StorageError {
+            message: format!("Submitted withdrawal {} has no transaction hash", withdrawal.id),
+        });
+    };
+
+    match progress(contract_client, &withdrawal, &tx_hash, nonce).await? {
+        progress @ (TxProgress::Pending | TxProgress::Unknown) => {
+            let waited = Utc::now().signed_duration_since(withdrawal.updated_at).num_seconds();
+            if waited < stuck_after {
+                return Ok(());
+            }
+            let lost = matches!(progress, TxProgress::Unknown);
+            if withdrawal.replaced_tx_hashes.len() >= MAX_REPLACEMENTS {
+                if !lost {
+                    return Ok(());
+                }
+                // Its nonce is still free, so later transactions would wait on it forever
+                contract_client.resync_nonce().await;
+                return fail(storage, withdrawal, WithdrawalStatus::Failed, "Transaction was dropped before being mined".to_string());
+            }
+            replace(storage, contract_client, withdrawal, tx_hash, nonce, lost).await
+        }
+        TxProgress::Replaced => {
+            let reason = format!("Nonce {} was used by another transaction", nonce);
+            fail(storage, withdrawal, WithdrawalStatus::Replaced, reason)
+        }
+        TxProgress::Mined(receipt) => {
+            // An earlier attempt may be the one that got mined
+            if receipt.tx_hash != tx_hash {
+                withdrawal.replaced_tx_hashes.retain(|hash| *hash != receipt.tx_hash);
+                withdrawal.replaced_tx_hashes.push(tx_hash);
+                withdrawal.tx_hash = Some(receipt.tx_hash.clone());
+            }
+            withdrawal.block_number = receipt.block_number;
+            withdrawal.gas_used = receipt.gas_used;
+            if receipt.status == TxStatus::Reverted {
+                return fail(storage, withdrawal, WithdrawalStatus::Failed, "Transaction reverted".to_string());
+            }
+
+            // The transfer was mined, so debit the held funds for good
+            let entry = ledger::withdrawal_entry(
+                &withdrawal.account_id,
+                &withdrawal.amount,
+                &withdrawal.fees,
+                &withdrawal.usdt_amount,
+                &withdrawal.id,
+                &withdrawal.description,
+            )?;
+            let transaction = Transaction {
+                id: Uuid::new_v4().to_string(),
+                user_id: withdrawal.user_id.clone(),
+                account_id: withdrawal.account_id.clone(),
+                amount: withdrawal.amount.clone(),
+                transaction_type: TransactionType::Withdrawal,
+                description: withdrawal.description.clone(),
+                timestamp: Utc::now(),
+                balance_after: Money::zero(withdrawal.amount.currency())?,
+                tx_hash: Some(receipt.tx_hash),
+                transfer_id: None,
+            };
+            withdrawal.status = WithdrawalStatus::Confirmed;
+            withdrawal.transaction_id = Some(transaction.id.clone());
+            withdrawal.updated_at = Utc::now();
+            storage.settle_withdrawal(&withdrawal, transaction, &entry)?;
+            println!("Withdrawal {} confirmed in block {:?}", withdrawal.id, withdrawal.block_number);
+            Ok(())
+        }
+    }
+}
+
+// Combined progress of the current transaction and every attempt it replaced,
+// since any of them can still be mined. A mined attempt wins, then one the node
+// still has; Replaced only when the nonce went to none of them.
+async fn progress(
+    contract_client: &ContractClient,
+    withdrawal: &Withdrawal,
+    tx_hash: &str,
+    nonce: u64,
+) -> Result<TxProgress, OpenBankError> {
+    let mut combined = TxProgress::Replaced;
+    let attempts = std::iter::once(tx_hash).chain(withdrawal.replaced_tx_hashes.iter().rev().map(String::as_str));
+    for hash in attempts {
+        match contract_client.transaction_progress(hash, nonce).await? {
+            TxProgress::Mined(receipt) => return Ok(TxProgress::Mined(receipt)),
+            TxProgress::Pending => combined = TxProgress::Pending,
+            TxProgress::Unknown if matches!(combined, TxProgress::Replaced) => combined = TxProgress::Unknown,
+            _ => {}
+        }
+    }
+    Ok(combined)
+}
+
+// Re-signs a stuck or lost withdrawal with the same nonce and higher fees.
+// The new hash is recorded before broadcasting, like in submit().
+async fn replace(
+    storage: &dyn Storage,
+    contract_client: &ContractClient,
+    mut withdrawal: Withdrawal,
+    tx_hash: String,
+    nonce: u64,
+    lost: bool,
+) -> Result<(), OpenBankError> {
+    let signed = contract_client
+        .sign_usdt_transfer_replacement(
+            withdrawal.wallet_address.clone(),
+            withdrawal.usdt_amount.to_usdt_base_units()?,
+            withdrawal.description.clone(),
+            nonce,
+            &tx_hash,
+        )
+        .await;
+    let signed = match signed {
+        Ok(signed) => signed,
+        // No attempt is known to the node and its nonce is free, so the funds can go back
+        Err(e) if lost => {
+            contract_client.resync_nonce().await;
+            return fail(storage, withdrawal, WithdrawalStatus::Failed, e.to_string());
+        }
+        Err(e) => return Err(e),
+    };
+
+    withdrawal.replaced_tx_hashes.push(tx_hash);
+    withdrawal.tx_hash = Some(signed.tx_hash.clone());
+    withdrawal.updated_at = Utc::now();
+    if let Err(e) = storage.update_withdrawal(&withdrawal) {
+        // Nothing is broadcast, so let the node's nonce count win again, as in submit()
+        contract_client.resync_nonce().await;
+        return Err(e);
+    }
+
+    if let Err(e) = contract_client.broadcast(&signed).await {
+        println!("Warning: Broadcast of replacement for withdrawal {} failed: {:?}", withdrawal.id, e);
+    } else {
+        println!("Withdrawal {} replaced with {} (nonce {})", withdrawal.id, signed.tx_hash, nonce);
+    }
+    Ok(())
+}
+
+// Moves a withdrawal to a final unsuccessful state and returns its held funds
+fn fail(
+    storage: &dyn Storage,
+    mut withdrawal: Withdrawal,
+    status: WithdrawalStatus,
+    reason: String,
+) -> Result<(), OpenBankError> {
+    println!("Withdrawal {} {:?}: {}", withdrawal.id, status, reason);
+    withdrawal.status = status;
+    withdrawal.failure_reason = Some(reason);
+    withdrawal.updated_at = Utc::now();
+    storage.cancel_withdrawal(&withdrawal)
+}